@@ -0,0 +1,90 @@
+// How long rofi-keys takes to get a large menu ready for rofi, measured with
+// --profile-startup against a stand-in rofi that dismisses the menu at once.
+// `cargo bench` runs each size many times; under `cargo test --benches` each
+// size runs once, as a smoke test
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// About as many entries as desktop files give, and a large generated menu
+const SIZES: [usize; 2] = [400, 1000];
+const RUNS: usize = 50;
+
+// The phases reported, and "rofi input" again for the total up to it, which
+// is how long the menu takes to appear apart from rofi's own startup
+const PHASES: [&str; 3] = ["read config", "menu rows", "rofi input"];
+
+fn main() {
+    let runs = if std::env::args().any(|arg| arg == "--bench") { RUNS } else { 1 };
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("bench-startup");
+    fs::create_dir_all(&dir).unwrap();
+    let rofi = dir.join("fake-rofi");
+    fs::write(&rofi, "#!/bin/sh\ncat > /dev/null\nexit 1\n").unwrap();
+    fs::set_permissions(&rofi, fs::Permissions::from_mode(0o755)).unwrap();
+
+    for size in SIZES {
+        let config = write_config(&dir, size);
+        let mut timings: Vec<Vec<f64>> = vec![Vec::new(); PHASES.len() + 1];
+        for _ in 0..runs {
+            let phases = profile_run(&config, &rofi, &dir);
+            for (index, phase) in PHASES.iter().enumerate() {
+                let (took, _) = phases
+                    .iter()
+                    .find(|(name, _)| name == phase)
+                    .map(|(_, took)| *took)
+                    .unwrap_or_else(|| panic!("no \"{}\" phase in --profile-startup", phase));
+                timings[index].push(took);
+            }
+            let (_, total) = phases.iter().find(|(name, _)| name == "rofi input").unwrap().1;
+            timings[PHASES.len()].push(total);
+        }
+        println!("{} entries, {} run(s):", size, runs);
+        let names = PHASES.iter().copied().chain(["total"]);
+        for (name, mut times) in names.zip(timings) {
+            times.sort_by(f64::total_cmp);
+            println!("  {:<12} median {:>8.3} ms  min {:>8.3} ms", name, times[times.len() / 2], times[0]);
+        }
+    }
+}
+
+// A config with `size` entries, keyed by CJK characters so each has its own key
+fn write_config(dir: &Path, size: usize) -> PathBuf {
+    let entries: Vec<serde_json::Value> = (0..size)
+        .map(|index| {
+            serde_json::json!({
+                "key": char::from_u32(0x4e00 + index as u32).unwrap().to_string(),
+                "label": format!("Application {}", index),
+                "command": format!("app-{}", index),
+            })
+        })
+        .collect();
+    let path = dir.join(format!("config-{}.json", size));
+    fs::write(&path, serde_json::json!({ "entries": entries }).to_string()).unwrap();
+    path
+}
+
+// Each phase --profile-startup printed, with how long it and the run so far took
+fn profile_run(config: &Path, rofi: &Path, dir: &Path) -> Vec<(String, (f64, f64))> {
+    let output = Command::new(env!("CARGO_BIN_EXE_rofi-keys"))
+        .arg("--config")
+        .arg(config)
+        .args(["--profile-startup", "--dry-run"])
+        .env("ROFI_KEYS_ROFI", rofi)
+        .env("HOME", dir)
+        .env("XDG_STATE_HOME", dir.join("state"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .output()
+        .unwrap();
+    // "menu rows           0.12 ms  (total 3.45 ms)"
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| {
+            let (phase, rest) = line.split_at_checked(16)?;
+            let mut numbers = rest
+                .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .filter_map(|word| word.parse::<f64>().ok());
+            Some((phase.trim().to_string(), (numbers.next()?, numbers.next()?)))
+        })
+        .collect()
+}
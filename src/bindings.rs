@@ -0,0 +1,55 @@
+/// Window manager whose config syntax `rofi-keys bindings` prints
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum WindowManager {
+    Sway,
+    I3,
+    Hyprland,
+}
+
+/// A menu with a key combination that should open it
+#[derive(Debug)]
+pub struct MenuBinding {
+    /// Menu name, for the comment above the binding
+    pub menu: String,
+    /// Key combination as written in the config, e.g. "Mod4+space"
+    pub binding: String,
+    /// Shell command that opens the menu
+    pub command: String,
+}
+
+/// Render ready-to-paste binding lines for `wm`
+pub fn render(wm: WindowManager, bindings: &[MenuBinding]) -> String {
+    let mut out = String::new();
+    for binding in bindings {
+        out.push_str(&format!("# rofi-keys: {}\n", binding.menu));
+        let line = match wm {
+            WindowManager::Sway | WindowManager::I3 => {
+                format!("bindsym {} exec {}", binding.binding, binding.command)
+            }
+            WindowManager::Hyprland => {
+                let (mods, key) = hyprland_combo(&binding.binding);
+                format!("bind = {}, {}, exec, {}", mods, key, binding.command)
+            }
+        };
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+// Split "Mod4+Shift+p" into Hyprland's "SUPER SHIFT" and "p"
+fn hyprland_combo(binding: &str) -> (String, String) {
+    let mut parts: Vec<&str> = binding.split('+').map(str::trim).collect();
+    let key = parts.pop().unwrap_or_default().to_string();
+    let mods: Vec<String> = parts
+        .iter()
+        .map(|part| match part.to_ascii_lowercase().as_str() {
+            "mod4" | "super" | "$mod" | "logo" | "win" => "SUPER".to_string(),
+            "mod1" | "alt" => "ALT".to_string(),
+            "control" | "ctrl" => "CTRL".to_string(),
+            "shift" => "SHIFT".to_string(),
+            other => other.to_ascii_uppercase(),
+        })
+        .collect();
+    (mods.join(" "), key)
+}
@@ -0,0 +1,143 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::now;
+use crate::sources::{Source, SourceItem};
+use crate::write_atomic;
+
+/// How long a source's rows may be reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// Seconds cached rows stay fresh
+    pub ttl: u64,
+    /// Serve expired rows right away and refresh them in the background
+    pub stale_while_revalidate: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    /// Seconds since the Unix epoch
+    created: u64,
+    items: Vec<SourceItem>,
+}
+
+// Background refreshes still running; waited for before exiting
+static REFRESHES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Rows of `source`, reused from the cache when `policy` allows;
+/// `refresh` skips the cached copy and replaces it
+pub fn items(
+    source: &Source,
+    policy: Option<CachePolicy>,
+    refresh: bool,
+) -> io::Result<Vec<SourceItem>> {
+    let Some(policy) = policy else {
+        return source.items();
+    };
+    let Ok(path) = cache_path(source) else {
+        return source.items();
+    };
+
+    if !refresh {
+        if let Some(cached) = read(&path) {
+            if now().saturating_sub(cached.created) <= policy.ttl {
+                return Ok(cached.items);
+            }
+            if policy.stale_while_revalidate {
+                let source = source.clone();
+                let handle = thread::spawn(move || {
+                    if let Ok(items) = source.items() {
+                        let _ = write(&path, &items);
+                    }
+                });
+                if let Ok(mut refreshes) = REFRESHES.lock() {
+                    refreshes.push(handle);
+                }
+                return Ok(cached.items);
+            }
+        }
+    }
+
+    let items = source.items()?;
+    if let Err(e) = write(&path, &items) {
+        eprintln!("Could not cache source rows: {}", e);
+    }
+    Ok(items)
+}
+
+/// Let background refreshes finish so their results aren't lost on exit
+pub fn wait_for_refreshes() {
+    let handles = match REFRESHES.lock() {
+        Ok(mut refreshes) => std::mem::take(&mut *refreshes),
+        Err(_) => return,
+    };
+    for handle in handles {
+        let _ = handle.join();
+    }
+}
+
+/// Delete every cached source, returning how many there were
+pub fn clear() -> io::Result<usize> {
+    let dir = cache_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// A missing or corrupt cache file just means the source runs again
+fn read(path: &Path) -> Option<CacheFile> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+fn write(path: &Path, items: &[SourceItem]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = CacheFile {
+        created: now(),
+        items: items.to_vec(),
+    };
+    let json = serde_json::to_string(&file).map_err(io::Error::other)?;
+    write_atomic(path, json.as_bytes())
+}
+
+// One file per source definition, named after a hash of it
+fn cache_path(source: &Source) -> io::Result<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", source).hash(&mut hasher);
+    Ok(cache_dir()?.join(format!("{:016x}.json", hasher.finish())))
+}
+
+/// $XDG_CACHE_HOME/rofi-keys, falling back to ~/.cache
+pub fn cache_dir() -> io::Result<PathBuf> {
+    let mut path = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var("HOME")
+                .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "HOME directory not found"))?;
+            PathBuf::from(home).join(".cache")
+        }
+    };
+    path.push("rofi-keys");
+    Ok(path)
+}
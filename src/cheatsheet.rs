@@ -0,0 +1,111 @@
+use crate::tree::MenuNode;
+
+/// Output format for `rofi-keys cheatsheet`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum CheatsheetFormat {
+    Text,
+    Markdown,
+    Html,
+}
+
+/// Render the whole menu tree as a keybinding reference
+pub fn render(tree: &MenuNode, format: CheatsheetFormat) -> String {
+    let mut sections = Vec::new();
+    tree.walk(&mut Vec::new(), &mut |path, menu| {
+        sections.push(match format {
+            CheatsheetFormat::Text => text_section(path, menu),
+            CheatsheetFormat::Markdown => markdown_section(path, menu),
+            CheatsheetFormat::Html => html_section(path, menu),
+        });
+    });
+
+    match format {
+        CheatsheetFormat::Text | CheatsheetFormat::Markdown => sections.join("\n"),
+        CheatsheetFormat::Html => html_document(&tree.title, &sections.concat()),
+    }
+}
+
+fn text_section(path: &[&str], menu: &MenuNode) -> String {
+    let width = menu
+        .entries
+        .iter()
+        .map(|entry| entry.label.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = format!("{}\n", path.join(" > "));
+    for entry in &menu.entries {
+        out.push_str(&format!(
+            "  {}  {:<width$}  {}\n",
+            entry.key, entry.label, entry.command
+        ));
+    }
+    out
+}
+
+fn markdown_section(path: &[&str], menu: &MenuNode) -> String {
+    let mut out = format!(
+        "## {}\n\n| Key | Label | Command |\n| --- | --- | --- |\n",
+        path.join(" › ")
+    );
+    for entry in &menu.entries {
+        out.push_str(&format!(
+            "| `{}` | {} | `{}` |\n",
+            entry.key,
+            entry.label.replace('|', "\\|"),
+            entry.command.replace('|', "\\|")
+        ));
+    }
+    out
+}
+
+fn html_section(path: &[&str], menu: &MenuNode) -> String {
+    let mut out = format!(
+        "<section>\n<h2>{}</h2>\n<table>\n<tr><th>Key</th><th>Label</th><th>Command</th></tr>\n",
+        escape_html(&path.join(" › "))
+    );
+    for entry in &menu.entries {
+        out.push_str(&format!(
+            "<tr><td><kbd>{}</kbd></td><td>{}</td><td><code>{}</code></td></tr>\n",
+            escape_html(&entry.key),
+            escape_html(&entry.label),
+            escape_html(&entry.command)
+        ));
+    }
+    out.push_str("</table>\n</section>\n");
+    out
+}
+
+// A single self-contained page, styled for screens and printing
+fn html_document(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }}
+section {{ break-inside: avoid; margin-bottom: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }}
+kbd {{ border: 1px solid #999; border-radius: 3px; padding: 0 0.4em; font-family: monospace; }}
+code {{ color: #555; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{body}</body>
+</html>
+"#,
+        title = escape_html(title),
+        body = body
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
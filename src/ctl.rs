@@ -0,0 +1,196 @@
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+/// A request to `rofi-keys daemon`, sent as one line of JSON such as
+/// `{"cmd":"show","menu":"power"}`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "lowercase")]
+pub enum Request {
+    /// Show the main menu, or the flow `menu`
+    Show {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        menu: Option<String>,
+    },
+    /// Run the entry with this label, from the main menu or the flow `menu`
+    Run {
+        label: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        menu: Option<String>,
+    },
+    /// Read the config file again
+    Reload,
+    /// Describe the main menu the way `rofi-keys dump` does
+    List,
+}
+
+/// The daemon's answer to a request, also one line of JSON
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The menu, or a prompt it led to, was dismissed without running anything
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// The `dump` JSON of the main menu, for `list`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub menu: Option<serde_json::Value>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Response {
+            ok: true,
+            ..Response::default()
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Response {
+            error: Some(message.into()),
+            ..Response::default()
+        }
+    }
+}
+
+/// $XDG_RUNTIME_DIR/rofi-keys.sock, or a per-user socket in /tmp without it
+pub fn socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => Path::new(&dir).join("rofi-keys.sock"),
+        // SAFETY: getuid has no preconditions and cannot fail
+        _ => PathBuf::from(format!("/tmp/rofi-keys-{}.sock", unsafe { libc::getuid() })),
+    }
+}
+
+/// Listen on `path` and answer every request with `handle`, each client on
+/// its own thread; `handle` decides what may run at the same time
+pub fn serve(path: &Path, handle: impl Fn(Request) -> Response + Sync) -> io::Result<()> {
+    // A socket left behind by a daemon that died is replaced; one that still
+    // answers belongs to a daemon that is running
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("A rofi-keys daemon is already listening on {}", path.display()),
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handle = &handle;
+                    scope.spawn(move || {
+                        if let Err(e) = answer(stream, handle) {
+                            eprintln!("ctl client: {}", e);
+                        }
+                    });
+                }
+                Err(e) => eprintln!("ctl socket: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+// Answer each line a client sends until it hangs up
+fn answer(stream: UnixStream, handle: &impl Fn(Request) -> Response) -> io::Result<()> {
+    let mut writer = &stream;
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => handle(request),
+            Err(e) => Response::error(format!("Invalid request: {}", e)),
+        };
+        let json = serde_json::to_string(&response).map_err(io::Error::other)?;
+        writeln!(writer, "{}", json)?;
+    }
+    Ok(())
+}
+
+/// Send one request to the daemon listening on `path` and wait for its answer
+pub fn send(path: &Path, request: &Request) -> io::Result<Response> {
+    let stream = UnixStream::connect(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused => io::Error::new(
+            e.kind(),
+            format!("No rofi-keys daemon is listening on {} (start `rofi-keys daemon`)", path.display()),
+        ),
+        _ => e,
+    })?;
+    let json = serde_json::to_string(request).map_err(io::Error::other)?;
+    writeln!(&stream, "{}", json)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The daemon closed the connection without answering",
+        ));
+    }
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_tagged_by_cmd() {
+        let parse = |line: &str| serde_json::from_str::<Request>(line).unwrap();
+        assert_eq!(
+            parse(r#"{"cmd":"show","menu":"power"}"#),
+            Request::Show {
+                menu: Some("power".to_string())
+            }
+        );
+        assert_eq!(parse(r#"{"cmd":"show"}"#), Request::Show { menu: None });
+        assert_eq!(
+            parse(r#"{"cmd":"run","label":"Firefox"}"#),
+            Request::Run {
+                label: "Firefox".to_string(),
+                menu: None
+            }
+        );
+        assert_eq!(parse(r#"{"cmd":"reload"}"#), Request::Reload);
+        assert_eq!(parse(r#"{"cmd":"list"}"#), Request::List);
+        assert!(serde_json::from_str::<Request>(r#"{"cmd":"quit"}"#).is_err());
+    }
+
+    #[test]
+    fn client_and_server_exchange_lines() {
+        let path = env::temp_dir().join(format!("rofi-keys-ctl-{}.sock", std::process::id()));
+        let server_path = path.clone();
+        thread::spawn(move || {
+            serve(&server_path, |request| match request {
+                Request::Reload => Response::ok(),
+                other => Response::error(format!("unexpected {:?}", other)),
+            })
+        });
+        // Wait for the listener to come up
+        let mut response = send(&path, &Request::Reload);
+        for _ in 0..50 {
+            if response.is_ok() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(20));
+            response = send(&path, &Request::Reload);
+        }
+        let response = response.unwrap();
+        assert!(response.ok);
+        let response = send(&path, &Request::List).unwrap();
+        assert_eq!(response.error.as_deref(), Some("unexpected List"));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,646 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use crate::ctl::{Request, Response};
+
+/// The well-known name `rofi-keys daemon --dbus` takes on the session bus;
+/// it is also the interface name
+pub const NAME: &str = "org.rofikeys.Menu";
+/// The object the methods are called on
+pub const PATH: &str = "/org/rofikeys/Menu";
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+const PEER: &str = "org.freedesktop.DBus.Peer";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.rofikeys.Menu">
+    <method name="Show"><arg name="menu" type="s" direction="in"/></method>
+    <method name="Run"><arg name="label" type="s" direction="in"/></method>
+    <method name="Reload"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="xml" type="s" direction="out"/></method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Peer">
+    <method name="Ping"/>
+  </interface>
+</node>
+"#;
+
+// Message types and flags
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+const NO_REPLY_EXPECTED: u8 = 1;
+
+// Header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+// RequestName: fail rather than wait in line, and what the bus answers
+const DO_NOT_QUEUE: u32 = 4;
+const PRIMARY_OWNER: u32 = 1;
+const ALREADY_OWNER: u32 = 4;
+
+// The bus refuses anything larger
+const MAX_MESSAGE: usize = 128 * 1024 * 1024;
+
+/// A connection to the session bus that owns `org.rofikeys.Menu`
+pub struct Service {
+    stream: UnixStream,
+    serial: u32,
+}
+
+/// Connect to the session bus and take `org.rofikeys.Menu`; fails if another
+/// process has the name rather than waiting for it
+pub fn register() -> io::Result<Service> {
+    let address = env::var("DBUS_SESSION_BUS_ADDRESS").ok().filter(|address| !address.is_empty());
+    let address = match address {
+        Some(address) => address,
+        None => match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) if !dir.is_empty() => format!("unix:path={}/bus", dir),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No session bus: DBUS_SESSION_BUS_ADDRESS is not set",
+                ))
+            }
+        },
+    };
+    let mut stream = connect(&address)?;
+    authenticate(&mut stream)?;
+    let mut service = Service { stream, serial: 0 };
+
+    service.call_bus("Hello", Vec::new())?;
+    let name = vec![Value::Str(NAME.to_string()), Value::U32(DO_NOT_QUEUE)];
+    let reply = service.call_bus("RequestName", name)?;
+    match reply.args()?.as_slice() {
+        [Value::U32(PRIMARY_OWNER | ALREADY_OWNER)] => Ok(service),
+        [Value::U32(_)] => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!(
+                "{} is already taken on the session bus; is another rofi-keys daemon running?",
+                NAME
+            ),
+        )),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected reply to RequestName")),
+    }
+}
+
+impl Service {
+    /// Answer method calls with `handle`, each call on its own thread, until
+    /// the bus hangs up; `handle` decides what may run at the same time
+    pub fn serve(self, handle: impl Fn(Request) -> Response + Sync) -> io::Result<()> {
+        let mut reader = self.stream.try_clone()?;
+        let writer = Mutex::new((self.stream, self.serial));
+        thread::scope(|scope| loop {
+            let call = read_message(&mut reader)?;
+            if call.kind != METHOD_CALL {
+                continue;
+            }
+            let (handle, writer) = (&handle, &writer);
+            scope.spawn(move || {
+                let reply = dispatch(&call, handle);
+                if call.flags & NO_REPLY_EXPECTED != 0 {
+                    return;
+                }
+                let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+                writer.1 += 1;
+                let mut reply = reply;
+                reply.serial = writer.1;
+                if let Err(e) = writer.0.write_all(&reply.encode()) {
+                    eprintln!("D-Bus reply: {}", e);
+                }
+            });
+        })
+    }
+
+    // Call a method of the bus itself and wait for its return, skipping the
+    // signals it sends meanwhile
+    fn call_bus(&mut self, member: &str, args: Vec<Value>) -> io::Result<Message> {
+        self.serial += 1;
+        let call = Message {
+            kind: METHOD_CALL,
+            serial: self.serial,
+            fields: vec![
+                (FIELD_PATH, Value::Path(BUS_PATH.to_string())),
+                (FIELD_INTERFACE, Value::Str(BUS_NAME.to_string())),
+                (FIELD_MEMBER, Value::Str(member.to_string())),
+                (FIELD_DESTINATION, Value::Str(BUS_NAME.to_string())),
+            ],
+            body: args,
+            ..Message::default()
+        };
+        self.stream.write_all(&call.encode())?;
+        loop {
+            let reply = read_message(&mut self.stream)?;
+            if reply.reply_serial() != Some(self.serial) {
+                continue;
+            }
+            if reply.kind == ERROR {
+                let text = reply.args().ok().and_then(|args| match args.into_iter().next() {
+                    Some(Value::Str(text)) => Some(text),
+                    _ => None,
+                });
+                let text = text.as_deref().or(reply.string_field(FIELD_ERROR_NAME));
+                return Err(io::Error::other(format!(
+                    "{} failed: {}",
+                    member,
+                    text.unwrap_or("unknown error")
+                )));
+            }
+            return Ok(reply);
+        }
+    }
+}
+
+// The reply to a method call on our object
+fn dispatch(call: &Message, handle: &impl Fn(Request) -> Response) -> Message {
+    let member = call.string_field(FIELD_MEMBER).unwrap_or("");
+    let interface = call.string_field(FIELD_INTERFACE);
+    if call.string_field(FIELD_PATH) != Some(PATH) {
+        return call.error("org.freedesktop.DBus.Error.UnknownObject", "No such object");
+    }
+    let args = match call.args() {
+        Ok(args) => args,
+        Err(e) => return call.error("org.freedesktop.DBus.Error.InvalidArgs", &e.to_string()),
+    };
+    let request = match (interface.unwrap_or(NAME), member, args.as_slice()) {
+        (NAME, "Show", [Value::Str(menu)]) => Request::Show {
+            menu: (!menu.is_empty()).then(|| menu.clone()),
+        },
+        (NAME, "Run", [Value::Str(label)]) => Request::Run {
+            label: label.clone(),
+            menu: None,
+        },
+        (NAME, "Reload", []) => Request::Reload,
+        (INTROSPECTABLE, "Introspect", []) => {
+            return call.reply(vec![Value::Str(INTROSPECTION.to_string())])
+        }
+        (PEER, "Ping", []) => return call.reply(Vec::new()),
+        (NAME, "Show" | "Run" | "Reload", _) => {
+            let expected = if member == "Reload" { "no arguments" } else { "one string" };
+            return call.error(
+                "org.freedesktop.DBus.Error.InvalidArgs",
+                &format!("{} takes {}", member, expected),
+            );
+        }
+        _ => {
+            return call.error(
+                "org.freedesktop.DBus.Error.UnknownMethod",
+                &format!("No method {} on {}", member, interface.unwrap_or(NAME)),
+            )
+        }
+    };
+    let response = handle(request);
+    match response.error {
+        Some(error) => call.error("org.rofikeys.Menu.Error.Failed", &error),
+        None if response.cancelled => call.error("org.rofikeys.Menu.Error.Cancelled", "Cancelled"),
+        None => call.reply(Vec::new()),
+    }
+}
+
+// The first address in `address` that can be connected to; only unix
+// sockets are supported, by path or (on Linux) by abstract name
+fn connect(address: &str) -> io::Result<UnixStream> {
+    let mut last_error = None;
+    for address in address.split(';') {
+        let Some(params) = address.strip_prefix("unix:") else {
+            continue;
+        };
+        for (key, value) in params.split(',').filter_map(|param| param.split_once('=')) {
+            let result = match key {
+                "path" => UnixStream::connect(unescape(value)),
+                #[cfg(target_os = "linux")]
+                "abstract" => {
+                    use std::os::linux::net::SocketAddrExt;
+                    std::os::unix::net::SocketAddr::from_abstract_name(unescape(value).as_bytes())
+                        .and_then(|addr| UnixStream::connect_addr(&addr))
+                }
+                _ => continue,
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("No unix socket in the session bus address '{}'", address),
+        )
+    }))
+}
+
+// Undo the %XX escapes of a bus address value
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = value
+            .get(index + 1..index + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// SASL EXTERNAL: the bus checks our uid against the socket's peer credentials
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() }.to_string();
+    let hex: String = uid.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex).as_bytes())?;
+
+    // Byte by byte, so nothing after the line is read ahead
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while !line.ends_with(b"\r\n") {
+        if stream.read(&mut byte)? == 0 || line.len() > 512 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "The session bus hung up while authenticating",
+            ));
+        }
+        line.push(byte[0]);
+    }
+    if !line.starts_with(b"OK ") {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("The session bus refused us: {}", String::from_utf8_lossy(&line).trim()),
+        ));
+    }
+    stream.write_all(b"BEGIN\r\n")
+}
+
+// The few D-Bus types the service sends and reads
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Path(String),
+    Signature(String),
+    U32(u32),
+}
+
+impl Value {
+    fn signature(&self) -> char {
+        match self {
+            Value::Str(_) => 's',
+            Value::Path(_) => 'o',
+            Value::Signature(_) => 'g',
+            Value::U32(_) => 'u',
+        }
+    }
+
+    fn write(&self, out: &mut Writer) {
+        match self {
+            Value::Str(text) | Value::Path(text) => out.string(text),
+            Value::Signature(text) => out.signature(text),
+            Value::U32(number) => out.u32(*number),
+        }
+    }
+
+    fn read(signature: char, input: &mut Reader<'_>) -> io::Result<Self> {
+        match signature {
+            's' => input.string().map(Value::Str),
+            'o' => input.string().map(Value::Path),
+            'g' => input.signature().map(Value::Signature),
+            'u' => input.u32().map(Value::U32),
+            other => Err(invalid(format!("Unsupported D-Bus type '{}'", other))),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Message {
+    kind: u8,
+    flags: u8,
+    serial: u32,
+    fields: Vec<(u8, Value)>,
+    body: Vec<Value>,
+    // A body read off the wire, decoded by `args` since its types may be
+    // ones the service doesn't read
+    raw_body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+    fn field(&self, code: u8) -> Option<&Value> {
+        self.fields.iter().find(|(field, _)| *field == code).map(|(_, value)| value)
+    }
+
+    fn string_field(&self, code: u8) -> Option<&str> {
+        match self.field(code)? {
+            Value::Str(text) | Value::Path(text) | Value::Signature(text) => Some(text),
+            Value::U32(_) => None,
+        }
+    }
+
+    fn reply_serial(&self) -> Option<u32> {
+        match self.field(FIELD_REPLY_SERIAL)? {
+            Value::U32(serial) => Some(*serial),
+            _ => None,
+        }
+    }
+
+    // The body's arguments
+    fn args(&self) -> io::Result<Vec<Value>> {
+        if self.raw_body.is_empty() {
+            return Ok(self.body.clone());
+        }
+        let mut input = Reader::new(&self.raw_body, self.big_endian);
+        self.string_field(FIELD_SIGNATURE)
+            .unwrap_or("")
+            .chars()
+            .map(|signature| Value::read(signature, &mut input))
+            .collect()
+    }
+
+    // A return for this call; the serial is set when it is sent
+    fn reply(&self, body: Vec<Value>) -> Message {
+        let mut fields = vec![(FIELD_REPLY_SERIAL, Value::U32(self.serial))];
+        if let Some(sender) = self.string_field(FIELD_SENDER) {
+            fields.push((FIELD_DESTINATION, Value::Str(sender.to_string())));
+        }
+        Message {
+            kind: METHOD_RETURN,
+            fields,
+            body,
+            ..Message::default()
+        }
+    }
+
+    fn error(&self, name: &str, text: &str) -> Message {
+        let mut error = self.reply(vec![Value::Str(text.to_string())]);
+        error.kind = ERROR;
+        error.fields.push((FIELD_ERROR_NAME, Value::Str(name.to_string())));
+        error
+    }
+
+    // Little-endian wire form
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Writer::default();
+        for value in &self.body {
+            value.write(&mut body);
+        }
+        let mut fields = self.fields.clone();
+        if !self.body.is_empty() {
+            let signature = self.body.iter().map(Value::signature).collect();
+            fields.push((FIELD_SIGNATURE, Value::Signature(signature)));
+        }
+
+        let mut out = Writer::default();
+        out.bytes(&[b'l', self.kind, self.flags, 1]);
+        out.u32(body.data.len() as u32);
+        out.u32(self.serial);
+        out.u32(0);
+        let start = out.data.len();
+        for (code, value) in &fields {
+            out.align(8);
+            out.bytes(&[*code]);
+            out.signature(&value.signature().to_string());
+            value.write(&mut out);
+        }
+        let length = (out.data.len() - start) as u32;
+        out.data[12..16].copy_from_slice(&length.to_le_bytes());
+        out.align(8);
+        out.bytes(&body.data);
+        out.data
+    }
+}
+
+fn read_message(stream: &mut impl Read) -> io::Result<Message> {
+    let mut fixed = [0; 16];
+    stream.read_exact(&mut fixed)?;
+    let big_endian = match fixed[0] {
+        b'l' => false,
+        b'B' => true,
+        other => return Err(invalid(format!("Bad D-Bus byte order '{}'", other))),
+    };
+    let number = |at: usize| {
+        let bytes = [fixed[at], fixed[at + 1], fixed[at + 2], fixed[at + 3]];
+        (if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }) as usize
+    };
+    let (body_length, fields_length) = (number(4), number(12));
+    let header_length = (16 + fields_length).next_multiple_of(8);
+    if header_length + body_length > MAX_MESSAGE {
+        return Err(invalid("D-Bus message too large".to_string()));
+    }
+
+    let mut header = fixed.to_vec();
+    header.resize(header_length, 0);
+    stream.read_exact(&mut header[16..])?;
+    let mut raw_body = vec![0; body_length];
+    stream.read_exact(&mut raw_body)?;
+
+    let mut input = Reader::new(&header, big_endian);
+    input.pos = 16;
+    let mut fields = Vec::new();
+    while input.pos < 16 + fields_length {
+        input.align(8);
+        let code = input.byte()?;
+        let signature = input.signature()?;
+        let mut chars = signature.chars();
+        let (Some(signature), None) = (chars.next(), chars.next()) else {
+            return Err(invalid(format!("Bad D-Bus header field type '{}'", signature)));
+        };
+        fields.push((code, Value::read(signature, &mut input)?));
+    }
+
+    Ok(Message {
+        kind: fixed[1],
+        flags: fixed[2],
+        serial: number(8) as u32,
+        fields,
+        body: Vec::new(),
+        raw_body,
+        big_endian,
+    })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn align(&mut self, to: usize) {
+        let aligned = self.data.len().next_multiple_of(to);
+        self.data.resize(aligned, 0);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, number: u32) {
+        self.align(4);
+        self.bytes(&number.to_le_bytes());
+    }
+
+    fn string(&mut self, text: &str) {
+        self.u32(text.len() as u32);
+        self.bytes(text.as_bytes());
+        self.bytes(&[0]);
+    }
+
+    fn signature(&mut self, text: &str) {
+        self.bytes(&[text.len() as u8]);
+        self.bytes(text.as_bytes());
+        self.bytes(&[0]);
+    }
+}
+
+// Reads values from a message part that starts 8-aligned in the message
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], big_endian: bool) -> Self {
+        Reader {
+            data,
+            pos: 0,
+            big_endian,
+        }
+    }
+
+    fn align(&mut self, to: usize) {
+        self.pos = self.pos.next_multiple_of(to);
+    }
+
+    fn take(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + length)
+            .ok_or_else(|| invalid("Truncated D-Bus message".to_string()))?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.align(4);
+        let bytes: [u8; 4] = self.take(4)?.try_into().expect("took 4 bytes");
+        Ok(if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    }
+
+    // A string or object path, followed by a nul
+    fn string(&mut self) -> io::Result<String> {
+        let length = self.u32()? as usize;
+        let text = self.take(length)?;
+        self.take(1)?;
+        String::from_utf8(text.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+
+    fn signature(&mut self) -> io::Result<String> {
+        let length = self.byte()? as usize;
+        let text = self.take(length)?;
+        self.take(1)?;
+        String::from_utf8(text.to_vec()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(member: &str, body: Vec<Value>) -> Message {
+        Message {
+            kind: METHOD_CALL,
+            serial: 7,
+            fields: vec![
+                (FIELD_PATH, Value::Path(PATH.to_string())),
+                (FIELD_INTERFACE, Value::Str(NAME.to_string())),
+                (FIELD_MEMBER, Value::Str(member.to_string())),
+                (FIELD_SENDER, Value::Str(":1.42".to_string())),
+            ],
+            body,
+            ..Message::default()
+        }
+    }
+
+    #[test]
+    fn messages_read_back_as_written() {
+        let sent = call("Run", vec![Value::Str("Firefox".to_string()), Value::U32(3)]);
+        let read = read_message(&mut sent.encode().as_slice()).unwrap();
+        assert_eq!(read.kind, METHOD_CALL);
+        assert_eq!(read.serial, 7);
+        assert_eq!(read.string_field(FIELD_MEMBER), Some("Run"));
+        assert_eq!(read.string_field(FIELD_SIGNATURE), Some("su"));
+        assert_eq!(read.args().unwrap(), sent.body);
+    }
+
+    #[test]
+    fn calls_become_requests_and_responses_replies() {
+        let handled = Mutex::new(Vec::new());
+        let handle = |request: Request| {
+            handled.lock().unwrap().push(request.clone());
+            match request {
+                Request::Run { .. } => Response::error("No entry"),
+                _ => Response::ok(),
+            }
+        };
+
+        let reply = dispatch(&call("Show", vec![Value::Str(String::new())]), &handle);
+        assert_eq!(reply.kind, METHOD_RETURN);
+        assert_eq!(reply.reply_serial(), Some(7));
+        assert_eq!(reply.string_field(FIELD_DESTINATION), Some(":1.42"));
+        let reply = dispatch(&call("Run", vec![Value::Str("Firefox".to_string())]), &handle);
+        assert_eq!(reply.kind, ERROR);
+        assert_eq!(reply.string_field(FIELD_ERROR_NAME), Some("org.rofikeys.Menu.Error.Failed"));
+        let reply = dispatch(&call("Reload", vec![Value::U32(1)]), &handle);
+        let error = reply.string_field(FIELD_ERROR_NAME);
+        assert_eq!(error, Some("org.freedesktop.DBus.Error.InvalidArgs"));
+
+        assert_eq!(
+            *handled.lock().unwrap(),
+            [
+                Request::Show { menu: None },
+                Request::Run {
+                    label: "Firefox".to_string(),
+                    menu: None
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn bus_addresses_are_unescaped() {
+        assert_eq!(unescape("/run/user/1000/bus"), "/run/user/1000/bus");
+        assert_eq!(unescape("/tmp/a%20b%2c"), "/tmp/a b,");
+    }
+}
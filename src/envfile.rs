@@ -0,0 +1,164 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::sync::{PoisonError, RwLock};
+
+use crate::write_atomic;
+
+// Describe the session rofi-keys runs in rather than the shell a snapshot was
+// taken from, so they are never captured or restored
+const SESSION_VARS: &[&str] = &[
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_SESSION_ID",
+    "XDG_VTNR",
+    "SWAYSOCK",
+    "I3SOCK",
+    "HYPRLAND_INSTANCE_SIGNATURE",
+];
+
+// Only meaningful inside the interactive shell that ran capture-env
+const SHELL_VARS: &[&str] = &["PWD", "OLDPWD", "SHLVL", "_", "COLUMNS", "LINES"];
+
+/// Read `KEY=value` lines, as written by `capture-env` or printed by
+/// `systemctl --user show-environment`, skipping blanks and `#` comments
+pub fn load(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+    let mut vars = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        match line.split_once('=') {
+            Some((name, value)) if is_name(name) => {
+                vars.push((name.to_string(), unquote(value)));
+            }
+            _ => eprintln!(
+                "Ignoring line {} of {}: expected KEY=value",
+                number + 1,
+                path.display()
+            ),
+        }
+    }
+    Ok(vars)
+}
+
+// What the env_file, the config's `path` and settings picked from sources add
+// to the environment rofi-keys was started with. It is handed to each command
+// rather than set with env::set_var, which races with the daemon's threads
+static OVERLAY: RwLock<Vec<(String, OsString)>> = RwLock::new(Vec::new());
+
+/// Start over from the environment rofi-keys was started with, adding `vars`
+/// (as read from an env_file) for every command started from now on
+pub fn apply(vars: &[(String, String)]) {
+    let vars = vars
+        .iter()
+        .filter(|(name, _)| !SESSION_VARS.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), OsString::from(value)));
+    *OVERLAY.write().unwrap_or_else(PoisonError::into_inner) = vars.collect();
+}
+
+/// Set `name` for every command started from now on
+pub fn set(name: &str, value: impl Into<OsString>) {
+    let mut overlay = OVERLAY.write().unwrap_or_else(PoisonError::into_inner);
+    overlay.retain(|(set, _)| set != name);
+    overlay.push((name.to_string(), value.into()));
+}
+
+/// The value commands started by rofi-keys see for `name`
+pub fn var_os(name: &str) -> Option<OsString> {
+    let overlay = OVERLAY.read().unwrap_or_else(PoisonError::into_inner);
+    match overlay.iter().find(|(set, _)| set == name) {
+        Some((_, value)) => Some(value.clone()),
+        None => env::var_os(name),
+    }
+}
+
+/// A Command for `program` with the variables set through this module; the
+/// program is looked up in their PATH
+pub fn command(program: impl AsRef<OsStr>) -> Command {
+    let mut command = Command::new(program);
+    command.envs(OVERLAY.read().unwrap_or_else(PoisonError::into_inner).iter().cloned());
+    command
+}
+
+/// Write the current environment to `path`, leaving out session and shell-local variables
+pub fn capture(path: &Path) -> io::Result<usize> {
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(name, _)| {
+            !SESSION_VARS.contains(&name.as_str()) && !SHELL_VARS.contains(&name.as_str())
+        })
+        .collect();
+    vars.sort();
+
+    let mut content = String::from("# Written by rofi-keys capture-env\n");
+    for (name, value) in &vars {
+        content.push_str(&format!("{}={}\n", name, quote(value)));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    write_atomic(path, content.as_bytes())?;
+    Ok(vars.len())
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Values that would not survive a plain KEY=value line use $'...' quoting,
+// the same form systemctl show-environment prints
+fn quote(value: &str) -> String {
+    if !value.contains(['\n', '\t', '\\', '\'', '"']) && value.trim() == value {
+        return value.to_string();
+    }
+    let mut quoted = String::from("$'");
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\\' => quoted.push_str("\\\\"),
+            '\'' => quoted.push_str("\\'"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+fn unquote(value: &str) -> String {
+    if let Some(inner) = value.strip_prefix("$'").and_then(|v| v.strip_suffix('\'')) {
+        let mut unquoted = String::new();
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unquoted.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => unquoted.push('\n'),
+                Some('t') => unquoted.push('\t'),
+                Some(other) => unquoted.push(other),
+                None => unquoted.push('\\'),
+            }
+        }
+        return unquoted;
+    }
+    for q in ['\'', '"'] {
+        if let Some(inner) = value.strip_prefix(q).and_then(|v| v.strip_suffix(q)) {
+            return inner.to_string();
+        }
+    }
+    value.to_string()
+}
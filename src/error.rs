@@ -0,0 +1,146 @@
+use std::io;
+use std::path::PathBuf;
+
+/// Why rofi-keys stopped, with what's needed to report it
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Config file {} not found", .path.display())]
+    ConfigNotFound { path: PathBuf },
+
+    #[error("{}:{line}:{col}: {source}", .path.display())]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+        line: usize,
+        col: usize,
+    },
+
+    #[error("{}:{line}:{col}: not valid UTF-8; save the config as UTF-8", .path.display())]
+    ConfigEncoding {
+        path: PathBuf,
+        line: usize,
+        col: usize,
+    },
+
+    /// The config parsed but asks for something rofi-keys can't do
+    #[error("{0}")]
+    ConfigInvalid(String),
+
+    #[error("{backend} is not installed or not on PATH")]
+    BackendMissing { backend: String },
+
+    #[error("{backend} failed ({}){}", exit_status(.code), stderr_suffix(.stderr))]
+    BackendFailed {
+        backend: String,
+        code: Option<i32>,
+        stderr: String,
+    },
+
+    #[error("Could not start {command}: {source}")]
+    SpawnFailed {
+        command: String,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The menu or a follow-up prompt was dismissed without running anything
+    #[error("Cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl Error {
+    /// Process exit status for this error: 2 for config problems, 3 when the
+    /// menu couldn't be shown, 4 when a command couldn't be started, 10 for a
+    /// cancel and 1 for anything else
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::ConfigNotFound { .. }
+            | Error::ConfigParse { .. }
+            | Error::ConfigEncoding { .. }
+            | Error::ConfigInvalid(_) => 2,
+            Error::BackendMissing { .. } | Error::BackendFailed { .. } => 3,
+            Error::SpawnFailed { .. } => 4,
+            Error::Cancelled => 10,
+            Error::Io(_) => 1,
+        }
+    }
+
+    /// Whether the problem is in the config file rather than the system
+    pub fn is_config(&self) -> bool {
+        matches!(
+            self,
+            Error::ConfigNotFound { .. }
+                | Error::ConfigParse { .. }
+                | Error::ConfigEncoding { .. }
+                | Error::ConfigInvalid(_)
+        )
+    }
+}
+
+// For code paths that still deal in io::Error; the Error rides along inside
+// it, so converting back gives the same variant and exit code
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::Io(e) => return e,
+            Error::ConfigNotFound { .. } | Error::BackendMissing { .. } => io::ErrorKind::NotFound,
+            Error::ConfigParse { .. } | Error::ConfigEncoding { .. } | Error::ConfigInvalid(_) => {
+                io::ErrorKind::InvalidData
+            }
+            Error::BackendFailed { .. } => io::ErrorKind::Other,
+            Error::Cancelled => io::ErrorKind::Interrupted,
+            Error::SpawnFailed { ref source, .. } => source.kind(),
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        error.downcast::<Error>().unwrap_or_else(Error::Io)
+    }
+}
+
+fn exit_status(code: &Option<i32>) -> String {
+    match code {
+        Some(code) => format!("exit status {}", code),
+        None => "killed".to_string(),
+    }
+}
+
+fn stderr_suffix(stderr: &str) -> String {
+    match stderr.trim() {
+        "" => String::new(),
+        text => format!(": {}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_failed_names_the_program() {
+        let error = Error::BackendFailed {
+            backend: "fzf".to_string(),
+            code: Some(2),
+            stderr: "unknown option\n".to_string(),
+        };
+        assert_eq!(error.to_string(), "fzf failed (exit status 2): unknown option");
+        assert_eq!(error.exit_code(), 3);
+    }
+
+    #[test]
+    fn config_invalid_survives_a_trip_through_io_error() {
+        let error = Error::ConfigInvalid("Unknown key_style 'loud'".to_string());
+        let error = Error::from(io::Error::from(error));
+        assert!(matches!(error, Error::ConfigInvalid(_)), "{:?}", error);
+        assert!(error.is_config());
+        assert_eq!(error.exit_code(), 2);
+        assert_eq!(error.to_string(), "Unknown key_style 'loud'");
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::Debug;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use crate::error::Error;
+use crate::{envfile, LaunchEnv};
+
+/// Runs the command lines the launcher assembles. Launch methods such as
+/// systemd or setsid only add to the command line, so one executor serves
+/// them all; another one can record command lines instead of running them
+pub trait Executor: Debug {
+    /// Start `argv` without waiting for it
+    fn spawn(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Result<(), Error>;
+    /// Run `argv` to completion, collecting what it prints
+    fn output(
+        &self,
+        argv: &[String],
+        cwd: Option<&Path>,
+        env: &LaunchEnv<'_>,
+    ) -> Result<Output, Error>;
+    /// Become `argv`, for exec_replace; only returns if it couldn't be started
+    fn replace(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Error;
+}
+
+/// Runs command lines as child processes, which is how every entry starts
+/// (through `sh -c` unless the launch method is exec)
+#[derive(Debug, Default)]
+pub struct ShellExecutor;
+
+impl Executor for ShellExecutor {
+    fn spawn(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Result<(), Error> {
+        // Launching through sh -c bypasses some of the systemd scoping issues
+        command_for(argv, cwd, env)?
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| spawn_failed(argv, e, cwd))?;
+        Ok(())
+    }
+
+    fn output(
+        &self,
+        argv: &[String],
+        cwd: Option<&Path>,
+        env: &LaunchEnv<'_>,
+    ) -> Result<Output, Error> {
+        command_for(argv, cwd, env)?
+            .output()
+            .map_err(|e| spawn_failed(argv, e, cwd))
+    }
+
+    fn replace(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Error {
+        match command_for(argv, cwd, env) {
+            Ok(mut command) => spawn_failed(argv, command.stdin(Stdio::inherit()).exec(), cwd),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+// A Command for `argv` with no input, started in `cwd` if given
+fn command_for(argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> io::Result<Command> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+
+    let mut command = envfile::command(program);
+    command.args(args).stdin(Stdio::null());
+    if let Some(dir) = cwd {
+        command.current_dir(dir);
+    }
+    env.apply(&mut command);
+    Ok(command)
+}
+
+// A missing working directory shows up as a confusing "not found" otherwise
+fn spawn_failed(argv: &[String], e: io::Error, cwd: Option<&Path>) -> Error {
+    let source = match cwd {
+        Some(dir) if !dir.is_dir() => io::Error::new(
+            e.kind(),
+            format!("Working directory {}: {}", dir.display(), e),
+        ),
+        _ => e,
+    };
+    Error::SpawnFailed {
+        command: argv.first().cloned().unwrap_or_default(),
+        source,
+    }
+}
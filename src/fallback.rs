@@ -0,0 +1,149 @@
+use std::io::{self, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::process::Stdio;
+
+use crate::error::Error;
+use crate::{envfile, find_in_path, hooks, output, rofi_program};
+
+/// Menu program used when rofi isn't on PATH, set with `fallback_backend`.
+/// Neither can bind entry keys, so entries are picked by selecting their row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fallback {
+    Dmenu,
+    /// fzf in the terminal rofi-keys was started from
+    Fzf,
+}
+
+impl Fallback {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "dmenu" => Ok(Fallback::Dmenu),
+            "fzf" => Ok(Fallback::Fzf),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown fallback_backend '{}' (expected \"dmenu\" or \"fzf\")",
+                other
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Fallback::Dmenu => "dmenu",
+            Fallback::Fzf => "fzf",
+        }
+    }
+
+    pub fn installed(self) -> bool {
+        find_in_path(self.name()).is_some()
+    }
+
+    /// Let the user pick one of `rows`; returns the picked line, or whatever
+    /// was typed when dmenu got text matching no row, and None on a cancel
+    pub fn pick(self, prompt: &str, rows: &[String]) -> Result<Option<String>, Error> {
+        let fzf_prompt = format!("{} ", prompt);
+        let args: Vec<&str> = match self {
+            Fallback::Dmenu => vec!["-i", "-l", "20", "-p", prompt],
+            Fallback::Fzf => vec!["--no-multi", "--layout=reverse", "--prompt", &fzf_prompt],
+        };
+        let _window = hooks::open();
+        // fzf draws on the terminal itself, so only stdin and stdout are piped
+        let mut child = envfile::command(self.name())
+            .args(&args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => Error::BackendMissing {
+                    backend: self.name().to_string(),
+                },
+                _ => Error::Io(e),
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            match stdin.write_all(rows.join("\n").as_bytes()) {
+                Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(e.into());
+                }
+                _ => {}
+            }
+        }
+
+        // dmenu exits 1 on Escape, fzf 1 on no match and 130 on Escape or Ctrl-C;
+        // anything else (fzf's 2) is the backend failing. Killed by a signal it
+        // may have printed half a line, which is never taken as a pick
+        let output = child.wait_with_output()?;
+        if let Some(signal) = output.status.signal() {
+            output::warn(format!("{} was killed by signal {}", self.name(), signal));
+            return Ok(None);
+        }
+        match output.status.code() {
+            Some(0) => {}
+            Some(1) => return Ok(None),
+            Some(130) if self == Fallback::Fzf => return Ok(None),
+            code => {
+                return Err(Error::BackendFailed {
+                    backend: self.name().to_string(),
+                    code,
+                    stderr: String::new(),
+                })
+            }
+        }
+        let line = String::from_utf8_lossy(&output.stdout);
+        let line = line.trim_end_matches('\n');
+        Ok((!line.is_empty()).then(|| line.to_string()))
+    }
+}
+
+/// Menu program an entry's follow-up dialogs (confirmations, argument
+/// choices, source rows) use instead of the menu's own, set with `backend`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Rofi,
+    Fallback(Fallback),
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "rofi" => Ok(Backend::Rofi),
+            "dmenu" => Ok(Backend::Fallback(Fallback::Dmenu)),
+            "fzf" => Ok(Backend::Fallback(Fallback::Fzf)),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown backend '{}' (expected \"rofi\", \"dmenu\" or \"fzf\")",
+                other
+            ))),
+        }
+    }
+
+    /// The fallback to show dialogs with, None for rofi, or BackendMissing
+    /// when the program isn't on PATH; unlike fallback_backend there is no
+    /// falling back from a backend an entry asked for
+    pub fn require(self) -> Result<Option<Fallback>, Error> {
+        let program = match self {
+            Backend::Rofi => rofi_program(),
+            Backend::Fallback(fallback) => fallback.name().to_string(),
+        };
+        if find_in_path(&program).is_none() {
+            return Err(Error::BackendMissing { backend: program });
+        }
+        Ok(match self {
+            Backend::Rofi => None,
+            Backend::Fallback(fallback) => Some(fallback),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backend_parses_by_name() {
+        assert_eq!(Backend::parse("rofi").unwrap(), Backend::Rofi);
+        assert_eq!(Backend::parse("dmenu").unwrap(), Backend::Fallback(Fallback::Dmenu));
+        assert_eq!(Backend::parse("fzf").unwrap(), Backend::Fallback(Fallback::Fzf));
+        assert!(Backend::parse("wofi").is_err());
+    }
+}
+
@@ -0,0 +1,159 @@
+/// A feature explained by `--help-all`, with a config fragment showing it
+pub struct Topic {
+    pub title: &'static str,
+    pub summary: &'static str,
+    /// JSON as it would appear in config.json
+    pub example: &'static str,
+}
+
+/// Every topic, in the order a new config usually grows
+pub const TOPICS: &[Topic] = &[
+    Topic {
+        title: "Entries",
+        summary: "Each entry binds one key to a shell command. The key is pressed while \
+                  the menu is open; no Enter needed.",
+        example: r#"{
+  "prompt": "Launch",
+  "entries": [
+    { "key": "f", "label": "Firefox", "command": "firefox" },
+    { "key": "t", "label": "Terminal", "command": "x-terminal-emulator" }
+  ]
+}"#,
+    },
+    Topic {
+        title: "Aliases",
+        summary: "An entry with `ref` borrows the command of the entry with that label, \
+                  so one command can sit behind several keys; \"flow/Label\" reaches \
+                  into another menu.",
+        example: r#"{ "key": "w", "label": "Web", "ref": "Firefox" }"#,
+    },
+    Topic {
+        title: "Argument choices",
+        summary: "`command_template` opens a second menu of `args`; the picked value \
+                  replaces {arg}.",
+        example: r#"{
+  "key": "s",
+  "label": "SSH",
+  "command_template": "x-terminal-emulator -e ssh {arg}",
+  "args": [
+    { "key": "w", "label": "Web server", "value": "web.example.org" },
+    { "label": "Backup host", "value": "backup.example.org" }
+  ]
+}"#,
+    },
+    Topic {
+        title: "Flows (submenus)",
+        summary: "Named menus under `flows` are opened by entries with `next`, or \
+                  directly with --flow NAME. A flow can set its own prompt and theme.",
+        example: r#"{
+  "entries": [{ "key": "p", "label": "Power…", "next": "power" }],
+  "flows": {
+    "power": {
+      "prompt": "Power",
+      "entries": [
+        { "key": "r", "label": "Reboot", "command": "systemctl reboot", "confirm": true }
+      ]
+    }
+  }
+}"#,
+    },
+    Topic {
+        title: "Sources",
+        summary: "Entries with a `source_type` list rows produced when they are picked: \
+                  \"clipboard_history\", \"snippets\" (from `source_file`), \"players\" or \"windows\". \
+                  `cache_seconds` reuses rows; `inline` lists them in the menu itself.",
+        example: r#"{
+  "key": "e",
+  "label": "Snippets",
+  "source_type": "snippets",
+  "source_file": "~/.config/rofi-keys/snippets.json",
+  "output": "type",
+  "cache_seconds": 60
+}"#,
+    },
+    Topic {
+        title: "Groups and tags",
+        summary: "`group` lists entries under a header that Enter collapses or expands; \
+                  \"layout\": \"columns\" puts each group in a column, \
+                  \"layout\": \"grid\" shows entries as icons (`icon`, `grid_columns`). \
+                  `tags` pick a subset of entries with --tag.",
+        example: r#"{ "key": "c", "label": "VS Code", "command": "code", "group": "Dev", "tags": ["dev"] }"#,
+    },
+    Topic {
+        title: "Themes and looks",
+        summary: "`theme` names a rofi theme or .rasi file, `theme_str` adds rules on top. \
+                  `message` and `status_command` fill the message bar, and `hint_key` \
+                  toggles a key reference there.",
+        example: r#"{
+  "theme": "Arc-Dark",
+  "theme_str": "window { width: 30%; }",
+  "message": "Pick an app",
+  "status_command": "date +%H:%M",
+  "hint_key": "?",
+  "key_style": "mnemonic"
+}"#,
+    },
+    Topic {
+        title: "Launching",
+        summary: "`launch_method` is \"shell\", \"exec\", \"systemd\", \"setsid\" or \"wm\"; entries \
+                  can override it with `exec_mode` and set `cwd`, `delay_ms`, `nice`, \
+                  `sandbox` or `capture_output`; `dual_activation` adds Alt+key for a \
+                  foreground run that shows the output.",
+        example: r#"{
+  "launch_method": "systemd",
+  "cwd": "~",
+  "path": ["~/.local/bin"],
+  "entries": [
+    { "key": "b", "label": "Build", "command": "make", "cwd": "~/src/app", "capture_output": true }
+  ]
+}"#,
+    },
+    Topic {
+        title: "Environment",
+        summary: "`env_file` holds KEY=value lines applied before anything is launched; \
+                  `rofi-keys capture-env` writes one from your login shell.",
+        example: r#"{ "env_file": "~/.config/rofi-keys/env" }"#,
+    },
+];
+
+/// The long help printed by `--help-all`
+pub fn render() -> String {
+    let mut out = String::from(
+        "rofi-keys reads a JSON config (see --config) and shows its entries in rofi.\n\
+         Every feature below is a fragment of that file.\n",
+    );
+    for topic in TOPICS {
+        out.push_str(&format!("\n{}\n", topic.title.to_uppercase()));
+        out.push_str(&wrap(topic.summary, 76));
+        out.push('\n');
+        for line in topic.example.lines() {
+            out.push_str("    ");
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out.push_str(
+        "\nRun `rofi-keys init` for a starting config and `rofi-keys check` to test it.\n",
+    );
+    out
+}
+
+// Break text into indented lines of at most `width` columns
+fn wrap(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            out.push_str(&format!("    {}\n", line));
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        out.push_str(&format!("    {}\n", line));
+    }
+    out
+}
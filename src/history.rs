@@ -0,0 +1,433 @@
+use std::collections::{BTreeSet, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::{output, write_atomic, CUSTOM_COMMAND_LABEL};
+
+// Oldest launches are dropped once the history grows past this
+const MAX_RECORDS: usize = 10_000;
+
+// Captured output beyond this is cut off before it is stored
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// A single launch of a menu entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LaunchRecord {
+    pub label: String,
+    pub key: String,
+    /// Flow menu the entry is in; None for the main menu
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub menu: Option<String>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+/// Persisted log of launches, used for usage statistics
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub launches: Vec<LaunchRecord>,
+}
+
+impl History {
+    /// Load the history, starting fresh if it is missing or unreadable
+    pub fn load() -> Self {
+        load_state("history.json")
+    }
+
+    /// Append a launch and persist the history
+    pub fn record(&mut self, label: &str, key: char, menu: Option<&str>) -> io::Result<()> {
+        self.launches.push(LaunchRecord {
+            label: label.to_string(),
+            key: key.to_string(),
+            menu: menu.map(str::to_string),
+            timestamp: now(),
+        });
+        if self.launches.len() > MAX_RECORDS {
+            let excess = self.launches.len() - MAX_RECORDS;
+            self.launches.drain(..excess);
+        }
+        self.save()
+    }
+
+    /// How often each entry of a menu (None for the main menu) was launched, by label
+    pub fn counts(&self, menu: Option<&str>) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for launch in self.launches.iter().filter(|l| l.menu.as_deref() == menu) {
+            *counts.entry(launch.label.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    fn save(&self) -> io::Result<()> {
+        save_state("history.json", self)
+    }
+}
+
+/// What one run of a capture_output entry printed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputRecord {
+    pub label: String,
+    /// The command line that ran, kept so it can be run again
+    pub argv: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    /// Exit code, or None when the command was killed by a signal
+    pub status: Option<i32>,
+    /// stdout followed by stderr
+    pub output: String,
+}
+
+impl OutputRecord {
+    /// Build a record of output that was just captured, cutting overly long output short
+    pub fn new(
+        label: &str,
+        argv: &[String],
+        cwd: Option<&Path>,
+        status: Option<i32>,
+        output: String,
+    ) -> Self {
+        let mut output = output;
+        if output.len() > MAX_OUTPUT_BYTES {
+            let mut end = MAX_OUTPUT_BYTES;
+            while !output.is_char_boundary(end) {
+                end -= 1;
+            }
+            output.truncate(end);
+            output.push_str("\n…");
+        }
+        OutputRecord {
+            label: label.to_string(),
+            argv: argv.to_vec(),
+            cwd: cwd.map(Path::to_path_buf),
+            timestamp: now(),
+            status,
+            output,
+        }
+    }
+}
+
+/// The most recent captured outputs, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OutputLog {
+    pub outputs: Vec<OutputRecord>,
+}
+
+impl OutputLog {
+    /// Load the log, starting fresh if it is missing or unreadable
+    pub fn load() -> Self {
+        load_state("outputs.json")
+    }
+
+    /// Append an output, keep only the newest `limit` and persist the log
+    pub fn record(&mut self, record: OutputRecord, limit: usize) -> io::Result<()> {
+        self.outputs.push(record);
+        if self.outputs.len() > limit {
+            let excess = self.outputs.len() - limit;
+            self.outputs.drain(..excess);
+        }
+        save_state("outputs.json", self)
+    }
+}
+
+/// Menu groups left collapsed, kept between runs when `remember_collapsed` is set
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CollapsedGroups {
+    pub groups: BTreeSet<String>,
+}
+
+impl CollapsedGroups {
+    pub fn load() -> Self {
+        load_state("collapsed.json")
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        save_state("collapsed.json", self)
+    }
+}
+
+// Read a state file, falling back to an empty state if it is missing or unreadable
+fn load_state<T: DeserializeOwned + Default>(name: &str) -> T {
+    let Ok(path) = state_path(name) else {
+        return T::default();
+    };
+    let Ok(content) = fs::read_to_string(&path) else {
+        return T::default();
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        eprintln!("Ignoring unreadable {}: {}", path.display(), e);
+        T::default()
+    })
+}
+
+fn save_state<T: Serialize>(name: &str, state: &T) -> io::Result<()> {
+    let path = state_path(name)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let json = serde_json::to_string(state).map_err(io::Error::other)?;
+    write_atomic(&path, json.as_bytes())
+}
+
+// $XDG_STATE_HOME/rofi-keys/<name>, falling back to ~/.local/state
+fn state_path(name: &str) -> io::Result<PathBuf> {
+    let mut path = match env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home = env::var("HOME")
+                .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "HOME directory not found"))?;
+            PathBuf::from(home).join(".local/state")
+        }
+    };
+    path.push("rofi-keys");
+    path.push(name);
+    Ok(path)
+}
+
+/// Current time in seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parse a span like "30d", "12h", "45m", "90s" or "2w" into seconds
+pub fn parse_span(span: &str) -> io::Result<u64> {
+    let invalid = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid time span '{}' (expected e.g. 30d, 12h, 45m)", span),
+        )
+    };
+
+    let unit = span.chars().last().ok_or_else(invalid)?;
+    let amount: u64 = span[..span.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    let scale = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount.checked_mul(scale).ok_or_else(invalid)
+}
+
+/// Format a Unix timestamp as "YYYY-MM-DD HH:MM" in UTC
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = timestamp / 86_400;
+    let seconds = timestamp % 86_400;
+
+    // Civil-from-days conversion (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        (seconds % 3600) / 60
+    )
+}
+
+/// Output format for `rofi-keys stats`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum StatsFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Usage summary for one entry
+#[derive(Debug, Serialize)]
+pub struct StatsRow {
+    pub label: String,
+    /// Flow menu the entry is in; None for the main menu
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub menu: Option<String>,
+    pub key: String,
+    pub count: usize,
+    /// Fraction of all launches in the reported period
+    pub share: f64,
+    pub last_used: u64,
+    /// The entry is in the history but no longer in the config
+    pub removed: bool,
+}
+
+/// Summarize launches since `since` (Unix seconds), most used first, with a
+/// row per menu and label; `current` holds the (menu, label) of every entry
+/// in the config, None being the main menu
+pub fn stats(history: &History, since: u64, current: &[(Option<&str>, &str)]) -> Vec<StatsRow> {
+    let mut rows: HashMap<(Option<&str>, &str), StatsRow> = HashMap::new();
+    let mut total = 0_usize;
+
+    for launch in history.launches.iter().filter(|l| l.timestamp >= since) {
+        total += 1;
+        let entry = (launch.menu.as_deref(), launch.label.as_str());
+        let row = rows.entry(entry).or_insert_with(|| StatsRow {
+            label: launch.label.clone(),
+            menu: launch.menu.clone(),
+            key: launch.key.clone(),
+            count: 0,
+            share: 0.0,
+            last_used: 0,
+            // Typed commands were never entries, so they can't have been removed
+            removed: !current.contains(&entry) && !launch.label.starts_with(CUSTOM_COMMAND_LABEL),
+        });
+        row.count += 1;
+        if launch.timestamp >= row.last_used {
+            row.last_used = launch.timestamp;
+            row.key.clone_from(&launch.key);
+        }
+    }
+
+    let mut rows: Vec<StatsRow> = rows.into_values().collect();
+    for row in &mut rows {
+        row.share = row.count as f64 / total as f64;
+    }
+    rows.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then_with(|| a.label.cmp(&b.label))
+            .then_with(|| a.menu.cmp(&b.menu))
+    });
+    rows
+}
+
+/// Render stats rows in the requested format
+pub fn render_stats(rows: &[StatsRow], format: StatsFormat) -> io::Result<String> {
+    match format {
+        StatsFormat::Json => serde_json::to_string_pretty(rows).map_err(io::Error::other),
+        StatsFormat::Csv => {
+            let mut out = String::from("label,key,count,share,last_used,removed,menu\n");
+            for row in rows {
+                out.push_str(&format!(
+                    "{},{},{},{:.4},{},{},{}\n",
+                    csv_field(&row.label),
+                    csv_field(&row.key),
+                    row.count,
+                    row.share,
+                    format_timestamp(row.last_used),
+                    row.removed,
+                    csv_field(row.menu.as_deref().unwrap_or_default())
+                ));
+            }
+            Ok(out)
+        }
+        StatsFormat::Table => {
+            let width = rows
+                .iter()
+                .map(|row| row.label.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("LABEL".len());
+            // Main menu entries leave the menu column empty
+            let menu_width = rows
+                .iter()
+                .filter_map(|row| row.menu.as_ref())
+                .map(|menu| menu.chars().count())
+                .max()
+                .unwrap_or(0)
+                .max("MENU".len());
+            let mut out = output::bold(&format!(
+                "{:<width$}  {:<menu_width$}  KEY  {:>6}  {:>6}  LAST USED",
+                "LABEL", "MENU", "COUNT", "SHARE"
+            ));
+            out.push('\n');
+            for row in rows {
+                let marker = if row.removed {
+                    output::notice("  (removed)")
+                } else {
+                    String::new()
+                };
+                out.push_str(&format!(
+                    "{:<width$}  {:<menu_width$}  {}  {:>6}  {:>5.1}%  {}{}\n",
+                    row.label,
+                    row.menu.as_deref().unwrap_or_default(),
+                    output::bold(&format!("{:<3}", row.key)),
+                    row.count,
+                    row.share * 100.0,
+                    format_timestamp(row.last_used),
+                    marker
+                ));
+            }
+            Ok(out)
+        }
+    }
+}
+
+// Quote a CSV field when it contains separators or quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_span_scales_units() {
+        assert_eq!(parse_span("90s").unwrap(), 90);
+        assert_eq!(parse_span("2w").unwrap(), 2 * 7 * 24 * 60 * 60);
+    }
+
+    #[test]
+    fn parse_span_rejects_overflow() {
+        assert!(parse_span("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn stats_keep_menus_apart() {
+        let launch = |label: &str, menu: Option<&str>, timestamp| LaunchRecord {
+            label: label.to_string(),
+            key: "s".to_string(),
+            menu: menu.map(str::to_string),
+            timestamp,
+        };
+        let history = History {
+            launches: vec![
+                launch("Slack", Some("work"), 1),
+                launch("Slack", Some("work"), 2),
+                launch("Slack", None, 3),
+                launch("Steam", Some("games"), 4),
+            ],
+        };
+        let rows = stats(&history, 0, &[(None, "Slack"), (Some("work"), "Slack")]);
+        let rows: Vec<(&str, Option<&str>, usize, bool)> = rows
+            .iter()
+            .map(|row| (row.label.as_str(), row.menu.as_deref(), row.count, row.removed))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                ("Slack", Some("work"), 2, false),
+                ("Slack", None, 1, false),
+                ("Steam", Some("games"), 1, true),
+            ]
+        );
+    }
+}
+
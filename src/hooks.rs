@@ -0,0 +1,128 @@
+use std::ffi::{c_char, CString};
+use std::process::Stdio;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+use crate::{envfile, output};
+
+/// The `on_open` and `on_close` commands, run around every menu window
+#[derive(Debug, Default, Clone)]
+struct MenuHooks {
+    on_open: Option<String>,
+    on_close: Option<String>,
+}
+
+static HOOKS: RwLock<Option<MenuHooks>> = RwLock::new(None);
+
+// on_close ready for execv, since the signal handler can neither allocate nor
+// take a lock. Replaced strings are leaked: a handler may still be reading one
+static ON_CLOSE_C: AtomicPtr<c_char> = AtomicPtr::new(ptr::null_mut());
+
+/// Run `on_open` before each menu window and `on_close` after it; without a
+/// call (as with --dry-run) no hooks run. A later call, as on a daemon's
+/// reload, replaces the hooks for the windows opened after it
+pub fn install(on_open: Option<String>, on_close: Option<String>) {
+    let mut hooks = HOOKS.write().unwrap_or_else(PoisonError::into_inner);
+    let installed_close = hooks.as_ref().and_then(|hooks| hooks.on_close.as_ref());
+    if hooks.is_none() || installed_close != on_close.as_ref() {
+        let on_close_c = on_close
+            .as_deref()
+            .and_then(|command| CString::new(command).ok())
+            .map_or(ptr::null_mut(), CString::into_raw);
+        ON_CLOSE_C.store(on_close_c, Ordering::SeqCst);
+    }
+    *hooks = Some(MenuHooks { on_open, on_close });
+}
+
+fn current() -> MenuHooks {
+    HOOKS.read().unwrap_or_else(PoisonError::into_inner).clone().unwrap_or_default()
+}
+
+/// A menu window about to be shown; runs `on_open` now and `on_close` when
+/// dropped, so errors and early returns still run it. The window keeps the
+/// hooks it was opened with, even if they are replaced while it is open
+pub struct MenuWindow {
+    on_close: Option<String>,
+}
+
+/// Run `on_open`, returning the guard that runs `on_close`
+pub fn open() -> MenuWindow {
+    let hooks = current();
+    if let Some(command) = &hooks.on_open {
+        run("on_open", command);
+    }
+    MenuWindow {
+        on_close: hooks.on_close,
+    }
+}
+
+impl Drop for MenuWindow {
+    fn drop(&mut self) {
+        if let Some(command) = &self.on_close {
+            run("on_close", command);
+        }
+    }
+}
+
+/// Start `on_close` from a signal handler, without waiting for it; only
+/// async-signal-safe calls (fork, execv, _exit) are made
+pub fn close_from_signal() {
+    let command = ON_CLOSE_C.load(Ordering::SeqCst);
+    if command.is_null() {
+        return;
+    }
+    let argv = [c"sh".as_ptr(), c"-c".as_ptr(), command.cast_const(), ptr::null()];
+    // SAFETY: the child only calls execv and _exit, argv outlives both, and
+    // `command` came from CString::into_raw and is never freed
+    unsafe {
+        if libc::fork() == 0 {
+            libc::execv(c"/bin/sh".as_ptr(), argv.as_ptr());
+            libc::_exit(127);
+        }
+    }
+}
+
+// Run a hook and wait for it; a failing hook is reported but doesn't stop the menu
+fn run(name: &str, command: &str) {
+    let status = envfile::command("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .status();
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => output::warn(format!("{} exited with {}", name, status)),
+        Err(e) => output::warn(format!("{} could not be started: {}", name, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs};
+
+    #[test]
+    fn installing_again_replaces_the_hooks() {
+        let dir = env::temp_dir().join(format!("rofi-keys-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let touch = |name: &str| Some(format!("touch {}", dir.join(name).display()));
+
+        install(touch("open-1"), touch("close-1"));
+        drop(open());
+        install(touch("open-2"), touch("close-2"));
+        let window = open();
+        // A window keeps the hooks it was opened with
+        install(None, None);
+        drop(window);
+        drop(open());
+
+        let mut ran: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|file| file.unwrap().file_name().into_string().unwrap())
+            .collect();
+        ran.sort();
+        assert_eq!(ran, ["close-1", "close-2", "open-1", "open-2"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
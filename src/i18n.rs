@@ -0,0 +1,163 @@
+use std::env;
+use std::sync::OnceLock;
+
+// The `locale` config setting, which wins over the environment once set
+static OVERRIDE: OnceLock<String> = OnceLock::new();
+
+/// Translations of the built-in labels and messages, by language; the English
+/// text is the key, and anything missing from a table stays English
+const TRANSLATIONS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "de",
+        &[
+            ("Applications", "Anwendungen"),
+            ("Shortcuts", "Verknüpfungen"),
+            ("Firefox Private", "Privates Fenster"),
+            ("MPV (clipboard)", "MPV (Zwischenablage)"),
+            ("Yes", "Ja"),
+            ("No", "Nein"),
+            ("Error", "Fehler"),
+            (
+                "No config yet. Set up rofi-keys",
+                "Noch keine Konfiguration. rofi-keys einrichten",
+            ),
+            ("Power", "Energie"),
+            ("Lock", "Sperren"),
+            ("Log Out", "Abmelden"),
+            ("Suspend", "Bereitschaft"),
+            ("Hibernate", "Ruhezustand"),
+            ("Reboot", "Neu starten"),
+            ("Shut Down", "Herunterfahren"),
+            ("Screenshot", "Bildschirmfoto"),
+            ("Screen", "Bildschirm"),
+            ("Region", "Bereich"),
+            ("Window", "Fenster"),
+            ("{} to File", "{} in Datei"),
+            ("{} to Clipboard", "{} in Zwischenablage"),
+            ("Media", "Medien"),
+            ("Play/Pause", "Wiedergabe/Pause"),
+            ("Next", "Weiter"),
+            ("Previous", "Zurück"),
+            ("Forward 10s", "10 s vor"),
+            ("Rewind 10s", "10 s zurück"),
+            ("Volume Up", "Lauter"),
+            ("Volume Down", "Leiser"),
+        ],
+    ),
+    (
+        "es",
+        &[
+            ("Applications", "Aplicaciones"),
+            ("Shortcuts", "Atajos"),
+            ("Firefox Private", "Ventana privada"),
+            ("MPV (clipboard)", "MPV (portapapeles)"),
+            ("Yes", "Sí"),
+            ("No", "No"),
+            ("Error", "Error"),
+            (
+                "No config yet. Set up rofi-keys",
+                "Aún no hay configuración. Configurar rofi-keys",
+            ),
+            ("Power", "Energía"),
+            ("Lock", "Bloquear"),
+            ("Log Out", "Cerrar sesión"),
+            ("Suspend", "Suspender"),
+            ("Hibernate", "Hibernar"),
+            ("Reboot", "Reiniciar"),
+            ("Shut Down", "Apagar"),
+            ("Screenshot", "Captura de pantalla"),
+            ("Screen", "Pantalla"),
+            ("Region", "Región"),
+            ("Window", "Ventana"),
+            ("{} to File", "{} a archivo"),
+            ("{} to Clipboard", "{} al portapapeles"),
+            ("Media", "Multimedia"),
+            ("Play/Pause", "Reproducir/Pausa"),
+            ("Next", "Siguiente"),
+            ("Previous", "Anterior"),
+            ("Forward 10s", "Avanzar 10 s"),
+            ("Rewind 10s", "Retroceder 10 s"),
+            ("Volume Up", "Subir volumen"),
+            ("Volume Down", "Bajar volumen"),
+            ("Player…", "Reproductor…"),
+        ],
+    ),
+    (
+        "fr",
+        &[
+            ("Applications", "Applications"),
+            ("Shortcuts", "Raccourcis"),
+            ("Firefox Private", "Fenêtre privée"),
+            ("MPV (clipboard)", "MPV (presse-papiers)"),
+            ("Yes", "Oui"),
+            ("No", "Non"),
+            ("Error", "Erreur"),
+            (
+                "No config yet. Set up rofi-keys",
+                "Pas encore de configuration. Configurer rofi-keys",
+            ),
+            ("Power", "Alimentation"),
+            ("Lock", "Verrouiller"),
+            ("Log Out", "Se déconnecter"),
+            ("Suspend", "Mettre en veille"),
+            ("Hibernate", "Hiberner"),
+            ("Reboot", "Redémarrer"),
+            ("Shut Down", "Éteindre"),
+            ("Screenshot", "Capture d'écran"),
+            ("Screen", "Écran"),
+            ("Region", "Zone"),
+            ("Window", "Fenêtre"),
+            ("{} to File", "{} vers un fichier"),
+            ("{} to Clipboard", "{} vers le presse-papiers"),
+            ("Media", "Médias"),
+            ("Play/Pause", "Lecture/Pause"),
+            ("Next", "Suivant"),
+            ("Previous", "Précédent"),
+            ("Forward 10s", "Avancer de 10 s"),
+            ("Rewind 10s", "Reculer de 10 s"),
+            ("Volume Up", "Volume +"),
+            ("Volume Down", "Volume −"),
+            ("Player…", "Lecteur…"),
+        ],
+    ),
+];
+
+/// Use `locale` (e.g. "de" or "de_DE.UTF-8") instead of the environment's;
+/// only the first call counts
+pub fn set_locale(locale: &str) {
+    let _ = OVERRIDE.set(locale.to_string());
+}
+
+/// `text` in the current language, or as given when there is no translation
+pub fn tr(text: &'static str) -> &'static str {
+    let Some(language) = language() else {
+        return text;
+    };
+    TRANSLATIONS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .and_then(|(_, strings)| strings.iter().find(|(english, _)| *english == text))
+        .map_or(text, |(_, translated)| translated)
+}
+
+/// A "{}" template from `tr` with `value` filled in
+pub fn tr_with(template: &'static str, value: &str) -> String {
+    tr(template).replacen("{}", value, 1)
+}
+
+// The language part of the override or of LC_ALL, LC_MESSAGES or LANG, in the
+// order the C library looks at them; None for C/POSIX and unset locales
+fn language() -> Option<String> {
+    let locale = OVERRIDE.get().cloned().or_else(|| {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+    })?;
+    let language = locale
+        .split(['_', '.', '@', '-'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    (!language.is_empty() && language != "c" && language != "posix").then_some(language)
+}
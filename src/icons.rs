@@ -0,0 +1,45 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::{cache, escape_markup, write_atomic};
+
+/// Icon name used when a letter icon can't be written
+pub const FALLBACK_ICON: &str = "application-x-executable";
+
+// Background colors for letter icons, picked by key so each key keeps its color
+const COLORS: &[&str] = &[
+    "#e06c75", "#d19a66", "#98c379", "#56b6c2", "#61afef", "#c678dd", "#be5046", "#7f848e",
+];
+
+/// A rounded square with `key` on it, for entries without an icon of their
+/// own; written once as SVG under the cache directory, which rofi renders
+/// itself, so no font rasterizing happens here
+pub fn letter_icon(key: char) -> String {
+    letter_icon_path(key)
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| FALLBACK_ICON.to_string())
+}
+
+fn letter_icon_path(key: char) -> io::Result<PathBuf> {
+    let path = cache::cache_dir()?
+        .join("icons")
+        .join(format!("letter-{:x}.svg", u32::from(key)));
+    if path.is_file() {
+        return Ok(path);
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let color = COLORS[u32::from(key) as usize % COLORS.len()];
+    let svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"64\" height=\"64\" viewBox=\"0 0 64 64\">\
+         <rect width=\"64\" height=\"64\" rx=\"12\" fill=\"{}\"/>\
+         <text x=\"32\" y=\"32\" dy=\"0.35em\" text-anchor=\"middle\" font-family=\"sans-serif\" \
+         font-size=\"36\" font-weight=\"bold\" fill=\"#ffffff\">{}</text></svg>\n",
+        color,
+        escape_markup(&key.to_uppercase().to_string())
+    );
+    write_atomic(&path, svg.as_bytes())?;
+    Ok(path)
+}
@@ -0,0 +1,73 @@
+use std::fs;
+use std::process;
+
+use regex::Regex;
+
+use crate::error::Error;
+use crate::wm::{self, Window};
+
+/// What a single_instance entry does when its program is already running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnRunning {
+    /// Focus the program's window, or do nothing if none is found
+    #[default]
+    Focus,
+    /// Do nothing
+    Ignore,
+    /// Start another instance anyway
+    Launch,
+}
+
+impl OnRunning {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "focus" => Ok(OnRunning::Focus),
+            "ignore" => Ok(OnRunning::Ignore),
+            "launch" => Ok(OnRunning::Launch),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown on_running '{}' (expected \"focus\", \"ignore\" or \"launch\")",
+                other
+            ))),
+        }
+    }
+}
+
+/// Whether a process other than this one has a name `pattern` matches, like
+/// `pgrep`: the kernel's process name, or the file name of its first argument
+/// for names the kernel cut short
+pub fn is_running(pattern: &Regex) -> bool {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return false;
+    };
+    let own = process::id().to_string();
+    entries
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.bytes().all(|b| b.is_ascii_digit()) && name != own.as_str()
+        })
+        .any(|entry| {
+            let dir = entry.path();
+            let comm = fs::read_to_string(dir.join("comm")).unwrap_or_default();
+            if pattern.is_match(comm.trim_end()) {
+                return true;
+            }
+            let cmdline = fs::read(dir.join("cmdline")).unwrap_or_default();
+            let argv0 = cmdline.split(|&b| b == 0).next().unwrap_or_default();
+            let argv0 = String::from_utf8_lossy(argv0);
+            let program = argv0.rsplit('/').next().unwrap_or_default();
+            !program.is_empty() && pattern.is_match(program)
+        })
+}
+
+/// The first window of the running compositor whose class is `class`,
+/// ignoring case, with the command line that focuses it
+pub fn find_window(class: &str) -> Option<Vec<String>> {
+    let compositor = wm::detect()?;
+    let windows = compositor.list_windows().ok()?;
+    let window: &Window = windows
+        .iter()
+        .find(|window| window.class.eq_ignore_ascii_case(class))?;
+    Some(compositor.focus(window))
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+
+/// rofi's default bindings that use a key without modifiers. Entry keys are
+/// bound without modifiers too, so these are the only defaults they can clash with
+const PLAIN_DEFAULTS: &[(&str, &str)] = &[
+    ("kb-accept-entry", "Return,KP_Enter"),
+    ("kb-cancel", "Escape"),
+    ("kb-element-next", "Tab"),
+    ("kb-element-prev", "ISO_Left_Tab"),
+    ("kb-move-char-back", "Left"),
+    ("kb-move-char-forward", "Right"),
+    ("kb-page-next", "Page_Down"),
+    ("kb-page-prev", "Page_Up"),
+    ("kb-remove-char-back", "BackSpace"),
+    ("kb-remove-char-forward", "Delete"),
+    ("kb-row-down", "Down"),
+    ("kb-row-first", "Home,KP_Home"),
+    ("kb-row-last", "End,KP_End"),
+    ("kb-row-up", "Up"),
+    ("kb-secondary-paste", "Insert"),
+    ("kb-toggle-case-sensitivity", "grave,dead_grave"),
+];
+
+/// Built-in `kb_preset` sets, by name. Each keeps the plain-key defaults and
+/// frees the Control combinations it takes from other actions, since rofi
+/// refuses to start when two actions share a key
+const PRESETS: &[(&str, &[(&str, &str)])] = &[
+    (
+        "vim-keys",
+        &[
+            ("kb-row-down", "Down,Control+j"),
+            ("kb-row-up", "Up,Control+k"),
+            ("kb-move-char-back", "Left,Control+h"),
+            ("kb-move-char-forward", "Right,Control+l"),
+            ("kb-accept-entry", "Return,KP_Enter,Control+m"),
+            ("kb-remove-to-eol", ""),
+            ("kb-remove-char-back", "BackSpace,Shift+BackSpace"),
+            ("kb-mode-complete", ""),
+        ],
+    ),
+    (
+        "emacs-keys",
+        &[
+            ("kb-row-down", "Down,Control+n"),
+            ("kb-row-up", "Up,Control+p"),
+            ("kb-page-next", "Page_Down,Control+v"),
+            ("kb-page-prev", "Page_Up,Alt+v"),
+            ("kb-row-first", "Home,KP_Home,Alt+less"),
+            ("kb-row-last", "End,KP_End,Alt+greater"),
+            ("kb-secondary-paste", "Insert"),
+        ],
+    ),
+];
+
+/// Names of the built-in `kb_preset` sets
+pub fn preset_names() -> Vec<&'static str> {
+    PRESETS.iter().map(|(name, _)| *name).collect()
+}
+
+/// Whether `name` is a built-in `kb_preset`
+pub fn is_preset(name: &str) -> bool {
+    PRESETS.iter().any(|(preset, _)| *preset == name)
+}
+
+/// The `kb_preset` bindings with `rofi_kb_overrides` on top, keyed by action
+/// name with the kb- prefix; an unknown preset adds nothing
+pub fn with_preset(
+    preset: Option<&str>,
+    overrides: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut bindings: BTreeMap<String, String> = PRESETS
+        .iter()
+        .filter(|(name, _)| Some(*name) == preset)
+        .flat_map(|(_, pairs)| pairs.iter())
+        .map(|(action, keys)| (action.to_string(), keys.to_string()))
+        .collect();
+    for (name, keys) in overrides {
+        bindings.insert(action_name(name), keys.clone());
+    }
+    bindings
+}
+
+/// rofi's own bindings after `rofi_kb_overrides`, as (action, keys) pairs with
+/// the kb- prefix on every action name; kb-custom-N actions are left out since
+/// rofi-keys assigns those itself
+pub fn effective_bindings(overrides: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let mut bindings: BTreeMap<String, String> = PLAIN_DEFAULTS
+        .iter()
+        .map(|(action, keys)| (action.to_string(), keys.to_string()))
+        .collect();
+    for (name, keys) in overrides {
+        let action = action_name(name);
+        if custom_slot(&action).is_none() {
+            bindings.insert(action, keys.clone());
+        }
+    }
+    bindings
+}
+
+/// The rofi action already bound to `key`, e.g. "kb-toggle-case-sensitivity"
+/// for "grave"
+pub fn action_for<'a>(bindings: &'a BTreeMap<String, String>, key: &str) -> Option<&'a str> {
+    bindings
+        .iter()
+        .find(|(_, keys)| keys.split(',').any(|bound| bound.trim() == key))
+        .map(|(action, _)| action.as_str())
+}
+
+/// kb-custom slots (1-based) that `rofi_kb_overrides` binds itself
+pub fn overridden_slots(overrides: &BTreeMap<String, String>) -> Vec<usize> {
+    overrides
+        .keys()
+        .filter_map(|name| custom_slot(&action_name(name)))
+        .collect()
+}
+
+// "accept-entry", "-kb-accept-entry" and "kb-accept-entry" all name the same action
+fn action_name(name: &str) -> String {
+    let name = name.trim_start_matches('-');
+    format!("kb-{}", name.strip_prefix("kb-").unwrap_or(name))
+}
+
+fn custom_slot(action: &str) -> Option<usize> {
+    action.strip_prefix("kb-custom-")?.parse().ok()
+}
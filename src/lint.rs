@@ -0,0 +1,124 @@
+/// Programs that print the clipboard or the primary selection
+const CLIPBOARD_READERS: &[&str] = &["xclip", "xsel", "wl-paste"];
+
+/// X11-only clipboard programs
+const X11_CLIPBOARD_TOOLS: &[&str] = &["xclip", "xsel"];
+
+/// A suspicious pattern found in an entry's command
+#[derive(Debug)]
+pub struct Lint {
+    /// Stable identifier, for `lint_ignore`
+    pub id: &'static str,
+    pub message: &'static str,
+}
+
+/// Every lint `check` knows, as (identifier, message) pairs
+pub const LINTS: &[(&str, &str)] = &[
+    (
+        "W001",
+        "unquoted $(...) reads the clipboard and is split on spaces; use {clipboard}",
+    ),
+    (
+        "W002",
+        "xclip/xsel only reach X11 programs; use wl-copy/wl-paste on Wayland",
+    ),
+    (
+        "W003",
+        "~ inside single quotes is not expanded; use $HOME or leave it unquoted",
+    ),
+    (
+        "W004",
+        "trailing & is not needed, entries are already started in the background",
+    ),
+];
+
+/// Lints for `command`; `wayland` is whether the session runs on Wayland
+pub fn lint_command(command: &str, wayland: bool) -> Vec<Lint> {
+    let scan = scan(command);
+    let mut found = Vec::new();
+    if scan
+        .unquoted_substitutions
+        .iter()
+        .any(|inner| words(inner).any(|word| CLIPBOARD_READERS.contains(&word)))
+    {
+        found.push("W001");
+    }
+    if wayland && words(command).any(|word| X11_CLIPBOARD_TOOLS.contains(&word)) {
+        found.push("W002");
+    }
+    if scan.tilde_in_single_quotes {
+        found.push("W003");
+    }
+    let trimmed = command.trim_end();
+    if trimmed.ends_with('&') && !trimmed.ends_with("&&") && !trimmed.ends_with("\\&") {
+        found.push("W004");
+    }
+
+    found
+        .into_iter()
+        .filter_map(|id| LINTS.iter().find(|(known, _)| *known == id))
+        .map(|&(id, message)| Lint { id, message })
+        .collect()
+}
+
+/// Whether `id` is a lint identifier, e.g. for validating `lint_ignore`
+pub fn is_known(id: &str) -> bool {
+    LINTS.iter().any(|(known, _)| *known == id)
+}
+
+#[derive(Default)]
+struct Scan {
+    // Bodies of $(...) that are outside any quotes
+    unquoted_substitutions: Vec<String>,
+    tilde_in_single_quotes: bool,
+}
+
+// A single pass over the command tracking shell quoting; best effort, so
+// nested substitutions and backticks are only followed as far as parentheses
+fn scan(command: &str) -> Scan {
+    let mut scan = Scan::default();
+    let chars: Vec<char> = command.chars().collect();
+    let mut single = false;
+    let mut double = false;
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        match c {
+            '\\' if !single => index += 1,
+            '\'' if !double => single = !single,
+            '"' if !single => double = !double,
+            '~' if single => scan.tilde_in_single_quotes = true,
+            '$' if !single && chars.get(index + 1) == Some(&'(') => {
+                let start = index + 2;
+                let mut depth = 1;
+                let mut end = start;
+                while end < chars.len() && depth > 0 {
+                    match chars[end] {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    end += 1;
+                }
+                if !double {
+                    let inner_end = if depth == 0 { end - 1 } else { end };
+                    scan.unquoted_substitutions
+                        .push(chars[start..inner_end].iter().collect());
+                }
+                index = end;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+    scan
+}
+
+// Command words, split on whitespace and shell operators, without paths
+fn words(command: &str) -> impl Iterator<Item = &str> {
+    command
+        .split(|c: char| c.is_whitespace() || "|&;()`\"'".contains(c))
+        .filter(|word| !word.is_empty())
+        .map(|word| word.rsplit('/').next().unwrap_or(word))
+}
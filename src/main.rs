@@ -1,39 +1,892 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::env;
+use std::fmt::Write as _;
 use std::fs;
-use std::io::{self, Write};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitCode, Stdio};
 use std::str;
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, PoisonError, TryLockError};
+use std::thread;
+use std::time::Duration;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 
+mod bindings;
+mod cache;
+mod cheatsheet;
+mod ctl;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod envfile;
+mod error;
+mod executor;
+mod fallback;
+mod help;
+mod history;
+mod hooks;
+mod i18n;
+mod icons;
+mod instance;
+mod kb;
+mod lint;
+mod output;
+mod presets;
+mod profile;
+mod selection;
+mod setup;
+mod sources;
+mod tree;
+mod wm;
+
+use bindings::{MenuBinding, WindowManager};
+use cache::CachePolicy;
+use error::Error;
+use executor::{Executor, ShellExecutor};
+use fallback::{Backend, Fallback};
+use output::ColorChoice;
+use cheatsheet::CheatsheetFormat;
+use history::{CollapsedGroups, History, OutputLog, OutputRecord, StatsFormat};
+use instance::OnRunning;
+use presets::Preset;
+use regex::Regex;
+use selection::{SelectionFormat, SelectionSink, SelectionTarget};
+use sources::{MissingPolicy, Source, SourceAction, SourceItem, SourceJob};
+use tree::{MenuDump, MenuNode};
+
 /// A keyboard-driven application launcher using Rofi
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
-    /// Specify an alternate config file path
-    #[arg(short, long, value_name = "FILE")]
+    /// Specify an alternate config file path (default: $ROFI_KEYS_CONFIG, then
+    /// $XDG_CONFIG_HOME/rofi-keys/config.json, ~/.config by default)
+    #[arg(short, long, value_name = "FILE", global = true)]
     config: Option<PathBuf>,
 
-    /// Initialize a default config file and exit
+    /// Use a built-in menu instead of the config file's
+    #[arg(long, value_enum, value_name = "NAME", global = true)]
+    preset: Option<Preset>,
+
+    /// Print how long each startup phase takes to stderr
+    #[arg(long, global = true)]
+    profile_startup: bool,
+
+    /// Warn about config fields kept only for older configs, such as menu_title
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Explain on stderr what happened behind the scenes, e.g. source rows left out
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// When to color terminal output (NO_COLOR turns off "auto")
+    #[arg(long, value_enum, value_name = "WHEN", global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+
+    /// Print help with a config example for every feature
     #[arg(long)]
+    help_all: bool,
+
+    // Kept for scripts written before `init` became a subcommand
+    #[arg(long, hide = true)]
     init: bool,
+
+    // Same as the `setup` subcommand, spelled like --init
+    #[arg(long, hide = true)]
+    setup: bool,
+
+    // Same as the `list` subcommand, e.g. `--preset power --list`
+    #[arg(long, hide = true)]
+    list: bool,
+
+    // Options of `run`, accepted without the subcommand name
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+/// Options for showing the menu
+#[derive(Args, Debug, Clone, Default, PartialEq, Eq)]
+struct RunArgs {
+    /// Start at the named flow menu instead of the main menu
+    #[arg(long, visible_alias = "menu", value_name = "NAME")]
+    flow: Option<String>,
+
+    /// Only show entries with this tag (repeatable; any tag matches)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+
+    /// Print the command line of the picked entry instead of running it
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Run sources again instead of using their cached rows
+    #[arg(long)]
+    refresh: bool,
+
+    /// Pass rofi only the flags rofi-keys needs, leaving the rest to your rofi config
+    #[arg(long)]
+    raw_rofi: bool,
+
+    /// Run the last launched entry again without showing the menu
+    /// (shows the menu if that entry no longer exists)
+    #[arg(long)]
+    repeat_last: bool,
+
+    /// Run the entry bound to KEY (in --flow's menu, or the main menu) without
+    /// showing the menu
+    #[arg(long, value_name = "KEY", conflicts_with = "repeat_last")]
+    run_key: Option<char>,
+
+    /// Write the picked entry's command line to file descriptor N instead of running it
+    #[arg(long, value_name = "N", conflicts_with = "output_file")]
+    output_fd: Option<i32>,
+
+    /// Write the picked entry's command line to a file or named pipe instead of running it
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+
+    /// What --output-fd and --output-file write
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = SelectionFormat::Command)]
+    output_format: SelectionFormat,
+
+    /// With --output-fd or --output-file, run the entry as well
+    #[arg(long)]
+    and_exec: bool,
+
+    /// Open the menu with QUERY already typed into the filter
+    #[arg(long, value_name = "QUERY")]
+    query: Option<String>,
+
+    /// Print the key of the picked entry instead of running it; a cancel
+    /// prints nothing and exits with 10
+    #[arg(long, conflicts_with_all = ["repeat_last", "run_key"])]
+    emit_key: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Show the menu (the default when no subcommand is given)
+    Run(RunArgs),
+
+    /// Write the default config file and exit
+    Init,
+
+    /// Pick apps in rofi, starting from the browsers and terminals installed,
+    /// and write a config for them
+    Setup,
+
+    /// Check the config file and report the first problem found,
+    /// warning about entries whose program isn't on PATH
+    Validate,
+
+    /// Validate the config, then report every entry whose program is missing,
+    /// missing themes and keys rofi can't bind; fails if anything is broken
+    Check,
+
+    /// Print the menu as config JSON
+    List {
+        /// Also print disabled entries
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Check that rofi and the programs rofi-keys relies on are installed
+    Doctor,
+
+    /// Open the config file in $VISUAL or $EDITOR, then validate it
+    Edit,
+
+    /// Show how often each entry has been launched
+    Stats {
+        /// Only count launches within this span, e.g. 30d, 12h
+        #[arg(long, value_name = "SPAN")]
+        since: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: StatsFormat,
+    },
+
+    /// Print a keybinding reference for every menu
+    Cheatsheet {
+        /// Output format
+        #[arg(long, value_enum, default_value = "text")]
+        format: CheatsheetFormat,
+    },
+
+    /// Print the resolved menu tree for other programs to render
+    Dump {
+        /// Output format
+        #[arg(long, value_enum, default_value = "json")]
+        format: DumpFormat,
+    },
+
+    /// Put a disabled entry back in the menu
+    Enable {
+        /// Label or key of the entry
+        entry: String,
+    },
+
+    /// Hide an entry from the menu without deleting it
+    Disable {
+        /// Label or key of the entry
+        entry: String,
+    },
+
+    /// Manage cached source rows
+    Cache {
+        #[command(subcommand)]
+        action: CacheCommand,
+    },
+
+    /// Print window manager keybindings that open each menu
+    Bindings {
+        /// Window manager to print the bindings for
+        #[arg(long, value_enum)]
+        wm: WindowManager,
+    },
+
+    /// Print the path of the config file in use
+    ConfigPath {
+        /// List every file the config was read from, in load order, with where
+        /// its path came from
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Save this shell's environment for launched commands (run it from your login shell)
+    CaptureEnv {
+        /// Where to write it; defaults to the config's env_file, or "env" next to the config
+        #[arg(value_name = "FILE")]
+        file: Option<PathBuf>,
+    },
+
+    /// Keep running and take requests from `rofi-keys ctl` on a Unix socket
+    Daemon {
+        /// Also take org.rofikeys.Menu on the session bus (needs the dbus feature)
+        #[arg(long)]
+        dbus: bool,
+    },
+
+    /// Send a request to a running `rofi-keys daemon`
+    Ctl {
+        #[command(subcommand)]
+        action: CtlCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum CtlCommand {
+    /// Show the main menu, or a flow; fails while the daemon shows another
+    Show {
+        /// Flow to show instead of the main menu
+        menu: Option<String>,
+    },
+    /// Run the entry with this label as if it had been picked
+    Run {
+        label: String,
+        /// Flow the entry is in
+        #[arg(long)]
+        menu: Option<String>,
+    },
+    /// Make the daemon read the config file again
+    Reload,
+    /// Print the main menu as `rofi-keys dump` JSON
+    List,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Delete all cached source rows
+    Clear,
+}
+
+/// Output format for `rofi-keys dump`
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum DumpFormat {
+    Json,
+    Markdown,
+}
+
+// PID of the rofi child while it is on screen, 0 otherwise
+static ROFI_PID: AtomicI32 = AtomicI32::new(0);
+
+// Warn about an unset HOME once, not for every path that needed it
+static HOME_WARNING: Once = Once::new();
+
+// The signals RofiGuard handles
+const GUARDED_SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+// Handler address and flags of each guarded signal's disposition from before
+// RofiGuard, put back when it is dropped or the signal arrives; atomics, so
+// the handler can read them
+static OLD_ACTIONS: [(AtomicUsize, AtomicI32); 2] = [
+    (AtomicUsize::new(0), AtomicI32::new(0)),
+    (AtomicUsize::new(0), AtomicI32::new(0)),
+];
+
+// Put back the disposition GUARDED_SIGNALS[index] had before RofiGuard; only
+// async-signal-safe calls, as the handler uses it too
+fn restore_disposition(index: usize) {
+    let (handler, flags) = &OLD_ACTIONS[index];
+    // SAFETY: an all-zero sigaction is valid, and sigemptyset and sigaction get
+    // pointers to it that live for the whole call
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler.load(Ordering::SeqCst);
+        action.sa_flags = flags.load(Ordering::SeqCst);
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(GUARDED_SIGNALS[index], &action, std::ptr::null_mut());
+    }
+}
+
+// Signal handler that takes the rofi window down with us, then lets the
+// signal do whatever it would have done without us
+extern "C" fn handle_termination(signal: libc::c_int) {
+    let pid = ROFI_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        // SAFETY: kill is async-signal-safe and takes any pid
+        unsafe {
+            libc::kill(pid, libc::SIGTERM);
+        }
+    }
+    hooks::close_from_signal();
+    if let Some(index) = GUARDED_SIGNALS.iter().position(|&guarded| guarded == signal) {
+        restore_disposition(index);
+    }
+    // SAFETY: sigemptyset, sigaddset, sigprocmask and raise are
+    // async-signal-safe and get a set that lives for the whole call. The
+    // signal is blocked while its handler runs, so it is unblocked to be
+    // delivered now, with the disposition just put back
+    unsafe {
+        let mut set: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, signal);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+        libc::raise(signal);
+    }
+    // Still here: the old disposition ignored or handled the signal, but rofi
+    // is gone, so the run ends the way it always did
+    // SAFETY: _exit is async-signal-safe and ends the process without running
+    // anything else
+    unsafe {
+        libc::_exit(128 + signal);
+    }
+}
+
+// Kill the rofi child on SIGINT/SIGTERM for as long as the guard lives
+struct RofiGuard;
+
+impl RofiGuard {
+    fn new(pid: u32) -> Self {
+        ROFI_PID.store(pid as i32, Ordering::SeqCst);
+        let handler = handle_termination as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        for (index, &signal) in GUARDED_SIGNALS.iter().enumerate() {
+            // SAFETY: an all-zero sigaction is valid, sigaction gets pointers to
+            // locals that outlive the call, and the handler only touches
+            // atomics and async-signal-safe calls
+            unsafe {
+                // Read the current disposition before replacing it, so a signal
+                // arriving in between finds it saved; one that is already ours
+                // (a guard that is still alive) is not saved over the original
+                let mut old: libc::sigaction = std::mem::zeroed();
+                libc::sigaction(signal, std::ptr::null(), &mut old);
+                if old.sa_sigaction != handler {
+                    OLD_ACTIONS[index].0.store(old.sa_sigaction, Ordering::SeqCst);
+                    OLD_ACTIONS[index].1.store(old.sa_flags, Ordering::SeqCst);
+                }
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+        RofiGuard
+    }
+}
+
+impl Drop for RofiGuard {
+    fn drop(&mut self) {
+        for index in 0..GUARDED_SIGNALS.len() {
+            restore_disposition(index);
+        }
+        ROFI_PID.store(0, Ordering::SeqCst);
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Config {
     theme: Option<String>,
-    menu_title: Option<String>,
+    // rofi's -p prompt; menu_title is the older name for it
+    #[serde(alias = "menu_title")]
+    prompt: Option<String>,
+    // Window title, for window manager rules matching the menu
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    // Language of built-in labels and messages, e.g. "de"; LC_MESSAGES/LANG otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    locale: Option<String>,
+    // Key combination that opens the main menu, for `rofi-keys bindings`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    binding: Option<String>,
+    // Extra rofi theme snippet, e.g. "window { width: 30%; }"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    theme_str: Option<String>,
+    // Fixed text for the message bar, shown above any status line
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    // Leave matching, markup and case sensitivity to the user's rofi config
+    #[serde(default, skip_serializing_if = "is_false")]
+    raw_rofi: bool,
+    // Full command line for the menu in place of the one rofi-keys builds, with
+    // {title}, {theme}, {kb_args} and {extra_args} filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rofi_command: Option<String>,
+    // Passed to rofi's -monitor: a number, an output name, or e.g. "-1" for
+    // the monitor of the focused window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    monitor: Option<String>,
+    // Edge or corner of the screen rofi opens at, e.g. "north-east"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    location: Option<String>,
+    // Pixels to move the window by from `location`, e.g. to sit under a bar widget
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    x_offset: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    y_offset: Option<i32>,
+    // Menu program used when rofi isn't installed: "dmenu" or "fzf"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    fallback_backend: Option<String>,
+    // How commands are started: "shell" (default), "exec", "systemd", "setsid" or "wm"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    launch_method: Option<String>,
+    // Working directory for launched commands (entries can override with cwd)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    // Directories prepended to PATH for lookups and launched commands
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    path: Vec<String>,
+    // How many captured outputs to keep (default 20)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output_history: Option<usize>,
+    // File of KEY=value lines merged into the environment of launched commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    env_file: Option<String>,
+    // Variables kept for entries with clean_env; defaults to DEFAULT_CLEAN_ENV
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clean_env_allowlist: Option<Vec<String>>,
+    // Tell launched commands which entry started them through ROFI_KEYS_KEY,
+    // ROFI_KEYS_LABEL, ROFI_KEYS_COMMAND, ROFI_KEYS_GROUP and ROFI_KEYS_TAGS
+    #[serde(default, skip_serializing_if = "is_false")]
+    entry_env: bool,
+    // Wrapper prepended to every command, e.g. "firejail"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    launch_prefix: Option<String>,
+    // How keys are shown in rows: "prefix" (default) or "mnemonic"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_style: Option<String>,
+    // Where the key goes when it isn't underlined: "prefix" (default), "suffix", "none" or "column"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key_position: Option<String>,
+    // How rows are arranged: "list" (default), "columns" (a column per group)
+    // or "grid" (icons with short labels)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layout: Option<String>,
+    // Icons per row in the grid layout (default 5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    grid_columns: Option<usize>,
+    // Built-in set of remaps of rofi's own keybindings, e.g. "vim-keys"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kb_preset: Option<String>,
+    // Remaps of rofi's own keybindings, e.g. {"accept-entry": "Return"};
+    // applied on top of kb_preset
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    rofi_kb_overrides: BTreeMap<String, String>,
+    // Commands run before each menu window opens and after it closes, e.g. to
+    // pause notifications while the menu is up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_open: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_close: Option<String>,
+    // Command whose output is shown as a status line above the entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status_command: Option<String>,
+    // Key that expands the message bar into a key reference, e.g. "?"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hint_key: Option<String>,
+    // Command url entries are opened with (default xdg-open)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url_handler: Option<String>,
+    // Themes cycle_theme_key steps through, e.g. to preview them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    themes: Vec<String>,
+    // Key that shows the menu again with the next of `themes`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cycle_theme_key: Option<String>,
+    // Write the theme picked with cycle_theme_key into the config file's `theme`
+    #[serde(default, skip_serializing_if = "is_false")]
+    save_theme: bool,
+    // The file this config was read from; None for presets and built-in defaults
+    #[serde(skip)]
+    file: Option<PathBuf>,
+    // Key that runs a source again in its submenu, skipping the cache (default F5)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    refresh_key: Option<String>,
+    // Put each entry's command on a dimmed second line under its label
+    #[serde(default, skip_serializing_if = "is_false")]
+    show_commands: bool,
+    // false leaves keys out of rows entirely; they still work, and Enter runs the picked row
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    show_key_hints: Option<bool>,
+    // Longest command shown by show_commands before its middle is cut (default 60)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command_width: Option<usize>,
+    // Keep groups collapsed or expanded the way they were left last time
+    #[serde(default, skip_serializing_if = "is_false")]
+    remember_collapsed: bool,
+    // Also bind 1-9 to the first nine entries, whatever their own keys are
+    #[serde(default, skip_serializing_if = "is_false")]
+    number_keys: bool,
+    // Add how often each entry was launched to its row, e.g. "Firefox ·42"
+    #[serde(default, skip_serializing_if = "is_false")]
+    show_usage_count: bool,
+    // Groups to list first, in this order; the rest follow as they appear
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    group_order: Vec<String>,
+    // How groups of the main menu are shown: "inline" (default), under headers,
+    // or "submenu", each behind an entry of its own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group_mode: Option<String>,
+    // Keys of the submenu entries, by group name; others get a free key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    group_keys: BTreeMap<String, String>,
+    // Don't warn about entries in the same menu with (nearly) the same label
+    #[serde(default, skip_serializing_if = "is_false")]
+    allow_duplicate_labels: bool,
+    // Warn on startup about entries whose program isn't on PATH
+    #[serde(default, skip_serializing_if = "is_false")]
+    warn_missing_commands: bool,
+    // Dim entries whose program isn't on PATH (entries can override with check_path)
+    #[serde(default, skip_serializing_if = "is_false")]
+    check_path: bool,
+    // Also stop unavailable entries from being activated
+    #[serde(default, skip_serializing_if = "is_false")]
+    disable_unavailable: bool,
+    // Run text typed into the filter as a shell command when Enter matches no row
+    #[serde(default, skip_serializing_if = "is_false")]
+    allow_custom_command: bool,
+    // Regex a typed command must match as a whole to be run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_command_pattern: Option<String>,
+    // Named command lines that entries run with command_ref, e.g. to keep them
+    // in one place shared between machines
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    commands: BTreeMap<String, String>,
+    entries: Vec<MenuEntryConfig>,
+    // Named menus reachable through entries with `next`, or started with --flow
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    flows: BTreeMap<String, FlowMenuConfig>,
+}
+
+// One named menu of a flow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FlowMenuConfig {
+    // Defaults to the flow's name; flows used to call this title
+    #[serde(default, alias = "title", skip_serializing_if = "Option::is_none")]
+    prompt: Option<String>,
+    // Key combination that opens this menu directly, for `rofi-keys bindings`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    binding: Option<String>,
+    // theme, theme_str and message override the menu this flow was opened from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    theme: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    theme_str: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    show_commands: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    show_key_hints: Option<bool>,
     entries: Vec<MenuEntryConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct MenuEntryConfig {
+    // Left empty to have a free key picked from the label when loaded
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     key: String,
     label: String,
+    // Left empty for aliases, which borrow the command of the entry they reference
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     command: String,
+    // Label of another entry this one is an alias for
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    reference: Option<String>,
+    // Name of a command in `commands`; becomes the entry's command when loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command_ref: Option<String>,
+    // Address opened with url_handler; becomes the entry's command when loaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    // Command with an {arg} placeholder filled from a second menu of `args`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    command_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    args: Vec<ArgChoiceConfig>,
+    // Dynamic rows from an external program, e.g. "clipboard_history"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_type: Option<String>,
+    // "cliphist" or "greenclip"; detected from the session when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    clipboard_tool: Option<String>,
+    // "hide" (default) or "disable" the entry when its source tool is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    missing: Option<String>,
+    // File read by file-backed sources such as "snippets"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_file: Option<String>,
+    // How picked snippets are delivered: "clipboard" (default) or "type"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
+    // Reuse a source's rows for this many seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_seconds: Option<u64>,
+    // Show expired cached rows at once and refresh them in the background
+    #[serde(default, skip_serializing_if = "is_false")]
+    stale_while_revalidate: bool,
+    // List the source's rows in this menu instead of behind the entry's key
+    #[serde(default, skip_serializing_if = "is_false")]
+    inline: bool,
+    // How long an inline source may run before it is reported as timed out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_timeout_ms: Option<u64>,
+    // Most rows a source lists (default DEFAULT_SOURCE_LIMIT, 0 for all of them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    limit: Option<usize>,
+    // Name of the flow menu to show instead of running anything
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+    // Built-in action instead of a command: "history" browses captured outputs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    action: Option<String>,
+    // Free-form labels used to pick subsets of entries with --tag
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+    // Section of the menu the entry is listed under, behind a collapsible header;
+    // `category` reads better with the columns layout
+    #[serde(default, alias = "category", skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    // Icon name from the icon theme, or a path, shown in the grid layout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    // Set to false to keep an entry in the config but out of the menu
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    enabled: bool,
+    #[serde(flatten)]
+    options: EntryOptions,
+}
+
+impl MenuEntryConfig {
+    fn new(key: &str, label: &str, command: &str) -> Self {
+        MenuEntryConfig {
+            key: key.to_string(),
+            label: label.to_string(),
+            command: command.to_string(),
+            reference: None,
+            command_ref: None,
+            url: None,
+            command_template: None,
+            args: Vec::new(),
+            source_type: None,
+            clipboard_tool: None,
+            missing: None,
+            source_file: None,
+            output: None,
+            cache_seconds: None,
+            stale_while_revalidate: false,
+            inline: false,
+            source_timeout_ms: None,
+            limit: None,
+            next: None,
+            action: None,
+            tags: Vec::new(),
+            group: None,
+            icon: None,
+            enabled: true,
+            options: EntryOptions::default(),
+        }
+    }
+}
+
+// One option offered by an argument-choice entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ArgChoiceConfig {
+    // Defaults to the next free digit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    label: String,
+    value: String,
+}
+
+// Per-entry settings that affect how the command is launched
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct EntryOptions {
+    // systemd unit name used with the systemd launch method
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    unit: Option<String>,
+    // systemd slice to place the unit in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    slice: Option<String>,
+    // Extra systemd properties, e.g. "MemoryMax=2G"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<String>,
+    // Refuse to launch instead of renaming when the unit is already running
+    #[serde(default, skip_serializing_if = "is_false")]
+    unique_unit: bool,
+    // CPU niceness, -20 (highest priority) to 19 (lowest)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nice: Option<i8>,
+    // I/O scheduling: "idle" or "best-effort:N" with N from 0 to 7
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ionice: Option<String>,
+    // Set to false to skip the global launch_prefix for this entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sandbox: Option<bool>,
+    // Overrides the global check_path for this entry
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    check_path: Option<bool>,
+    // Ask for a yes/no confirmation before launching
+    #[serde(default, skip_serializing_if = "is_false")]
+    confirm: bool,
+    // Menu program for this entry's follow-up dialogs: "rofi", "dmenu" or "fzf"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backend: Option<String>,
+    // Overrides the global launch_method: "shell", "exec", "systemd", "setsid" or "wm"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exec_mode: Option<String>,
+    // Show the menu again after launching, for entries pressed several times in a row
+    #[serde(default, skip_serializing_if = "is_false")]
+    repeatable: bool,
+    // Working directory for this entry, overriding the global cwd
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cwd: Option<String>,
+    // Wait this long after rofi has closed, e.g. so focus returns to the window to act on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    delay_ms: Option<u64>,
+    // Wait for the command and keep what it prints in the output history
+    #[serde(default, skip_serializing_if = "is_false")]
+    capture_output: bool,
+    // Also bind Alt+key, which runs the command in the foreground and shows its output
+    #[serde(default, skip_serializing_if = "is_false")]
+    dual_activation: bool,
+    // Become the command (exec) instead of starting it, e.g. to hand off to another launcher
+    #[serde(default, skip_serializing_if = "is_false")]
+    exec_replace: bool,
+    // Variables set for this entry's command, on top of the inherited environment
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+    // Start from an empty environment keeping only clean_env_allowlist (and `env`)
+    #[serde(default, skip_serializing_if = "is_false")]
+    clean_env: bool,
+    // Don't start a second copy of a program that is already running
+    #[serde(default, skip_serializing_if = "is_false")]
+    single_instance: bool,
+    // Regex for the running program's name; defaults to the command's program
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    match_pattern: Option<String>,
+    // Window class to focus when it is running; defaults to the program's name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    focus_class: Option<String>,
+    // "focus" (default), "ignore" or "launch" when it is already running
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    on_running: Option<String>,
+    // `check` lints not to report for this entry, e.g. ["W003"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    lint_ignore: Vec<String>,
+}
+
+impl EntryOptions {
+    // Take each setting this one leaves unset from `other`; env is merged,
+    // with this one's variables winning
+    fn fill_from(&mut self, other: EntryOptions) {
+        let EntryOptions {
+            unit,
+            slice,
+            properties,
+            unique_unit,
+            nice,
+            ionice,
+            sandbox,
+            check_path,
+            confirm,
+            backend,
+            exec_mode,
+            repeatable,
+            cwd,
+            delay_ms,
+            capture_output,
+            dual_activation,
+            exec_replace,
+            mut env,
+            clean_env,
+            single_instance,
+            match_pattern,
+            focus_class,
+            on_running,
+            lint_ignore,
+        } = other;
+        self.unit = self.unit.take().or(unit);
+        self.slice = self.slice.take().or(slice);
+        if self.properties.is_empty() {
+            self.properties = properties;
+        }
+        self.unique_unit |= unique_unit;
+        self.nice = self.nice.or(nice);
+        self.ionice = self.ionice.take().or(ionice);
+        self.sandbox = self.sandbox.or(sandbox);
+        self.check_path = self.check_path.or(check_path);
+        self.confirm |= confirm;
+        self.backend = self.backend.take().or(backend);
+        self.exec_mode = self.exec_mode.take().or(exec_mode);
+        self.repeatable |= repeatable;
+        self.cwd = self.cwd.take().or(cwd);
+        self.delay_ms = self.delay_ms.or(delay_ms);
+        self.capture_output |= capture_output;
+        self.dual_activation |= dual_activation;
+        self.exec_replace |= exec_replace;
+        env.append(&mut self.env);
+        self.env = env;
+        self.clean_env |= clean_env;
+        self.single_instance |= single_instance;
+        self.match_pattern = self.match_pattern.take().or(match_pattern);
+        self.focus_class = self.focus_class.take().or(focus_class);
+        self.on_running = self.on_running.take().or(on_running);
+        if self.lint_ignore.is_empty() {
+            self.lint_ignore = lint_ignore;
+        }
+    }
+}
+
+// Used by serde to keep default flags out of written configs
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug)]
@@ -41,283 +894,4892 @@ struct MenuEntry {
     key: char,
     label: String,
     command: String,
+    command_template: Option<String>,
+    args: Vec<ArgChoiceConfig>,
+    options: EntryOptions,
+    // Dynamic source opened instead of running a command
+    source: Option<Source>,
+    // How long the source's rows may be reused
+    cache: Option<CachePolicy>,
+    // Most rows the source lists; 0 for no limit
+    limit: usize,
+    // Flow menu this entry switches to
+    next: Option<String>,
+    action: Option<MetaAction>,
+    // False when the program behind the entry is missing
+    available: bool,
+    // Whether the entry gets a keybinding at all
+    activatable: bool,
+    // An earlier entry in the menu has the same key
+    shadowed: bool,
+    // Row listed by an inline source; it has no key and is picked with Enter
+    inline: bool,
+    // What picking an inline source row does; None for rows reporting a failure
+    item: Option<SourceItem>,
+    // Group the entry is listed under
+    group: Option<String>,
+    icon: Option<String>,
+    tags: Vec<String>,
 }
 
+// A source entry whose rows are listed in the menu itself
 #[derive(Debug)]
-struct Menu {
-    title: String,
-    entries: Vec<MenuEntry>,
-    theme: Option<String>,
+struct InlineSource {
+    label: String,
+    // None when the source's program is missing
+    job: Option<SourceJob>,
+    options: EntryOptions,
+    // Most rows listed; 0 for no limit
+    limit: usize,
 }
 
-impl Menu {
-    fn new(title: &str, theme: Option<String>) -> Self {
-        Menu {
-            title: title.to_string(),
-            entries: Vec::new(),
-            theme,
-        }
-    }
+// Inline sources that take longer than this show a "timed out" row
+const DEFAULT_SOURCE_TIMEOUT_MS: u64 = 2000;
 
-    fn add_entry(&mut self, key: char, label: &str, command: &str) {
-        self.entries.push(MenuEntry {
-            key,
-            label: label.to_string(),
-            command: command.to_string(),
-        });
-    }
+// Sources list at most this many rows unless their entry sets `limit`; past
+// that, rows are too many to scroll through and only cost time to pipe to rofi
+const DEFAULT_SOURCE_LIMIT: usize = 500;
 
-    fn generate_rofi_input(&self) -> String {
-        self.entries
-            .iter()
-            .map(|entry| format!("[{}] {}", entry.key, entry.label))
-            .collect::<Vec<_>>()
-            .join("\n")
+// Cut `items` down to `limit` rows (0 keeps them all), returning the number left
+// out; --verbose reports it
+fn limit_items(label: &str, items: &mut Vec<SourceItem>, limit: usize) -> usize {
+    if limit == 0 || items.len() <= limit {
+        return 0;
     }
+    let dropped = items.len() - limit;
+    items.truncate(limit);
+    output::verbose(format!("{}: listing {} of {} rows (limit {})", label, limit, limit + dropped, limit));
+    dropped
+}
 
-    fn get_command_for_key(&self, key: char) -> Option<&str> {
-        self.entries
-            .iter()
-            .find(|entry| entry.key == key)
-            .map(|entry| entry.command.as_str())
-    }
+// The informational last row of a truncated source
+fn more_rows_label(dropped: usize) -> String {
+    format!("… {} more not listed", dropped)
+}
 
-    fn display_with_rofi(&self) -> io::Result<Option<String>> {
-        // Prepare key bindings for each menu entry
-        let mut kb_args = Vec::new();
-        let mut key_to_index: HashMap<char, i32> = HashMap::new();
-        
-        // For each entry, create a custom keybinding
-        for (i, entry) in self.entries.iter().enumerate() {
-            let kb_index = i + 1; // Rofi uses 1-based indexing for kb-custom
-            kb_args.push(format!("-kb-custom-{}", kb_index));
-            kb_args.push(entry.key.to_string());
-            key_to_index.insert(entry.key, kb_index as i32);
+// Default for command_width
+const DEFAULT_COMMAND_WIDTH: usize = 60;
+
+// Default for refresh_key
+const DEFAULT_REFRESH_KEY: &str = "F5";
+
+// Default for url_handler
+const DEFAULT_URL_HANDLER: &str = "xdg-open";
+
+// Default for grid_columns
+const DEFAULT_GRID_COLUMNS: usize = 5;
+
+// Labels in the grid layout are cut to this many characters
+const GRID_LABEL_WIDTH: usize = 14;
+
+// Row separator under show_commands, since rows then contain a newline
+const TWO_LINE_SEPARATOR: char = '\u{1f}';
+
+// rofi has kb-custom-1 to kb-custom-19; entries past that get no key
+const ROFI_CUSTOM_KEYS: usize = 19;
+
+// The kb-custom slot (0-based) behind a rofi exit code: Custom-1 = 10 is
+// slot 0, Custom-19 = 28 is slot 18, and other codes are no key at all
+fn custom_slot(exit_code: i32) -> Option<usize> {
+    let slot = usize::try_from(exit_code.checked_sub(10)?).ok()?;
+    (slot < ROFI_CUSTOM_KEYS).then_some(slot)
+}
+
+// Which kb-custom slot (0-based) each key of a menu gets. Entry keys use the
+// entry's index, so an exit code maps straight to the entry that should run;
+// the hint key and then the Alt+key of dual_activation entries come after the
+// last entry. show() binds this plan, check and doctor report on it
+#[derive(Debug, Default)]
+struct SlotPlan {
+    // (slot, entry index) of every bound entry key
+    entries: Vec<(usize, usize)>,
+    hint: Option<usize>,
+    theme: Option<usize>,
+    // (slot, entry index) of every bound Alt+key
+    foreground: Vec<(usize, usize)>,
+    // Slots needed for every key to be bound, which may be more than rofi has
+    needed: usize,
+    // Entries whose key, or Alt+key, didn't fit
+    overflow: Vec<usize>,
+    foreground_overflow: Vec<usize>,
+    hint_overflow: bool,
+    theme_overflow: bool,
+}
+
+fn plan_slots(entries: &[MenuEntry], hint_key: bool, theme_key: bool) -> SlotPlan {
+    let mut plan = SlotPlan::default();
+    // Only the first entry with a key is bound; inline rows have none
+    let bound = |entry: &&MenuEntry| entry.activatable && !entry.shadowed && !entry.inline;
+    for (index, _) in entries.iter().enumerate().filter(|(_, entry)| bound(entry)) {
+        if index < ROFI_CUSTOM_KEYS {
+            plan.entries.push((index, index));
+        } else {
+            plan.overflow.push(index);
         }
-        
-        // Generate menu items
-        let menu_input = self.generate_rofi_input();
-        
-        // Basic Rofi arguments
-        let mut rofi_args = vec![
-            "-dmenu", 
-            "-i", 
-            "-p", 
-            &self.title,
-            "-no-fork",  // Added to prevent forking which may trigger systemd
-            "-markup-rows",
-            "-no-custom", // Disable manual entry
-            "-theme-str", "configuration { matching: \"regex\"; }" // Use regex matching to avoid filtering
-        ];
-        
-        // Add theme if specified
-        if let Some(theme) = &self.theme {
-            rofi_args.push("-theme");
-            rofi_args.push(theme);
+        plan.needed = index + 1;
+    }
+
+    let mut next = entries.len();
+    if hint_key {
+        if next < ROFI_CUSTOM_KEYS {
+            plan.hint = Some(next);
+        } else {
+            plan.hint_overflow = true;
         }
-        
-        // Add all the key binding arguments
-        for arg in kb_args.iter() {
-            rofi_args.push(arg);
+        next += 1;
+        plan.needed = next;
+    }
+    if theme_key {
+        if next < ROFI_CUSTOM_KEYS {
+            plan.theme = Some(next);
+        } else {
+            plan.theme_overflow = true;
         }
-        
-        // Prepare and execute rofi command
-        let mut child = Command::new("rofi")
-            .args(rofi_args)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::null())
-            .spawn()?;
-            
-        // Write menu items to rofi's stdin
-        if let Some(mut stdin) = child.stdin.take() {
-            stdin.write_all(menu_input.as_bytes())?;
+        next += 1;
+        plan.needed = next;
+    }
+    for &(_, index) in &plan.entries {
+        if !entries[index].options.dual_activation {
+            continue;
         }
-        
-        // Get rofi's output and exit status
-        let output = child.wait_with_output()?;
-        let exit_code = output.status.code().unwrap_or(0);
-        
-        // Check for direct key activation (custom-N exit codes)
-        if exit_code >= 10 {
-            // Find which key was pressed based on exit code
-            let kb_index = exit_code - 9; // Custom-1 = 10, Custom-2 = 11, etc.
-            
-            // Find the key that corresponds to this index
-            for (key, idx) in &key_to_index {
-                if *idx == kb_index {
-                    // Get the command for this key
-                    if let Some(cmd) = self.get_command_for_key(*key) {
-                        return Ok(Some(cmd.to_string()));
-                    }
-                }
-            }
+        if next < ROFI_CUSTOM_KEYS {
+            plan.foreground.push((next, index));
+        } else {
+            plan.foreground_overflow.push(index);
         }
-        
-        // If no direct key was detected, return None
-        Ok(None)
+        next += 1;
+        plan.needed = next;
     }
+    plan
 }
 
-// Modified function to execute a command from string
-// This avoids systemd scope issues
-fn execute_command(command: &str) -> io::Result<()> {
-    // Use sh -c to launch the program
-    // This bypasses some of the systemd scoping issues
-    Command::new("sh")
-        .arg("-c")
-        .arg(command)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .spawn()?;
+// Keys handed out to argument choices that don't pick their own
+const CHOICE_KEYS: &str = "123456789abcdefghijklmnopqrstuvwxyz";
 
-    Ok(())
-}
+impl MenuEntry {
+    // The ROFI_KEYS_* variables describing this entry; group and tags are
+    // empty when unset, tags comma-separated
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("ROFI_KEYS_KEY", self.key.to_string()),
+            ("ROFI_KEYS_LABEL", self.label.clone()),
+            ("ROFI_KEYS_COMMAND", self.command.clone()),
+            ("ROFI_KEYS_GROUP", self.group.clone().unwrap_or_default()),
+            ("ROFI_KEYS_TAGS", self.tags.join(",")),
+        ]
+    }
 
-// Expand ~ to home directory in paths
-fn expand_path(path: &str) -> String {
-    if path.starts_with("~/") {
-        if let Ok(home) = env::var("HOME") {
-            return path.replacen("~", &home, 1);
+    // Build the second-stage menu for an argument-choice entry
+    fn choice_menu(&self, parent: &Menu) -> Option<Menu> {
+        let template = self.command_template.as_ref()?;
+        let mut menu = Menu::new(MenuStyle {
+            prompt: self.label.clone(),
+            ..parent.style.clone()
+        });
+        menu.key_style = parent.key_style;
+        menu.key_position = parent.key_position;
+        menu.monitor.clone_from(&parent.monitor);
+        menu.placement.clone_from(&parent.placement);
+        menu.fallback = parent.fallback;
+        menu.backend = self.backend();
+        menu.raw = parent.raw;
+        menu.extra_args.clone_from(&parent.extra_args);
+        menu.rofi_command.clone_from(&parent.rofi_command);
+        menu.command_width = parent.command_width;
+
+        let taken: Vec<char> = self
+            .args
+            .iter()
+            .filter_map(|arg| arg.key.as_ref().and_then(|key| key.chars().next()))
+            .collect();
+        let mut free_keys = CHOICE_KEYS.chars().filter(|key| !taken.contains(key));
+
+        for arg in &self.args {
+            let key = match arg.key.as_ref().and_then(|key| key.chars().next()) {
+                Some(key) => key,
+                None => match free_keys.next() {
+                    Some(key) => key,
+                    None => break,
+                },
+            };
+            let mut choice =
+                MenuEntryConfig::new("", &arg.label, &template.replace("{arg}", &arg.value));
+            choice.options = self.options.clone();
+            menu.add_entry(key, choice);
+        }
+
+        Some(menu)
+    }
+
+    // The menu program its follow-up dialogs ask for, if not the menu's own
+    fn backend(&self) -> Option<Backend> {
+        self.options.backend.as_deref().and_then(|name| Backend::parse(name).ok())
+    }
+
+    // What the entry does, as shown next to its label
+    fn action_text(&self) -> String {
+        match (&self.command_template, &self.next) {
+            (Some(template), _) => template.clone(),
+            (None, Some(next)) => format!("→ {}", next),
+            (None, None) => self.command.clone(),
         }
     }
-    path.to_string()
 }
 
-// Create a default configuration
-fn create_default_config() -> Config {
-    Config {
-        theme: None, // Use Rofi's default theme
-        menu_title: Some("Applications".to_string()),
-        entries: vec![
-            MenuEntryConfig {
-                key: "f".to_string(),
-                label: "Firefox".to_string(),
-                command: "firefox".to_string(),
-            },
-            MenuEntryConfig {
-                key: "p".to_string(),
-                label: "Firefox Private".to_string(),
-                command: "firefox --private-window".to_string(),
-            },
-            MenuEntryConfig {
-                key: "m".to_string(),
-                label: "MPV".to_string(),
-                command: "mpv".to_string(),
-            },
-            MenuEntryConfig {
-                key: "v".to_string(),
-                label: "MPV (clipboard)".to_string(),
-                command: "mpv \"$(xclip -o)\"".to_string(),
-            },
-            MenuEntryConfig {
-                key: "t".to_string(),
-                label: "Terminal".to_string(),
-                command: "x-terminal-emulator".to_string(),
-            },
-        ],
+// How an entry's key is presented in its row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum KeyStyle {
+    // "[f] Firefox"
+    #[default]
+    Prefix,
+    // The key underlined where it occurs in the label, like GTK mnemonics
+    Mnemonic,
+}
+
+impl KeyStyle {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "prefix" => Ok(KeyStyle::Prefix),
+            "mnemonic" => Ok(KeyStyle::Mnemonic),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown key_style '{}' (expected \"prefix\" or \"mnemonic\")",
+                other
+            ))),
+        }
     }
 }
 
-// Write a config to a specific path
-fn write_config(config: &Config, path: &PathBuf) -> io::Result<()> {
-    // Create directory if it doesn't exist
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent)?;
+// Where a key shown as text sits in its row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum KeyPosition {
+    // "[f] Firefox"
+    #[default]
+    Prefix,
+    // "Firefox (f)"
+    Suffix,
+    // Just "Firefox"; the key still works
+    None,
+    // "Firefox   f", labels padded so the keys form a column on the right
+    Column,
+}
+
+impl KeyPosition {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "prefix" => Ok(KeyPosition::Prefix),
+            "suffix" => Ok(KeyPosition::Suffix),
+            "none" => Ok(KeyPosition::None),
+            "column" => Ok(KeyPosition::Column),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown key_position '{}' (expected \"prefix\", \"suffix\", \"none\" or \"column\")",
+                other
+            ))),
+        }
     }
 
-    // Serialize to JSON with pretty formatting
-    let json = serde_json::to_string_pretty(config)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    
-    // Write the config
-    fs::write(path, json)?;
-    println!("Configuration written to {}", path.display());
-    
-    Ok(())
+    // `width` is the widest label in the menu, which Column pads to
+    fn write(self, out: &mut String, key: char, label: &str, width: usize) {
+        // Writing to a String can't fail
+        let _ = match self {
+            KeyPosition::Prefix => write!(out, "[{}] {}", key, label),
+            KeyPosition::Suffix => write!(out, "{} ({})", label, key),
+            KeyPosition::None => write!(out, "{}", label),
+            KeyPosition::Column => write!(out, "{:<width$}  {}", label, key, width = width),
+        };
+    }
 }
 
-// Get the default config path
-fn get_default_config_path() -> io::Result<PathBuf> {
-    let home = env::var("HOME")
-        .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "HOME directory not found"))?;
-    
-    let mut path = PathBuf::from(home);
-    path.push(".config/rofi-keys/config.json");
-    
-    Ok(path)
+// How a menu's rows are arranged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Layout {
+    // One column, groups one after another
+    #[default]
+    List,
+    // A column per group, side by side; ungrouped entries get the first one
+    Columns,
+    // Icons with short labels, row after row
+    Grid,
 }
 
-// Function to load menu entries from JSON config file
-fn load_config(config_path: &PathBuf) -> io::Result<Config> {
-    // Check if the config file exists
-    if !config_path.exists() {
-        // Create a default config
-        let default_config = create_default_config();
-        
-        // Write the default config
-        write_config(&default_config, config_path)?;
-        
-        return Ok(default_config);
+impl Layout {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "list" => Ok(Layout::List),
+            "columns" => Ok(Layout::Columns),
+            "grid" => Ok(Layout::Grid),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown layout '{}' (expected \"list\", \"columns\" or \"grid\")",
+                other
+            ))),
+        }
     }
-    
-    // Read and parse the JSON config
-    let content = fs::read_to_string(config_path)?;
-    let config: Config = serde_json::from_str(&content)
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid JSON config: {}", e)))?;
-    
-    Ok(config)
 }
 
-fn main() -> io::Result<()> {
-    // Parse command-line arguments using Clap
-    let cli = Cli::parse();
-    
-    // Get the config path (custom or default)
-    let config_path = match cli.config {
-        Some(path) => path,
-        None => get_default_config_path()?,
-    };
-    
-    // If --init flag is set, just create the config and exit
-    if cli.init {
-        let default_config = create_default_config();
-        write_config(&default_config, &config_path)?;
-        println!("Default configuration initialized at {}", config_path.display());
-        return Ok(());
+// Edge or corner of the screen rofi's window is placed at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Center,
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Location {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "center" => Ok(Location::Center),
+            "north" => Ok(Location::North),
+            "north-east" => Ok(Location::NorthEast),
+            "east" => Ok(Location::East),
+            "south-east" => Ok(Location::SouthEast),
+            "south" => Ok(Location::South),
+            "south-west" => Ok(Location::SouthWest),
+            "west" => Ok(Location::West),
+            "north-west" => Ok(Location::NorthWest),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown location '{}' (expected \"center\", \"north\", \"north-east\", \"east\", \
+                 \"south-east\", \"south\", \"south-west\", \"west\" or \"north-west\")",
+                other
+            ))),
+        }
     }
-    
-    // Load configuration
-    let config = match load_config(&config_path) {
-        Ok(cfg) => cfg,
-        Err(e) => {
-            eprintln!("Error loading config from {}: {}", config_path.display(), e);
-            // Use default config if loading fails
-            create_default_config()
+
+    // The position as rofi themes write it
+    fn rasi(self) -> &'static str {
+        match self {
+            Location::Center => "center",
+            Location::North => "north",
+            Location::NorthEast => "north east",
+            Location::East => "east",
+            Location::SouthEast => "south east",
+            Location::South => "south",
+            Location::SouthWest => "south west",
+            Location::West => "west",
+            Location::NorthWest => "north west",
         }
-    };
-    
-    // Expand theme path if it exists
-    let theme = config.theme.map(|t| expand_path(&t));
-    
-    // Create menu
-    let mut menu = Menu::new(
-        config.menu_title.as_deref().unwrap_or("Shortcuts"),
-        theme,
-    );
+    }
+}
+
+// How the main menu shows its groups
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum GroupMode {
+    // Under collapsible headers
+    #[default]
+    Inline,
+    // Each group a flow of its own, opened from an entry in the main menu
+    Submenu,
+}
+
+impl GroupMode {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "inline" => Ok(GroupMode::Inline),
+            "submenu" => Ok(GroupMode::Submenu),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown group_mode '{}' (expected \"inline\" or \"submenu\")",
+                other
+            ))),
+        }
+    }
+}
+
+// Outcome of showing a menu once
+enum Shown<'a> {
+    Picked(&'a MenuEntry),
+    // Picked with the Alt+key of a dual_activation entry
+    Foreground(&'a MenuEntry),
+    // Text typed into the filter, with allow_custom_command
+    Custom(String),
+    HintKey,
+    CycleTheme,
+    // A group header was picked
+    Toggle(&'a str),
+    Cancelled,
+}
+
+// What the user settled on in a menu
+enum Choice<'a> {
+    Entry(&'a MenuEntry),
+    // An entry to run in the foreground, showing what it prints
+    Foreground(&'a MenuEntry),
+    // A shell command typed into the filter
+    Custom(String),
+}
+
+// A line of the menu as rofi shows it
+enum Row<'a> {
+    Entry(&'a MenuEntry),
+    // Group name, and whether the group's entries are hidden
+    Header(&'a str, bool),
+    // Empty row that pads a column in the columns layout
+    Filler,
+}
+
+// How a menu looks; submenus start from their parent's style
+#[derive(Debug, Clone, Default)]
+struct MenuStyle {
+    prompt: String,
+    // Passed to -window-title; submenus keep the main menu's
+    window_title: Option<String>,
+    theme: Option<String>,
+    theme_str: Option<String>,
+    message: Option<String>,
+    // Two-line rows with the command under the label
+    show_commands: bool,
+    // Keys hidden from rows, which are then picked with Enter
+    hide_keys: bool,
+}
+
+impl MenuStyle {
+    // The main menu's style, straight from the global settings
+    fn from_config(config: &Config) -> Self {
+        MenuStyle {
+            prompt: config
+                .prompt
+                .clone()
+                .unwrap_or_else(|| i18n::tr("Shortcuts").to_string()),
+            window_title: config.title.clone(),
+            theme: config.theme.as_deref().map(expand_path),
+            theme_str: config.theme_str.clone(),
+            message: config.message.clone(),
+            show_commands: config.show_commands,
+            hide_keys: config.show_key_hints == Some(false),
+        }
+    }
+
+    // This style with whatever the flow sets itself
+    fn for_flow(&self, name: &str, flow: &FlowMenuConfig) -> Self {
+        MenuStyle {
+            prompt: flow.prompt.clone().unwrap_or_else(|| name.to_string()),
+            window_title: self.window_title.clone(),
+            theme: flow.theme.as_deref().map(expand_path).or_else(|| self.theme.clone()),
+            theme_str: flow.theme_str.clone().or_else(|| self.theme_str.clone()),
+            message: flow.message.clone().or_else(|| self.message.clone()),
+            show_commands: flow.show_commands.unwrap_or(self.show_commands),
+            hide_keys: flow.show_key_hints.map_or(self.hide_keys, |show| !show),
+        }
+    }
+
+    // -window-title, -theme and -theme-str arguments for rofi
+    fn rofi_args(&self) -> Vec<&str> {
+        self.rofi_args_with(self.theme_str.as_deref())
+    }
+
+    // The same with a theme_str of the caller's, e.g. the style's own plus layout rules
+    fn rofi_args_with<'a>(&'a self, theme_str: Option<&'a str>) -> Vec<&'a str> {
+        let mut args = Vec::new();
+        if let Some(title) = &self.window_title {
+            args.extend(["-window-title", title.as_str()]);
+        }
+        if let Some(theme) = &self.theme {
+            args.extend(["-theme", theme.as_str()]);
+        }
+        if let Some(theme_str) = theme_str {
+            args.extend(["-theme-str", theme_str]);
+        }
+        args
+    }
+}
+
+#[derive(Debug)]
+struct Menu {
+    style: MenuStyle,
+    entries: Vec<MenuEntry>,
+    // Index of the first entry bound to each key
+    key_index: HashMap<char, usize>,
+    key_style: KeyStyle,
+    key_position: KeyPosition,
+    layout: Layout,
+    grid_columns: usize,
+    // Where rofi opens, passed to -monitor
+    monitor: Option<String>,
+    // window rules for location and offsets, added to the theme_str
+    placement: Option<String>,
+    // Tried when rofi isn't on PATH
+    fallback: Option<Fallback>,
+    // Used instead of rofi or the fallback, for an entry's argument choices
+    backend: Option<Backend>,
+    // Text the filter starts with (--query), passed to -filter
+    query: Option<String>,
+    // Pass rofi only the flags rofi-keys can't work without
+    raw: bool,
+    // Additional arguments appended to the rofi invocation
+    extra_args: Vec<String>,
+    // Template replacing the built-in rofi command line for the menu itself
+    rofi_command: Option<String>,
+    // Re-run every time the menu opens to fill rofi's message bar
+    status_command: Option<String>,
+    // Key that shows a key reference in the message bar
+    hint_key: Option<String>,
+    // Key that fetches a source submenu's rows again
+    refresh_key: String,
+    // Set only when there are themes to cycle through
+    cycle_theme_key: Option<String>,
+    themes: Vec<String>,
+    // Index in `themes` of the theme shown instead of the style's, once cycled
+    cycled_theme: Cell<Option<usize>>,
+    // Config file a cycled theme is written back to, with save_theme
+    save_theme_to: Option<PathBuf>,
+    // Sources whose rows follow the entries, fetched again each time the menu opens
+    inline_sources: Vec<InlineSource>,
+    // Groups whose entries are hidden behind their header
+    collapsed: RefCell<BTreeSet<String>>,
+    // Save `collapsed` whenever a group is toggled
+    remember_collapsed: bool,
+    // Flow this menu shows, None for the main menu; launches are recorded under it
+    flow: Option<String>,
+    // Read the launch counts from the history each time the rows are written
+    show_usage_count: bool,
+    // Bind 1-9 to the first nine bound entries as well
+    number_keys: bool,
+    // Commands longer than this are shortened in the middle under show_commands
+    command_width: usize,
+    // Let rofi accept typed text, which is run as a command
+    allow_custom: bool,
+    // What typed commands are limited to, anchored at both ends
+    custom_pattern: Option<Regex>,
+}
+
+impl Menu {
+    fn new(style: MenuStyle) -> Self {
+        Menu {
+            style,
+            entries: Vec::new(),
+            key_index: HashMap::new(),
+            key_style: KeyStyle::default(),
+            key_position: KeyPosition::default(),
+            layout: Layout::default(),
+            grid_columns: DEFAULT_GRID_COLUMNS,
+            monitor: None,
+            placement: None,
+            fallback: None,
+            backend: None,
+            query: None,
+            raw: false,
+            extra_args: Vec::new(),
+            rofi_command: None,
+            status_command: None,
+            hint_key: None,
+            refresh_key: DEFAULT_REFRESH_KEY.to_string(),
+            cycle_theme_key: None,
+            themes: Vec::new(),
+            cycled_theme: Cell::new(None),
+            save_theme_to: None,
+            inline_sources: Vec::new(),
+            collapsed: RefCell::new(BTreeSet::new()),
+            remember_collapsed: false,
+            flow: None,
+            show_usage_count: false,
+            number_keys: false,
+            command_width: DEFAULT_COMMAND_WIDTH,
+            allow_custom: false,
+            custom_pattern: None,
+        }
+    }
+
+    // Build the main menu from a loaded config
+    fn from_config(config: &Config) -> io::Result<Self> {
+        Menu::build(config, MenuStyle::from_config(config), &config.entries)
+    }
+
+    // Build one of the config's named flow menus, styled like `parent`
+    // (or the main menu) unless the flow says otherwise
+    fn for_flow(config: &Config, name: &str, parent: Option<&MenuStyle>) -> io::Result<Self> {
+        let flow = config.flows.get(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Unknown flow '{}'", name))
+        })?;
+        let style = match parent {
+            Some(parent) => parent.for_flow(name, flow),
+            None => MenuStyle::from_config(config).for_flow(name, flow),
+        };
+        let mut menu = Menu::build(config, style, &flow.entries)?;
+        menu.flow = Some(name.to_string());
+        Ok(menu)
+    }
+
+    // Build a menu of `entries` using the config's global settings
+    fn build(config: &Config, style: MenuStyle, entries: &[MenuEntryConfig]) -> io::Result<Self> {
+        let mut menu = Menu::new(style);
+        if let Some(style) = &config.key_style {
+            menu.key_style = KeyStyle::parse(style)?;
+        }
+        if let Some(position) = &config.key_position {
+            menu.key_position = KeyPosition::parse(position)?;
+        }
+        if let Some(layout) = &config.layout {
+            menu.layout = Layout::parse(layout)?;
+        }
+        if let Some(columns) = config.grid_columns {
+            menu.grid_columns = columns.max(1);
+        }
+        // Grid cells hold one short line
+        if menu.layout == Layout::Grid {
+            menu.style.show_commands = false;
+        }
+        menu.monitor.clone_from(&config.monitor);
+        menu.placement = placement_theme(config)?;
+        if let Some(name) = &config.fallback_backend {
+            menu.fallback = Some(Fallback::parse(name)?);
+        }
+        menu.raw = config.raw_rofi;
+        menu.extra_args = kb_override_args(&kb_overrides(config));
+        menu.rofi_command.clone_from(&config.rofi_command);
+        menu.status_command.clone_from(&config.status_command);
+        menu.hint_key.clone_from(&config.hint_key);
+        if let Some(key) = &config.refresh_key {
+            menu.refresh_key.clone_from(key);
+        }
+        if !config.themes.is_empty() {
+            menu.cycle_theme_key.clone_from(&config.cycle_theme_key);
+            menu.themes = config.themes.iter().map(|theme| expand_path(theme)).collect();
+            if config.save_theme {
+                menu.save_theme_to.clone_from(&config.file);
+            }
+        }
+        if let Some(width) = config.command_width {
+            menu.command_width = width;
+        }
+        menu.allow_custom = config.allow_custom_command;
+        if let Some(pattern) = &config.custom_command_pattern {
+            menu.custom_pattern = Some(custom_command_regex(pattern)?);
+        }
+        if config.remember_collapsed {
+            menu.collapsed = RefCell::new(CollapsedGroups::load().groups);
+            menu.remember_collapsed = true;
+        }
+        menu.show_usage_count = config.show_usage_count;
+        menu.number_keys = config.number_keys;
+
+        // Add entries from config, each group gathered where it first appears
+        // (after those in group_order) and ungrouped entries above all groups
+        let mut groups: Vec<Option<&str>> = vec![None];
+        for name in &config.group_order {
+            let listed = Some(name.as_str());
+            if !groups.contains(&listed) && entries.iter().any(|entry| entry.group.as_deref() == listed) {
+                groups.push(listed);
+            }
+        }
+        for entry in entries {
+            if !groups.contains(&entry.group.as_deref()) {
+                groups.push(entry.group.as_deref());
+            }
+        }
+        let ordered = groups
+            .iter()
+            .flat_map(|group| entries.iter().filter(move |entry| entry.group.as_deref() == *group));
+        for entry in ordered.filter(|entry| entry.enabled).cloned() {
+            // Source entries without their tool are hidden or disabled
+            let source = match &entry.source_type {
+                Some(_) => {
+                    let source = Source::resolve(&entry)?;
+                    let policy = match &entry.missing {
+                        Some(policy) => MissingPolicy::parse(policy)?,
+                        None => MissingPolicy::default(),
+                    };
+                    if source.is_none() && policy == MissingPolicy::Hide {
+                        continue;
+                    }
+                    Some(source)
+                }
+                None => None,
+            };
+
+            if entry.inline {
+                menu.inline_sources.push(InlineSource {
+                    job: source.flatten().map(|source| source_job(&entry, source)),
+                    label: entry.label,
+                    options: entry.options,
+                    limit: entry.limit.unwrap_or(DEFAULT_SOURCE_LIMIT),
+                });
+                continue;
+            }
+
+            let Some(key_char) = entry.key.chars().next() else {
+                continue;
+            };
+            let added = menu.add_entry(key_char, entry);
+            if let Some(source) = source {
+                added.available = source.is_some();
+                added.activatable = source.is_some();
+                added.source = source;
+            }
+        }
+
+        // Look each program up once, however many entries use it
+        let mut lookups: HashMap<String, bool> = HashMap::new();
+        for entry in &mut menu.entries {
+            if entry.source.is_some() || !entry.options.check_path.unwrap_or(config.check_path) {
+                continue;
+            }
+            if let Some(program) = command_program(&entry.command) {
+                entry.available = *lookups
+                    .entry(program.clone())
+                    .or_insert_with(|| find_in_path(&program).is_some());
+                entry.activatable = entry.available || !config.disable_unavailable;
+            }
+        }
+
+        Ok(menu)
+    }
+
+    fn add_entry(&mut self, key: char, entry: MenuEntryConfig) -> &mut MenuEntry {
+        let shadowed = self.key_index.contains_key(&key);
+        self.key_index.entry(key).or_insert(self.entries.len());
+        self.entries.push(MenuEntry {
+            key,
+            label: entry.label,
+            command: entry.command,
+            command_template: entry.command_template,
+            args: entry.args,
+            options: entry.options,
+            source: None,
+            cache: entry.cache_seconds.map(|ttl| CachePolicy {
+                ttl,
+                stale_while_revalidate: entry.stale_while_revalidate,
+            }),
+            limit: entry.limit.unwrap_or(DEFAULT_SOURCE_LIMIT),
+            next: entry.next,
+            action: entry.action.as_deref().and_then(|action| MetaAction::parse(action).ok()),
+            available: true,
+            activatable: true,
+            shadowed,
+            inline: false,
+            item: None,
+            group: entry.group,
+            icon: entry.icon,
+            tags: entry.tags,
+        });
+        self.entries.last_mut().expect("entry was just added")
+    }
+
+    // Replace the rows of inline sources with fresh ones, running every source
+    // at once; they go after the entries so keyed entries keep their slots
+    fn fill_inline_sources(&mut self, refresh: bool) {
+        if self.inline_sources.is_empty() {
+            return;
+        }
+        self.entries.retain(|entry| !entry.inline);
+
+        let jobs: Vec<SourceJob> = self.inline_sources.iter().filter_map(|inline| inline.job.clone()).collect();
+        // The first menu's sources may already have been started by load_config
+        let results = sources::take_prefetched(&jobs).unwrap_or_else(|| sources::run_concurrently(jobs, refresh));
+        let mut results = results.into_iter();
+        for inline in &self.inline_sources {
+            let result = match inline.job {
+                Some(_) => results.next().expect("one result per job"),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "not installed")),
+            };
+            // A failing source gets one disabled row instead of holding up the menu,
+            // and a truncated one a disabled row saying how much is missing
+            let rows: Vec<(String, Option<SourceItem>)> = match result {
+                Ok(mut items) => {
+                    let dropped = limit_items(&inline.label, &mut items, inline.limit);
+                    let mut rows: Vec<_> = items.into_iter().map(|item| (item.display.clone(), Some(item))).collect();
+                    if dropped > 0 {
+                        rows.push((more_rows_label(dropped), None));
+                    }
+                    rows
+                }
+                Err(e) => vec![(format!("{}: {}", inline.label, e), None)],
+            };
+            for (label, item) in rows {
+                self.entries.push(MenuEntry {
+                    key: ' ',
+                    label,
+                    command: String::new(),
+                    command_template: None,
+                    args: Vec::new(),
+                    options: inline.options.clone(),
+                    source: None,
+                    cache: None,
+                    limit: 0,
+                    next: None,
+                    action: None,
+                    available: item.is_some(),
+                    activatable: item.is_some(),
+                    shadowed: false,
+                    inline: true,
+                    item,
+                    group: None,
+                    icon: None,
+                    tags: Vec::new(),
+                });
+            }
+        }
+    }
+
+    // Entries in display order with a header above each group;
+    // entries of collapsed groups are left out
+    fn rows(&self) -> Vec<Row<'_>> {
+        let collapsed = self.collapsed.borrow();
+        let mut rows = Vec::with_capacity(self.entries.len());
+        let mut current: Option<&str> = None;
+        for entry in &self.entries {
+            let group = entry.group.as_deref();
+            if let Some(name) = group.filter(|_| group != current) {
+                rows.push(Row::Header(name, collapsed.contains(name)));
+            }
+            current = group;
+            if !group.is_some_and(|name| collapsed.contains(name)) {
+                rows.push(Row::Entry(entry));
+            }
+        }
+        rows
+    }
+
+    fn generate_rofi_input(&self, rows: &[Row<'_>]) -> String {
+        // Rows are roughly "[k] label"; one allocation for the common case
+        let estimate: usize = self.entries.iter().map(|entry| entry.label.len() + 8).sum();
+        let mut input = String::with_capacity(estimate);
+        let separator = if self.style.show_commands { TWO_LINE_SEPARATOR } else { '\n' };
+        // Loaded for every showing, so a repeatable entry's count goes up as it's used
+        let counts = if self.show_usage_count {
+            History::load().counts(self.flow.as_deref())
+        } else {
+            HashMap::new()
+        };
+        let label_width = match self.key_position {
+            KeyPosition::Column => rows
+                .iter()
+                .filter_map(|row| match row {
+                    Row::Entry(entry) if !entry.inline => Some(entry.label.chars().count()),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        };
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 {
+                input.push(separator);
+            }
+            match row {
+                Row::Entry(entry) => {
+                    self.write_row(&mut input, entry, counts.get(&entry.label).copied(), label_width)
+                }
+                Row::Header(name, collapsed) => self.write_header(&mut input, name, *collapsed),
+                Row::Filler => {}
+            }
+        }
+        input
+    }
+
+    fn write_header(&self, out: &mut String, name: &str, collapsed: bool) {
+        let marker = if collapsed { "▶" } else { "▼" };
+        // Writing to a String can't fail
+        let _ = if self.raw {
+            write!(out, "{} {}", marker, name)
+        } else {
+            write!(out, "<b>{} {}</b>", marker, escape_markup(name))
+        };
+    }
+
+    // With number_keys, the digit (1-9) each of the first nine bound entries
+    // gets by position, by entry index. A digit that is an entry's own key, the
+    // hint key or the theme key stays with it and that position goes without
+    fn number_keys(&self, slots: &SlotPlan) -> HashMap<usize, char> {
+        if !self.number_keys {
+            return HashMap::new();
+        }
+        let menu_keys = [&self.hint_key, &self.cycle_theme_key];
+        let taken = |digit: char| {
+            slots.entries.iter().any(|&(_, index)| self.entries[index].key == digit)
+                || menu_keys.iter().any(|key| key.as_deref() == Some(digit.to_string().as_str()))
+        };
+        slots
+            .entries
+            .iter()
+            .zip('1'..='9')
+            .filter(|&(_, digit)| !taken(digit))
+            .map(|(&(_, index), digit)| (index, digit))
+            .collect()
+    }
+
+    // Move on to the next of `themes`, continuing after the style's own theme
+    // when it is one of them
+    fn cycle_theme(&self) {
+        let current = self.cycled_theme.get().or_else(|| {
+            let own = self.style.theme.as_ref()?;
+            self.themes.iter().position(|theme| theme == own)
+        });
+        let next = current.map_or(0, |index| (index + 1) % self.themes.len());
+        self.cycled_theme.set(Some(next));
+        if let Some(path) = &self.save_theme_to {
+            if let Err(e) = save_theme(path, self.flow.as_deref(), next) {
+                eprintln!("Could not save the theme: {}", e);
+            }
+        }
+    }
+
+    // Show or hide a group's entries, saving the change if asked to
+    fn toggle_group(&self, name: &str) {
+        let mut collapsed = self.collapsed.borrow_mut();
+        if !collapsed.remove(name) {
+            collapsed.insert(name.to_string());
+        }
+        if self.remember_collapsed {
+            let mut state = CollapsedGroups::load();
+            if collapsed.contains(name) {
+                state.groups.insert(name.to_string());
+            } else {
+                state.groups.remove(name);
+            }
+            if let Err(e) = state.save() {
+                eprintln!("Could not save collapsed groups: {}", e);
+            }
+        }
+    }
+
+    // Append an entry's row to `out`, the hot path for large menus, so the
+    // common "[k] label" case doesn't allocate a String per row; `count` is
+    // how often the entry was launched, with show_usage_count, and
+    // `label_width` the widest label for the key column
+    fn write_row(&self, out: &mut String, entry: &MenuEntry, count: Option<usize>, label_width: usize) {
+        // Rows are plain text without -markup-rows
+        let dimmed = !entry.available && !self.raw;
+        if dimmed {
+            out.push_str("<span alpha=\"50%\">");
+        }
+        let write_key = |out: &mut String| {
+            if self.key_position == KeyPosition::Column && !self.raw {
+                // Padding only lines the keys up in a monospace font
+                out.push_str("<span font_family=\"monospace\">");
+                self.key_position.write(out, entry.key, &entry.label, label_width);
+                out.push_str("</span>");
+            } else {
+                self.key_position.write(out, entry.key, &entry.label, label_width);
+            }
+        };
+        match self.key_style {
+            // Source rows carry arbitrary text such as clipboard contents
+            _ if entry.inline && !self.raw => out.push_str(&escape_markup(&entry.label)),
+            _ if entry.inline => out.push_str(&entry.label),
+            // show_key_hints: false wins over key_style and key_position
+            _ if self.style.hide_keys => out.push_str(&entry.label),
+            // Grid cells are small; the icon stands in for the key
+            _ if self.layout == Layout::Grid => out.push_str(&truncate_middle(&entry.label, GRID_LABEL_WIDTH)),
+            KeyStyle::Mnemonic if !self.raw => match mnemonic_label(&entry.label, entry.key) {
+                Some(label) => out.push_str(&label),
+                None => write_key(out),
+            },
+            KeyStyle::Mnemonic | KeyStyle::Prefix => write_key(out),
+        }
+        if let Some(count) = count.filter(|_| !entry.inline && self.layout != Layout::Grid) {
+            let _ = if self.raw {
+                write!(out, " ·{}", count)
+            } else {
+                write!(out, " <span alpha=\"60%\">·{}</span>", count)
+            };
+        }
+        if self.style.show_commands && !entry.inline {
+            self.write_command_line(out, entry);
+        }
+        if dimmed {
+            out.push_str("</span>");
+        }
+        // rofi's row options follow a NUL; entries without an icon get their key as one
+        if self.layout == Layout::Grid && !entry.inline {
+            let icon = match &entry.icon {
+                Some(icon) => expand_path(icon),
+                None => icons::letter_icon(entry.key),
+            };
+            let _ = write!(out, "\0icon\x1f{}", icon);
+        }
+    }
+
+    // Add the entry's command below its row, dimmed and shortened to command_width
+    fn write_command_line(&self, out: &mut String, entry: &MenuEntry) {
+        let action = entry.action_text().replace(['\n', TWO_LINE_SEPARATOR], " ");
+        let action = truncate_middle(&action, self.command_width);
+        let _ = if self.raw {
+            write!(out, "\n{}", action)
+        } else {
+            write!(out, "\n<span alpha=\"60%\" size=\"small\">{}</span>", escape_markup(&action))
+        };
+    }
+
+    fn display_with_rofi(&self) -> Result<Option<Choice<'_>>, Error> {
+        // The hint key switches between the menu and the same menu with a key reference
+        let mut show_hints = false;
+        loop {
+            match self.show(show_hints)? {
+                Shown::Picked(entry) => return Ok(Some(Choice::Entry(entry))),
+                Shown::Foreground(entry) => return Ok(Some(Choice::Foreground(entry))),
+                Shown::Custom(command) => return Ok(Some(Choice::Custom(command))),
+                Shown::HintKey => show_hints = !show_hints,
+                Shown::CycleTheme => self.cycle_theme(),
+                Shown::Toggle(group) => self.toggle_group(group),
+                // Escape leaves the key reference before it leaves the menu
+                Shown::Cancelled if show_hints => show_hints = false,
+                Shown::Cancelled => return Ok(None),
+            }
+        }
+    }
+
+    fn show(&self, show_hints: bool) -> Result<Shown<'_>, Error> {
+        // Prepare key bindings for each menu entry
+        let mut kb_args = Vec::new();
+        
+        // For each entry, create a custom keybinding; rofi numbers them from 1
+        let slots = plan_slots(&self.entries, self.hint_key.is_some(), self.cycle_theme_key.is_some());
+        let numbers = self.number_keys(&slots);
+        for &(slot, index) in &slots.entries {
+            kb_args.push(format!("-kb-custom-{}", slot + 1));
+            // A number key shares its entry's slot, so it costs none of rofi's 19
+            let binding = key_binding(self.entries[index].key);
+            match numbers.get(&index) {
+                Some(digit) => kb_args.push(format!("{},{}", binding, digit)),
+                None => kb_args.push(binding),
+            }
+        }
+        if let (Some(slot), Some(hint_key)) = (slots.hint, &self.hint_key) {
+            kb_args.push(format!("-kb-custom-{}", slot + 1));
+            kb_args.push(named_key_binding(hint_key));
+        }
+        if let (Some(slot), Some(theme_key)) = (slots.theme, &self.cycle_theme_key) {
+            kb_args.push(format!("-kb-custom-{}", slot + 1));
+            kb_args.push(named_key_binding(theme_key));
+        }
+        for &(slot, index) in &slots.foreground {
+            kb_args.push(format!("-kb-custom-{}", slot + 1));
+            kb_args.push(format!("Alt+{}", key_binding(self.entries[index].key)));
+        }
+        
+        // Generate menu items; keys stay bound while their group is collapsed
+        let mut rows = self.rows();
+        if let Some(fallback) = self.dialog_fallback(None)? {
+            return self.show_with_fallback(fallback, &rows);
+        }
+        // Columns come from rofi's listview filling one column after another,
+        // so each group is padded to the tallest; the user's theme_str goes last to win
+        let layout = match self.layout {
+            Layout::Columns => {
+                let (columns, lines) = pad_columns(&mut rows);
+                Some(format!(
+                    "listview {{ columns: {}; lines: {}; flow: vertical; fixed-columns: true; }}",
+                    columns, lines
+                ))
+            }
+            Layout::Grid => Some(format!(
+                "listview {{ columns: {}; lines: {}; flow: horizontal; fixed-columns: true; }} \
+                 element {{ orientation: vertical; }} element-icon {{ size: 48px; }} \
+                 element-text {{ horizontal-align: 0.5; }}",
+                self.grid_columns,
+                rows.len().div_ceil(self.grid_columns).max(1)
+            )),
+            Layout::List => None,
+        };
+        let theme_str = self.theme_str_with(layout);
+        profile::mark("menu rows");
+        let menu_input = self.generate_rofi_input(&rows);
+        profile::mark("rofi input");
+        
+        // Fill in placeholders in the prompt
+        let prompt = expand_title(&self.style.prompt, displayed_entries(&rows));
+
+        // A theme picked with cycle_theme_key replaces the style's own
+        let cycled_style = self.cycled_theme.get().map(|index| MenuStyle {
+            theme: Some(self.themes[index].clone()),
+            ..self.style.clone()
+        });
+        let style = cycled_style.as_ref().unwrap_or(&self.style);
+
+        // Show the fixed message, the status line if there is one right now,
+        // the key reference if asked for and the theme being previewed
+        let status = self.status_command.as_deref().and_then(status_line);
+        let hints = show_hints.then(|| self.key_hints());
+        let theme_name = self.cycled_theme.get().map(|index| format!("Theme: {}", escape_markup(&self.themes[index])));
+        let parts: Vec<&str> = [
+            self.style.message.as_deref(),
+            status.as_deref(),
+            hints.as_deref(),
+            theme_name.as_deref(),
+        ]
+            .into_iter()
+            .flatten()
+            .collect();
+        let message = (!parts.is_empty()).then(|| parts.join("\n"));
+
+        // Headers and inline source rows have no key, and rows have no visible
+        // one with show_key_hints off; Enter picks them by index
+        let pick_by_index = self.style.hide_keys
+            || rows
+                .iter()
+                .any(|row| matches!(row, Row::Header(..)) || matches!(row, Row::Entry(entry) if entry.inline));
+        let rofi_args = self.rofi_args(&prompt, style, theme_str.as_deref(), message.as_deref(), &kb_args, pick_by_index);
+        
+        // Get rofi's output and exit status; rofi_command replaces everything above
+        let output = match &self.rofi_command {
+            Some(template) => {
+                let theme = style.theme.as_deref();
+                let argv = rofi_command_line(template, &prompt, theme, &kb_args, &self.extra_args);
+                let (program, args) = argv.split_first().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "rofi_command is empty")
+                })?;
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                run_menu_program(program, &args, |stdin| stdin.write_all(menu_input.as_bytes()))?
+            }
+            None => {
+                let rofi_args: Vec<&str> = rofi_args.iter().map(String::as_str).collect();
+                run_rofi(&rofi_args, &menu_input)?
+            }
+        };
+        let exit_code = output.status.code().unwrap_or(1);
+        
+        // Check for direct key activation (custom-N exit codes)
+        if let Some(index) = custom_slot(exit_code) {
+            if slots.hint == Some(index) {
+                return Ok(Shown::HintKey);
+            }
+            if slots.theme == Some(index) {
+                return Ok(Shown::CycleTheme);
+            }
+            if let Some(&(_, entry)) = slots.foreground.iter().find(|(slot, _)| *slot == index) {
+                return Ok(Shown::Foreground(&self.entries[entry]));
+            }
+            
+            if let Some(entry) = self.entries.get(index) {
+                return Ok(Shown::Picked(entry));
+            }
+        }
+        
+        // Enter on an empty filter or on nothing can leave stdout empty or
+        // whitespace; that is a cancel like any other unrecognized answer
+        if exit_code == 0 && (pick_by_index || self.allow_custom) {
+            let stdout = str::from_utf8(&output.stdout).unwrap_or_default();
+            let line = stdout.lines().next().unwrap_or_default();
+            let (index, text) = line.split_once(' ').unwrap_or((line, ""));
+            match index.trim().parse::<isize>() {
+                Ok(-1) if self.allow_custom && !text.trim().is_empty() => {
+                    return Ok(Shown::Custom(text.trim().to_string()));
+                }
+                Ok(index) => match usize::try_from(index).ok().and_then(|index| rows.get(index)) {
+                    Some(Row::Header(name, _)) => return Ok(Shown::Toggle(name)),
+                    Some(Row::Entry(entry)) if entry.inline && entry.item.is_some() => {
+                        return Ok(Shown::Picked(entry));
+                    }
+                    // Typing filters the rows, so Enter on a match runs that entry
+                    Some(Row::Entry(entry))
+                        if (self.allow_custom || self.style.hide_keys) && entry.activatable && !entry.inline =>
+                    {
+                        return Ok(Shown::Picked(entry));
+                    }
+                    _ => {}
+                },
+                Err(_) => {}
+            }
+        }
+
+        // If no direct key was detected, the menu was cancelled
+        Ok(Shown::Cancelled)
+    }
+
+    // The rofi command line for showing the menu once. Nothing here runs a
+    // program, so show() works out the status line and rows beforehand
+    fn rofi_args(
+        &self,
+        prompt: &str,
+        style: &MenuStyle,
+        theme_str: Option<&str>,
+        message: Option<&str>,
+        kb_args: &[String],
+        pick_by_index: bool,
+    ) -> Vec<String> {
+        // Basic Rofi arguments
+        let mut rofi_args = vec![
+            "-dmenu", 
+            "-p", 
+            prompt,
+            "-no-fork",  // Added to prevent forking which may trigger systemd
+        ];
+        if !self.allow_custom {
+            rofi_args.push("-no-custom"); // Disable manual entry
+        }
+
+        // Behavior and looks rofi-keys picks itself, left to the user's rofi config in raw mode
+        if !self.raw {
+            rofi_args.extend([
+                "-i",
+                "-markup-rows",
+                // Regex matching avoids filtering; a plain option rather than -theme-str
+                // leaves the user's own theme (used when `theme` is unset) untouched
+                "-matching", "regex",
+            ]);
+        }
+        
+        // Add theme if specified
+        rofi_args.extend(style.rofi_args_with(theme_str));
+        if let Some(monitor) = &self.monitor {
+            rofi_args.push("-monitor");
+            rofi_args.push(monitor);
+        }
+        if let Some(query) = &self.query {
+            rofi_args.push("-filter");
+            rofi_args.push(query);
+        }
+        if self.layout == Layout::Grid {
+            rofi_args.push("-show-icons");
+        }
+
+        // Two-line rows need a separator other than newline; headers and
+        // inline rows get the same height with an empty second line
+        let separator = TWO_LINE_SEPARATOR.to_string();
+        if self.style.show_commands {
+            rofi_args.extend(["-eh", "2", "-sep", &separator]);
+        }
+
+        if let Some(message) = message {
+            rofi_args.push("-mesg");
+            rofi_args.push(message);
+        }
+        
+        // Add all the key binding arguments
+        for arg in kb_args {
+            rofi_args.push(arg);
+        }
+
+        // Typed text comes back after the index, which rofi gives as -1 for it
+        if self.allow_custom {
+            rofi_args.extend(["-format", "i s"]);
+        } else if pick_by_index {
+            rofi_args.extend(["-format", "i"]);
+        }
+
+        // Extra arguments go last so they can override the defaults above
+        for arg in &self.extra_args {
+            rofi_args.push(arg);
+        }
+
+        rofi_args.into_iter().map(str::to_string).collect()
+    }
+
+    // The fallback backend, when one is set and rofi can't be found
+    fn fallback_in_use(&self) -> Option<Fallback> {
+        self.fallback.filter(|_| find_in_path(&rofi_program()).is_none())
+    }
+
+    // The fallback a dialog is shown with, None meaning rofi: `backend` when
+    // an entry asked for one, else this menu's own
+    fn dialog_fallback(&self, backend: Option<Backend>) -> Result<Option<Fallback>, Error> {
+        match backend.or(self.backend) {
+            Some(backend) => backend.require(),
+            None => Ok(self.fallback_in_use()),
+        }
+    }
+
+    // The menu as plain lines for dmenu or fzf, which can't bind entry keys:
+    // rows are picked by selecting them and the key is only shown
+    fn show_with_fallback<'a>(&'a self, fallback: Fallback, rows: &[Row<'a>]) -> Result<Shown<'a>, Error> {
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|row| match row {
+                Row::Entry(entry) if entry.inline => entry.label.clone(),
+                Row::Entry(entry) => format!("{}  {}", entry.key, entry.label),
+                Row::Header(name, collapsed) => format!("{} {}", if *collapsed { "▶" } else { "▼" }, name),
+                Row::Filler => String::new(),
+            })
+            .collect();
+        let prompt = expand_title(&self.style.prompt, displayed_entries(rows));
+        let Some(picked) = fallback.pick(&prompt, &lines)? else {
+            return Ok(Shown::Cancelled);
+        };
+        match lines.iter().position(|line| *line == picked).map(|index| &rows[index]) {
+            Some(Row::Header(name, _)) => Ok(Shown::Toggle(name)),
+            Some(Row::Entry(entry)) if entry.inline && entry.item.is_none() => Ok(Shown::Cancelled),
+            Some(Row::Entry(entry)) if entry.inline || entry.activatable => Ok(Shown::Picked(entry)),
+            Some(_) => Ok(Shown::Cancelled),
+            None if self.allow_custom => Ok(Shown::Custom(picked.trim().to_string())),
+            None => Ok(Shown::Cancelled),
+        }
+    }
+
+    // One "key  label → command" line per entry, for the message bar
+    fn key_hints(&self) -> String {
+        let mut lines: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|entry| !entry.inline)
+            .map(|entry| {
+                let action = entry.action_text();
+                format!(
+                    "<b>{}</b>  {}  <i>{}</i>",
+                    escape_markup(&entry.key.to_string()),
+                    escape_markup(&entry.label),
+                    escape_markup(&action)
+                )
+            })
+            .collect();
+        if let Some(hint_key) = &self.hint_key {
+            lines.push(format!("Press {} or Escape to hide this", escape_markup(hint_key)));
+        }
+        lines.join("\n")
+    }
+
+    // Yes/no prompt; anything but an explicit "Yes" counts as no
+    fn confirm(&self, action: &str, backend: Option<Backend>) -> Result<bool, Error> {
+        let rows = [i18n::tr("Yes").to_string(), i18n::tr("No").to_string()];
+        let choice = self.choose_with_rofi(&format!("{}?", action), &rows, backend)?;
+        Ok(choice == Some(0))
+    }
+
+    // The theme_str passed to rofi: `layout` rules, then the placement, then
+    // the user's theme_str last so it can override both
+    fn theme_str_with(&self, layout: Option<String>) -> Option<String> {
+        let own = self.style.theme_str.as_deref();
+        let parts: Vec<&str> = [layout.as_deref(), self.placement.as_deref(), own]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+
+    // Let the user pick one of `rows` with Enter, sharing this menu's look;
+    // `backend` is the entry's own menu program, if it has one
+    fn choose_with_rofi(&self, prompt: &str, rows: &[String], backend: Option<Backend>) -> Result<Option<usize>, Error> {
+        match self.choose_or_key(prompt, rows, None, backend)? {
+            Picked::Row(index) => Ok(Some(index)),
+            Picked::Key | Picked::Cancelled => Ok(None),
+        }
+    }
+
+    // choose_with_rofi with `key` bound as well; the fallback backends can't bind it
+    fn choose_or_key(
+        &self,
+        prompt: &str,
+        rows: &[String],
+        key: Option<&str>,
+        backend: Option<Backend>,
+    ) -> Result<Picked, Error> {
+        if let Some(fallback) = self.dialog_fallback(backend)? {
+            let picked = fallback.pick(prompt, rows)?;
+            return Ok(picked
+                .and_then(|picked| rows.iter().position(|row| *row == picked))
+                .map_or(Picked::Cancelled, Picked::Row));
+        }
+        let mut rofi_args = vec!["-dmenu", "-p", prompt, "-no-fork", "-no-custom", "-format", "i"];
+        if !self.raw {
+            rofi_args.push("-i");
+        }
+        let theme_str = self.theme_str_with(None);
+        rofi_args.extend(self.style.rofi_args_with(theme_str.as_deref()));
+        if let Some(monitor) = &self.monitor {
+            rofi_args.push("-monitor");
+            rofi_args.push(monitor);
+        }
+        let binding = key.map(named_key_binding);
+        if let Some(binding) = &binding {
+            rofi_args.extend(["-kb-custom-1", binding]);
+        }
+        for arg in &self.extra_args {
+            rofi_args.push(arg);
+        }
+
+        let output = run_rofi_with(&rofi_args, |stdin| {
+            for (index, row) in rows.iter().enumerate() {
+                if index > 0 {
+                    stdin.write_all(b"\n")?;
+                }
+                stdin.write_all(row.as_bytes())?;
+            }
+            Ok(())
+        })?;
+        match output.status.code() {
+            Some(0) => {}
+            Some(10) if binding.is_some() => return Ok(Picked::Key),
+            _ => return Ok(Picked::Cancelled),
+        }
+        Ok(str::from_utf8(&output.stdout)
+            .ok()
+            .and_then(|stdout| stdout.trim().parse::<usize>().ok())
+            .filter(|&index| index < rows.len())
+            .map_or(Picked::Cancelled, Picked::Row))
+    }
+}
+
+// How a follow-up prompt with an extra key was answered
+enum Picked {
+    Row(usize),
+    // The extra key, e.g. the refresh key of a source submenu
+    Key,
+    Cancelled,
+}
+
+// Trimmed output of a status command; failures and empty output mean no status
+fn status_line(command: &str) -> Option<String> {
+    let output = envfile::command("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let status = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!status.is_empty()).then_some(status)
+}
+
+// rofi_command split into words, each placeholder filled in within its word;
+// {kb_args} and {extra_args} standing alone become as many words as they hold
+fn rofi_command_line(
+    template: &str,
+    title: &str,
+    theme: Option<&str>,
+    kb_args: &[String],
+    extra_args: &[String],
+) -> Vec<String> {
+    let mut argv = Vec::new();
+    for word in split_words(template) {
+        match word.as_str() {
+            "{kb_args}" => argv.extend(kb_args.iter().cloned()),
+            "{extra_args}" => argv.extend(extra_args.iter().cloned()),
+            _ => argv.push(word.replace("{title}", title).replace("{theme}", theme.unwrap_or_default())),
+        }
+    }
+    argv
+}
+
+// Run rofi with the given arguments and menu rows, waiting for it to exit
+fn run_rofi(args: &[&str], input: &str) -> Result<std::process::Output, Error> {
+    run_rofi_with(args, |stdin| stdin.write_all(input.as_bytes()))
+}
+
+// run_rofi with the input written by `write_input` while rofi reads it, so
+// long row lists aren't joined into one string first
+fn run_rofi_with(
+    args: &[&str],
+    write_input: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<std::process::Output, Error> {
+    run_menu_program(&rofi_program(), args, write_input)
+}
+
+// The rofi to run: $ROFI_KEYS_ROFI if set, e.g. a wrapper script or a stand-in
+// for scripted runs, otherwise rofi from PATH
+fn rofi_program() -> String {
+    env::var("ROFI_KEYS_ROFI")
+        .ok()
+        .filter(|program| !program.is_empty())
+        .unwrap_or_else(|| "rofi".to_string())
+}
+
+// run_rofi_with for `program`, which is rofi unless rofi_command names another
+fn run_menu_program(
+    program: &str,
+    args: &[&str],
+    write_input: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> Result<std::process::Output, Error> {
+    use std::os::unix::process::ExitStatusExt;
+
+    // on_close runs when this goes out of scope, whichever way rofi ends
+    let _window = hooks::open();
+
+    // Prepare and execute rofi command
+    let mut child = envfile::command(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => Error::BackendMissing {
+                backend: program.to_string(),
+            },
+            _ => Error::Io(e),
+        })?;
+
+    // Make sure a Ctrl-C doesn't leave an orphaned rofi window behind
+    let _guard = RofiGuard::new(child.id());
+
+    // Write menu items to rofi's stdin
+    if let Some(stdin) = child.stdin.take() {
+        let mut stdin = io::BufWriter::new(stdin);
+        match write_input(&mut stdin).and_then(|()| stdin.flush()) {
+            // rofi closed its end early (e.g. exited before reading every row);
+            // whatever it printed before that is still a valid answer
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+            Ok(()) => {}
+        }
+        // Close stdin so rofi sees the end of the rows before we wait on it
+        drop(stdin);
+    }
+    profile::finish("spawn rofi");
+
+    // 0 is a pick, 1 a cancel and 10-28 the custom keys; anything else is
+    // rofi giving up, e.g. over a theme it can't parse
+    let output = child.wait_with_output()?;
+    // Killed from outside (e.g. the output it was on went away); whatever was
+    // printed so far may be half a selection, so it is dropped and the menu
+    // counts as dismissed
+    if let Some(signal) = output.status.signal() {
+        output::warn(format!("{} was killed by signal {}", program, signal));
+        return Ok(std::process::Output {
+            status: std::process::ExitStatus::from_raw(1 << 8),
+            stdout: Vec::new(),
+            stderr: output.stderr,
+        });
+    }
+    match output.status.code() {
+        Some(0 | 1) => Ok(output),
+        Some(code) if (10..10 + ROFI_CUSTOM_KEYS as i32).contains(&code) => Ok(output),
+        code => Err(Error::BackendFailed {
+            backend: program.to_string(),
+            code,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }),
+    }
+}
+
+// Underline the first occurrence of the key in the label, if it has one
+fn mnemonic_label(label: &str, key: char) -> Option<String> {
+    let (start, found) = label
+        .char_indices()
+        .find(|(_, c)| c.to_lowercase().eq(key.to_lowercase()))?;
+    let end = start + found.len_utf8();
+    Some(format!(
+        "{}<u>{}</u>{}",
+        escape_markup(&label[..start]),
+        escape_markup(&label[start..end]),
+        escape_markup(&label[end..])
+    ))
+}
+
+// Escape text for inclusion in Pango markup
+fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Keysym names rofi expects for symbol keys; letters and digits bind as themselves
+const KEYSYMS: &[(char, &str)] = &[
+    ('!', "exclam"),
+    ('"', "quotedbl"),
+    ('#', "numbersign"),
+    ('$', "dollar"),
+    ('%', "percent"),
+    ('&', "ampersand"),
+    ('\'', "apostrophe"),
+    ('(', "parenleft"),
+    (')', "parenright"),
+    ('*', "asterisk"),
+    ('+', "plus"),
+    (',', "comma"),
+    ('-', "minus"),
+    ('.', "period"),
+    ('/', "slash"),
+    (':', "colon"),
+    (';', "semicolon"),
+    ('<', "less"),
+    ('=', "equal"),
+    ('>', "greater"),
+    ('?', "question"),
+    ('@', "at"),
+    ('[', "bracketleft"),
+    ('\\', "backslash"),
+    (']', "bracketright"),
+    ('^', "asciicircum"),
+    ('_', "underscore"),
+    ('`', "grave"),
+    ('{', "braceleft"),
+    ('|', "bar"),
+    ('}', "braceright"),
+    ('~', "asciitilde"),
+    (' ', "space"),
+];
+
+// What to pass rofi to bind `key`
+fn key_binding(key: char) -> String {
+    KEYSYMS
+        .iter()
+        .find(|(symbol, _)| *symbol == key)
+        .map_or_else(|| key.to_string(), |(_, name)| name.to_string())
+}
+
+// A configured key such as hint_key as rofi binds it: a single character
+// like an entry key, anything longer (e.g. "Control+h" or "F5") as written
+fn named_key_binding(key: &str) -> String {
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(key), None) => key_binding(key),
+        _ => key.to_string(),
+    }
+}
+
+// kb_preset with rofi_kb_overrides on top
+fn kb_overrides(config: &Config) -> BTreeMap<String, String> {
+    kb::with_preset(config.kb_preset.as_deref(), &config.rofi_kb_overrides)
+}
+
+// Turn rofi_kb_overrides into -kb-* flags; names may omit the kb- prefix
+fn kb_override_args(overrides: &BTreeMap<String, String>) -> Vec<String> {
+    overrides
+        .iter()
+        .flat_map(|(name, binding)| {
+            let name = name.trim_start_matches('-');
+            let name = name.strip_prefix("kb-").unwrap_or(name);
+            [format!("-kb-{}", name), binding.clone()]
+        })
+        .collect()
+}
+
+// The program a shell command runs, skipping leading VAR=value assignments
+// and looking inside `sh -c '...'` wrappers; best effort, without a real parser
+fn command_program(command: &str) -> Option<String> {
+    let words = split_words(command);
+    let mut words = words.iter().skip_while(|word| is_env_assignment(word));
+    let first = words.next()?;
+    // "foo;bar" or "foo|bar" still runs foo first
+    let program = first
+        .split([';', '|', '&', '<', '>', '(', ')'])
+        .next()
+        .filter(|program| !program.is_empty())?;
+
+    if matches!(program, "sh" | "bash" | "dash" | "zsh") {
+        if let (Some(flag), Some(script)) = (words.next(), words.next()) {
+            if flag == "-c" {
+                return command_program(script);
+            }
+        }
+    }
+    Some(program.to_string())
+}
+
+// Shell builtins and keywords that never show up on PATH
+const SHELL_BUILTINS: &[&str] = &[
+    ".", "alias", "cd", "command", "eval", "exec", "exit", "export", "for", "if", "read", "set",
+    "source", "trap", "unset", "wait", "while", "{",
+];
+
+// (label, program) for each entry whose program can't be found on PATH
+fn missing_commands(config: &Config) -> Vec<(String, String)> {
+    let mut lookups: HashMap<String, bool> = HashMap::new();
+    let mut missing = Vec::new();
+    let all_entries = config
+        .entries
+        .iter()
+        .chain(config.flows.values().flat_map(|flow| &flow.entries));
+    for entry in all_entries {
+        let command = entry.command_template.as_deref().unwrap_or(&entry.command);
+        let Some(program) = checked_program(command) else {
+            continue;
+        };
+        let found = *lookups
+            .entry(program.clone())
+            .or_insert_with(|| find_in_path(&program).is_some());
+        if !found {
+            missing.push((entry.label.clone(), program));
+        }
+    }
+    missing
+}
+
+// The program of a command worth looking up on PATH, if any
+fn checked_program(command: &str) -> Option<String> {
+    command_program(command).filter(|program| !SHELL_BUILTINS.contains(&program.as_str()))
+}
+
+// Whether rofi can find a theme given by path or by name
+fn theme_exists(theme: &str) -> bool {
+    if theme.contains('/') {
+        return Path::new(theme).is_file();
+    }
+    // Bare names are looked up in rofi's theme directories, with or without .rasi
+    let data_home = env::var("XDG_DATA_HOME")
+        .ok()
+        .or_else(|| env::var("HOME").ok().map(|home| format!("{}/.local/share", home)));
+    let config_home = env::var("XDG_CONFIG_HOME")
+        .ok()
+        .or_else(|| env::var("HOME").ok().map(|home| format!("{}/.config", home)));
+    let dirs = [
+        config_home.map(|dir| format!("{}/rofi/themes", dir)),
+        data_home.map(|dir| format!("{}/rofi/themes", dir)),
+        Some("/usr/share/rofi/themes".to_string()),
+        Some("/usr/local/share/rofi/themes".to_string()),
+    ];
+    dirs.iter().flatten().any(|dir| {
+        let path = Path::new(dir).join(theme);
+        path.is_file() || path.with_extension("rasi").is_file()
+    })
+}
+
+// Print a warning per entry whose program is missing; these can be shell
+// functions or aliases, so they never fail the config
+fn warn_missing_commands(config: &Config) {
+    for (label, program) in missing_commands(config) {
+        output::warn(format!("entry '{}': '{}' not found on PATH", label, program));
+    }
+}
+
+// True for shell words like FOO=bar
+fn is_env_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+// Locate an executable the way the shell would
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let is_executable = |path: &PathBuf| {
+        fs::metadata(path)
+            .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    };
+
+    if program.contains('/') {
+        let path = PathBuf::from(expand_path(program));
+        return is_executable(&path).then_some(path);
+    }
+
+    envfile::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(program))
+            .find(is_executable)
+    })
+}
+
+// Fill in launch-time placeholders such as {timestamp} in a command
+fn expand_placeholders(command: &str) -> String {
+    let mut command = if command.contains("{timestamp}") {
+        command.replace("{timestamp}", &local_timestamp())
+    } else {
+        command.to_string()
+    };
+    if command.contains("{window_") {
+        command = expand_window_placeholders(&command);
+    }
+    if command.contains("{clipboard}") {
+        command = command.replace("{clipboard}", &shell_quote(&clipboard_text()));
+    }
+    command
+}
+
+// The clipboard's text, from wl-paste on Wayland and xclip elsewhere; empty
+// when it can't be read
+fn clipboard_text() -> String {
+    let reader: &[&str] = if env::var_os("WAYLAND_DISPLAY").is_some() {
+        &["wl-paste", "--no-newline"]
+    } else {
+        &["xclip", "-o", "-selection", "clipboard"]
+    };
+    envfile::command(reader[0])
+        .args(&reader[1..])
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default()
+}
+
+// {window_id}, {window_title}, {window_class}, {window_pid}, {window_workspace}
+// and {window_geometry} ("x,y wxh", as slurp prints it) of the focused window,
+// shell-quoted since titles can hold anything; empty without a supported compositor
+fn expand_window_placeholders(command: &str) -> String {
+    let window = wm::detect()
+        .and_then(|compositor| compositor.focused_window().ok().flatten())
+        .unwrap_or_default();
+    let geometry = window
+        .rect
+        .map(|(x, y, width, height)| format!("{},{} {}x{}", x, y, width, height))
+        .unwrap_or_default();
+    [
+        ("{window_id}", window.id),
+        ("{window_title}", window.title),
+        ("{window_class}", window.class),
+        ("{window_pid}", window.pid.map(|pid| pid.to_string()).unwrap_or_default()),
+        ("{window_workspace}", window.workspace.unwrap_or_default()),
+        ("{window_geometry}", geometry),
+    ]
+    .iter()
+    .fold(command.to_string(), |command, (placeholder, value)| {
+        command.replace(placeholder, &shell_quote(value))
+    })
+}
+
+// Current local time as YYYYmmdd-HHMMSS, suitable for file names
+fn local_timestamp() -> String {
+    // SAFETY: localtime_r only writes into the tm we hand it
+    let tm = unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        tm
+    };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    )
+}
+
+// Split rows into a column per group (ungrouped entries and inline rows count
+// as one) and pad every column with fillers to the tallest; returns the number
+// of columns and their height
+fn pad_columns(rows: &mut Vec<Row<'_>>) -> (usize, usize) {
+    let mut columns: Vec<Vec<Row<'_>>> = Vec::new();
+    let mut current_group: Option<&str> = None;
+    for row in rows.drain(..) {
+        let starts_column = match &row {
+            Row::Header(name, _) => {
+                current_group = Some(name);
+                true
+            }
+            Row::Entry(entry) if entry.group.is_none() && current_group.is_some() => {
+                current_group = None;
+                true
+            }
+            _ => columns.is_empty(),
+        };
+        if starts_column {
+            columns.push(Vec::new());
+        }
+        columns.last_mut().expect("a column was started").push(row);
+    }
+
+    let lines = columns.iter().map(Vec::len).max().unwrap_or(1);
+    let count = columns.len().max(1);
+    for mut column in columns {
+        column.resize_with(lines, || Row::Filler);
+        rows.append(&mut column);
+    }
+    (count, lines)
+}
+
+// Shorten text longer than `width` characters by replacing its middle with "…"
+fn truncate_middle(text: &str, width: usize) -> String {
+    let count = text.chars().count();
+    if count <= width || width == 0 {
+        return text.to_string();
+    }
+    let head = (width - 1).div_ceil(2);
+    let tail = width - 1 - head;
+    let mut shortened: String = text.chars().take(head).collect();
+    shortened.push('…');
+    shortened.extend(text.chars().skip(count - tail));
+    shortened
+}
+
+// The number {count} stands for: entry rows being shown, without source rows,
+// entries of collapsed groups or dimmed unavailable entries
+fn displayed_entries(rows: &[Row<'_>]) -> usize {
+    rows.iter()
+        .filter(|row| matches!(row, Row::Entry(entry) if !entry.inline && entry.available))
+        .count()
+}
+
+// Substitute {count} in a menu title; {{count}} stays a literal {count}
+fn expand_title(title: &str, count: usize) -> String {
+    title
+        .split("{{count}}")
+        .map(|part| part.replace("{count}", &count.to_string()))
+        .collect::<Vec<_>>()
+        .join("{count}")
+}
+
+// Built-in behaviors an entry can have instead of a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetaAction {
+    // Browse captured outputs and view or re-run them
+    History,
+}
+
+impl MetaAction {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "history" => Ok(MetaAction::History),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown action '{}' (expected \"history\")",
+                other
+            ))),
+        }
+    }
+}
+
+// How a selected entry's command gets started
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LaunchMethod {
+    // Plain `sh -c`, detached from rofi-keys
+    Shell,
+    // The command split into words and run directly, without a shell
+    Exec,
+    // `sh -c` inside a transient systemd scope via systemd-run
+    Systemd,
+    // `sh -c` in a new session via setsid, fully detached from our terminal
+    Setsid,
+    // Handed to the compositor (sway, i3 or Hyprland) to start like its own bindings do
+    Wm,
+}
+
+impl LaunchMethod {
+    fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "shell" => Ok(LaunchMethod::Shell),
+            "exec" => Ok(LaunchMethod::Exec),
+            "systemd" => Ok(LaunchMethod::Systemd),
+            "setsid" => Ok(LaunchMethod::Setsid),
+            "wm" => Ok(LaunchMethod::Wm),
+            other => Err(Error::ConfigInvalid(format!(
+                "Unknown launch method '{}' (expected \"shell\", \"exec\", \"systemd\", \"setsid\" or \"wm\")",
+                other
+            ))),
+        }
+    }
+}
+
+// I/O scheduling class applied through ionice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IoPriority {
+    Idle,
+    BestEffort(u8),
+}
+
+impl IoPriority {
+    fn parse(value: &str) -> Result<Self, Error> {
+        let invalid = || {
+            Error::ConfigInvalid(format!(
+                "Invalid ionice '{}' (expected \"idle\" or \"best-effort:N\" with N from 0 to 7)",
+                value
+            ))
+        };
+
+        if value == "idle" {
+            return Ok(IoPriority::Idle);
+        }
+        let level = value.strip_prefix("best-effort:").ok_or_else(invalid)?;
+        match level.parse::<u8>() {
+            Ok(level) if level <= 7 => Ok(IoPriority::BestEffort(level)),
+            _ => Err(invalid()),
+        }
+    }
+
+    fn ionice_args(self) -> Vec<String> {
+        match self {
+            IoPriority::Idle => vec!["ionice".to_string(), "-c".to_string(), "3".to_string()],
+            IoPriority::BestEffort(level) => vec![
+                "ionice".to_string(),
+                "-c".to_string(),
+                "2".to_string(),
+                "-n".to_string(),
+                level.to_string(),
+            ],
+        }
+    }
+}
+
+// Check a niceness value against the range the kernel accepts
+fn validate_nice(nice: i8) -> Result<(), Error> {
+    if (-20..=19).contains(&nice) {
+        Ok(())
+    } else {
+        Err(Error::ConfigInvalid(format!("Invalid nice {} (expected -20 to 19)", nice)))
+    }
+}
+
+// Captured outputs kept when the config doesn't say otherwise
+const DEFAULT_OUTPUT_HISTORY: usize = 20;
+
+// What clean_env keeps unless clean_env_allowlist says otherwise: enough to
+// find programs, reach the display and talk to the user's session bus
+const DEFAULT_CLEAN_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LANG",
+    "DISPLAY",
+    "WAYLAND_DISPLAY",
+    "XAUTHORITY",
+    "XDG_RUNTIME_DIR",
+    "DBUS_SESSION_BUS_ADDRESS",
+];
+
+// Changes to the inherited environment for one command
+#[derive(Debug, Default)]
+struct LaunchEnv<'a> {
+    // Clear the environment first, keeping only these variables
+    keep: Option<&'a [String]>,
+    vars: Option<&'a BTreeMap<String, String>>,
+    // The entry's ROFI_KEYS_* variables with entry_env; `vars` can still override them
+    entry: Vec<(&'static str, String)>,
+}
+
+impl LaunchEnv<'_> {
+    fn apply(&self, command: &mut Command) {
+        if let Some(keep) = self.keep {
+            command.env_clear();
+            for name in keep {
+                if let Some(value) = envfile::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(self.entry.iter().map(|(name, value)| (*name, value)));
+        if let Some(vars) = self.vars {
+            command.envs(vars);
+        }
+    }
+}
+
+// Turns menu entries into running processes
+#[derive(Debug)]
+struct Launcher {
+    method: LaunchMethod,
+    prefix: Vec<String>,
+    cwd: Option<String>,
+    // Print command lines instead of running them
+    dry_run: bool,
+    // Captured outputs to keep
+    output_history: usize,
+    // Ignore cached source rows and cache them afresh
+    refresh_sources: bool,
+    // Variables entries with clean_env keep
+    clean_env_allowlist: Vec<String>,
+    // --output-fd/--output-file: where picked entries are handed over
+    selection: Option<SelectionSink>,
+    // Describe the entry to its command in ROFI_KEYS_* variables
+    entry_env: bool,
+    // What actually starts the assembled command lines
+    executor: Box<dyn Executor>,
+}
+
+impl Launcher {
+    fn from_config(config: &Config) -> io::Result<Self> {
+        let method = match &config.launch_method {
+            Some(name) => LaunchMethod::parse(name)?,
+            None => LaunchMethod::Shell,
+        };
+        let prefix = config
+            .launch_prefix
+            .as_deref()
+            .map(split_words)
+            .unwrap_or_default();
+        Ok(Launcher {
+            method,
+            prefix,
+            cwd: config.cwd.clone(),
+            dry_run: false,
+            output_history: config.output_history.unwrap_or(DEFAULT_OUTPUT_HISTORY),
+            refresh_sources: false,
+            clean_env_allowlist: config.clean_env_allowlist.clone().unwrap_or_else(|| {
+                DEFAULT_CLEAN_ENV.iter().map(|name| name.to_string()).collect()
+            }),
+            selection: None,
+            entry_env: config.entry_env,
+            executor: Box::new(ShellExecutor),
+        })
+    }
+
+    // False when picked entries are only printed or handed over, not run
+    fn runs_commands(&self) -> bool {
+        !self.dry_run && self.selection.as_ref().is_none_or(|sink| sink.and_exec)
+    }
+
+    fn launch(&self, entry: &MenuEntry) -> Result<(), Error> {
+        self.launch_as(entry, entry.options.capture_output)
+    }
+
+    // Launch an entry, waiting for it and keeping its output when `capture` is set
+    fn launch_as(&self, entry: &MenuEntry, capture: bool) -> Result<(), Error> {
+        if entry.options.single_instance && self.runs_commands() && self.handled_running(entry)? {
+            return Ok(());
+        }
+        // The entry's exec_mode wins over the global launch_method
+        let method = match &entry.options.exec_mode {
+            Some(mode) => LaunchMethod::parse(mode)?,
+            None => self.method,
+        };
+        // The compositor would run it out of reach, with nothing to wait for
+        let method = match method {
+            LaunchMethod::Wm if capture => LaunchMethod::Shell,
+            method => method,
+        };
+
+        let mut argv = Vec::new();
+        match method {
+            LaunchMethod::Systemd => argv.extend(systemd_run_args(entry)?),
+            LaunchMethod::Setsid => argv.extend(["setsid".to_string(), "-f".to_string()]),
+            LaunchMethod::Shell | LaunchMethod::Exec | LaunchMethod::Wm => {}
+        }
+        if let Some(nice) = entry.options.nice {
+            argv.extend(["nice".to_string(), "-n".to_string(), nice.to_string()]);
+        }
+        if let Some(ionice) = &entry.options.ionice {
+            argv.extend(IoPriority::parse(ionice)?.ionice_args());
+        }
+        if entry.options.sandbox.unwrap_or(true) {
+            argv.extend(self.prefix.iter().cloned());
+        }
+        let command = expand_placeholders(&entry.command);
+        if method == LaunchMethod::Exec {
+            argv.extend(split_words(&command));
+        } else {
+            argv.extend(["sh".to_string(), "-c".to_string(), command]);
+        }
+        let cwd = entry.options.cwd.as_ref().or(self.cwd.as_ref()).map(|dir| expand_path(dir));
+        if method == LaunchMethod::Wm {
+            // The compositor runs the command from its own directory and environment
+            let compositor = wm::detect().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "launch method \"wm\" needs sway, i3 or Hyprland",
+                )
+            })?;
+            let mut line: Vec<String> = argv.iter().map(|word| shell_quote(word)).collect();
+            if let Some(dir) = &cwd {
+                line.splice(0..0, ["cd".to_string(), shell_quote(dir), "&&".to_string()]);
+            }
+            return self.start(entry, &compositor.exec(&line.join(" ")), None);
+        }
+        self.start_as(entry, &argv, cwd.as_deref().map(Path::new), capture)
+    }
+
+    // Start a command line on behalf of `entry`, after its delay
+    fn start(&self, entry: &MenuEntry, argv: &[String], cwd: Option<&Path>) -> Result<(), Error> {
+        self.start_as(entry, argv, cwd, entry.options.capture_output)
+    }
+
+    fn start_as(&self, entry: &MenuEntry, argv: &[String], cwd: Option<&Path>, capture: bool) -> Result<(), Error> {
+        if let Some(sink) = &self.selection {
+            sink.write(&entry.label, entry.key, argv, cwd)?;
+            if !sink.and_exec {
+                return Ok(());
+            }
+        }
+        if self.dry_run {
+            print_command(argv);
+            return Ok(());
+        }
+        // rofi has already exited by now; the delay gives its window time to unmap
+        if let Some(delay) = entry.options.delay_ms {
+            thread::sleep(Duration::from_millis(delay));
+        }
+        let env = self.env_for(entry);
+        if entry.options.exec_replace && !capture {
+            return Err(self.executor.replace(argv, cwd, &env));
+        }
+        if capture {
+            return self.capture(&entry.label, argv, cwd, &env);
+        }
+        self.executor.spawn(argv, cwd, &env)
+    }
+
+    // For single_instance entries whose program is running already: focus its
+    // window or do nothing as on_running says, returning whether that replaced
+    // the launch
+    fn handled_running(&self, entry: &MenuEntry) -> Result<bool, Error> {
+        let options = &entry.options;
+        let program = command_program(&entry.command)
+            .map(|program| program.rsplit('/').next().unwrap_or_default().to_string());
+        let pattern = match (&options.match_pattern, &program) {
+            (Some(pattern), _) => instance_regex(pattern)?,
+            (None, Some(program)) => instance_regex(&format!("^{}$", regex::escape(program)))?,
+            (None, None) => return Ok(false),
+        };
+        if !instance::is_running(&pattern) {
+            return Ok(false);
+        }
+        let on_running = match &options.on_running {
+            Some(name) => OnRunning::parse(name)?,
+            None => OnRunning::default(),
+        };
+        match on_running {
+            OnRunning::Launch => Ok(false),
+            OnRunning::Ignore => Ok(true),
+            OnRunning::Focus => {
+                let class = options.focus_class.as_ref().or(program.as_ref());
+                if let Some(argv) = class.and_then(|class| instance::find_window(class)) {
+                    self.executor.spawn(&argv, None, &LaunchEnv::default())?;
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    // How `entry` changes the environment its command inherits
+    fn env_for<'a>(&'a self, entry: &'a MenuEntry) -> LaunchEnv<'a> {
+        let options = &entry.options;
+        LaunchEnv {
+            keep: options.clean_env.then_some(self.clean_env_allowlist.as_slice()),
+            vars: (!options.env.is_empty()).then_some(&options.env),
+            entry: if self.entry_env { entry.env_vars() } else { Vec::new() },
+        }
+    }
+
+    // Run a command to completion and add what it printed to the output history
+    fn capture(
+        &self,
+        label: &str,
+        argv: &[String],
+        cwd: Option<&Path>,
+        env: &LaunchEnv<'_>,
+    ) -> Result<(), Error> {
+        if self.dry_run {
+            print_command(argv);
+            return Ok(());
+        }
+        let output = self.executor.output(argv, cwd, env)?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        let record = OutputRecord::new(label, argv, cwd, output.status.code(), text);
+        Ok(OutputLog::load().record(record, self.output_history)?)
+    }
+}
+
+// Show a command line the way --dry-run reports it
+fn print_command(argv: &[String]) {
+    let words: Vec<String> = argv.iter().map(|word| shell_quote(word)).collect();
+    println!("{}", words.join(" "));
+}
+
+// Quote a word for display so it can be pasted back into a shell
+fn shell_quote(word: &str) -> String {
+    let plain = !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c));
+    if plain {
+        word.to_string()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+// Split a command into words like a shell would, honoring quotes and backslashes
+// but without expansions, for running programs directly
+fn split_words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(next @ ('"' | '\\' | '$' | '`')) => word.push(next),
+                Some(next) => {
+                    word.push('\\');
+                    word.push(next);
+                }
+                None => word.push('\\'),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    word.push(next);
+                }
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+// Build the systemd-run prefix for an entry, resolving unit name collisions
+fn systemd_run_args(entry: &MenuEntry) -> io::Result<Vec<String>> {
+    let options = &entry.options;
+    // A scope keeps our environment (DISPLAY etc.) unlike a transient service
+    let mut args = vec![
+        "systemd-run".to_string(),
+        "--user".to_string(),
+        "--scope".to_string(),
+        "--quiet".to_string(),
+    ];
+
+    if let Some(unit) = &options.unit {
+        let unit = if unit_is_active(unit) {
+            if options.unique_unit {
+                let message = format!("Unit {} is already running", unit);
+                notify(&entry.label, &message);
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, message));
+            }
+            format!("{}-{}", unit, random_suffix())
+        } else {
+            unit.clone()
+        };
+        args.push(format!("--unit={}", unit));
+    }
+
+    if let Some(slice) = &options.slice {
+        args.push(format!("--slice={}", slice));
+    }
+
+    for property in &options.properties {
+        args.push("-p".to_string());
+        args.push(property.clone());
+    }
+
+    Ok(args)
+}
+
+// Check whether a user scope with this name is currently running
+fn unit_is_active(unit: &str) -> bool {
+    let unit = if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{}.scope", unit)
+    };
+
+    envfile::command("systemctl")
+        .args(["--user", "is-active", "--quiet", &unit])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+// Short random hex string for disambiguating unit names
+fn random_suffix() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    format!("{:06x}", hasher.finish() & 0xff_ffff)
+}
+
+// Show a desktop notification, ignoring failures (notify-send may be missing)
+fn notify(summary: &str, body: &str) {
+    let _ = envfile::command("notify-send")
+        .args(["rofi-keys", &format!("{}: {}", summary, body)])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+}
+
+// Set up the environment launched commands get: the env_file first, then
+// the extra PATH directories in front of whatever PATH it set. Each call
+// starts over from the environment rofi-keys was started with
+fn prepare_environment(config: &Config) -> Result<(), Error> {
+    let mut vars = Vec::new();
+    if let Some(file) = &config.env_file {
+        // A stale or missing snapshot shouldn't keep the menu from opening
+        match envfile::load(Path::new(&expand_path(file))) {
+            Ok(loaded) => vars = loaded,
+            Err(e) => eprintln!("Could not read env_file: {}", e),
+        }
+    }
+    envfile::apply(&vars);
+    prepend_path(&config.path)
+}
+
+// Where capture-env writes when no file is given
+fn default_env_file(config_path: &Path) -> PathBuf {
+    read_config(config_path)
+        .ok()
+        .and_then(|config| config.env_file)
+        .map(|file| PathBuf::from(expand_path(&file)))
+        .unwrap_or_else(|| config_path.with_file_name("env"))
+}
+
+// Put the config's extra directories in front of PATH, so they count both for
+// availability checks and for everything launched
+fn prepend_path(dirs: &[String]) -> Result<(), Error> {
+    if dirs.is_empty() {
+        return Ok(());
+    }
+    let mut paths: Vec<PathBuf> = dirs.iter().map(|dir| PathBuf::from(expand_path(dir))).collect();
+    if let Some(current) = envfile::var_os("PATH") {
+        paths.extend(env::split_paths(&current));
+    }
+    let joined = env::join_paths(paths).map_err(|e| {
+        Error::ConfigInvalid(format!("Invalid path entry: {}", e))
+    })?;
+    envfile::set("PATH", joined);
+    Ok(())
+}
+
+// Expand ~ to home directory in paths
+fn expand_path(path: &str) -> String {
+    let home = env::var("HOME").ok().filter(|home| !home.is_empty());
+    if home.is_none() && path.starts_with("~/") {
+        HOME_WARNING.call_once(|| {
+            eprintln!("HOME is not set; paths starting with ~/ are used as written")
+        });
+    }
+    expand_path_with(path, home.as_deref())
+}
+
+// expand_path with the home directory passed in rather than read from HOME
+fn expand_path_with(path: &str, home: Option<&str>) -> String {
+    match home {
+        Some(home) if path.starts_with("~/") => path.replacen("~", home, 1),
+        _ => path.to_string(),
+    }
+}
+
+// Create a default configuration
+fn create_default_config() -> Config {
+    Config {
+        theme: None, // Use Rofi's default theme
+        prompt: Some(i18n::tr("Applications").to_string()),
+        entries: vec![
+            MenuEntryConfig::new("f", "Firefox", "firefox"),
+            MenuEntryConfig::new("p", i18n::tr("Firefox Private"), "firefox --private-window"),
+            MenuEntryConfig::new("m", "MPV", "mpv"),
+            MenuEntryConfig::new("v", i18n::tr("MPV (clipboard)"), "mpv \"$(xclip -o)\""),
+            MenuEntryConfig::new("t", "Terminal", "x-terminal-emulator"),
+        ],
+        ..Config::default()
+    }
+}
+
+// Write a config to a specific path
+fn write_config(config: &Config, path: &Path) -> io::Result<()> {
+    // Create directory if it doesn't exist
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Serialize to JSON with pretty formatting
+    let json = serde_json::to_string_pretty(config).map_err(io::Error::other)?;
+    
+    // Write the config
+    write_atomic(path, json.as_bytes())?;
+    println!("Configuration written to {}", path.display());
+    
+    Ok(())
+}
+
+// Replace a file's contents so readers see either the old or the new version,
+// never a truncated one, by writing a sibling temp file and renaming it
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Path has no file name"))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// Get the default config path
+fn get_default_config_path() -> io::Result<PathBuf> {
+    let config_home = env::var("XDG_CONFIG_HOME").ok();
+    let home = env::var("HOME").ok();
+    default_config_path_with(config_home.as_deref(), home.as_deref())
+}
+
+// get_default_config_path with $XDG_CONFIG_HOME and $HOME passed in rather
+// than read from the environment; empty ones count as unset
+fn default_config_path_with(config_home: Option<&str>, home: Option<&str>) -> io::Result<PathBuf> {
+    let config_home = match (config_home.filter(|dir| !dir.is_empty()), home.filter(|home| !home.is_empty())) {
+        (Some(dir), _) => PathBuf::from(dir),
+        (None, Some(home)) => Path::new(home).join(".config"),
+        (None, None) => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "HOME is not set; pass --config or set ROFI_KEYS_CONFIG",
+            ))
+        }
+    };
+    Ok(config_home.join("rofi-keys/config.json"))
+}
+
+// Fill in aliases from the entries they reference, rejecting dangling refs and cycles;
+// url entries get their command first so aliases of them work too
+fn resolve_references(config: &mut Config) -> Result<(), Error> {
+    let handler = config.url_handler.as_deref().unwrap_or(DEFAULT_URL_HANDLER);
+    let all_entries = config
+        .entries
+        .iter_mut()
+        .chain(config.flows.values_mut().flat_map(|flow| flow.entries.iter_mut()));
+    for entry in all_entries {
+        resolve_command_ref(entry, &config.commands)?;
+        resolve_url(entry, handler);
+    }
+    resolve_entry_references(config)
+}
+
+// window rules placing rofi at `location` (used as the window's anchor too,
+// so the offsets count from that corner) moved by the offsets; None if unset
+fn placement_theme(config: &Config) -> Result<Option<String>, Error> {
+    let mut rules = Vec::new();
+    if let Some(location) = &config.location {
+        let location = Location::parse(location)?.rasi();
+        rules.push(format!("location: {}; anchor: {};", location, location));
+    }
+    for (name, offset) in [("x-offset", config.x_offset), ("y-offset", config.y_offset)] {
+        if let Some(offset) = offset {
+            rules.push(format!("{}: {}px;", name, offset));
+        }
+    }
+    Ok((!rules.is_empty()).then(|| format!("window {{ {} }}", rules.join(" "))))
+}
+
+// With group_mode "submenu", move each group of the main menu into a flow
+// named after it, opened from an entry with the group's name; those entries
+// follow the ungrouped ones, in the order the headers would have
+fn group_submenus(config: &mut Config) -> Result<(), Error> {
+    let mode = match &config.group_mode {
+        Some(mode) => GroupMode::parse(mode)?,
+        None => GroupMode::default(),
+    };
+    if mode != GroupMode::Submenu {
+        return Ok(());
+    }
+    let invalid = Error::ConfigInvalid;
+
+    // Same order as the headers of the inline mode
+    let mut groups: Vec<String> = Vec::new();
+    let listed = config.group_order.iter().map(Some);
+    let found = config.entries.iter().map(|entry| entry.group.as_ref());
+    for name in listed.chain(found).flatten() {
+        let used = config.entries.iter().any(|entry| entry.group.as_ref() == Some(name));
+        if used && !groups.contains(name) {
+            groups.push(name.clone());
+        }
+    }
+    if let Some(name) = config.group_keys.keys().find(|name| !groups.contains(name)) {
+        return Err(invalid(format!("group_keys names '{}', which no entry is in", name)));
+    }
+    if let Some(name) = groups.iter().find(|name| config.flows.contains_key(*name)) {
+        return Err(invalid(format!("Group '{}' has the same name as a flow", name)));
+    }
+
+    let (grouped, mut entries): (Vec<_>, Vec<_>) =
+        std::mem::take(&mut config.entries).into_iter().partition(|entry| entry.group.is_some());
+    for name in groups {
+        let members: Vec<MenuEntryConfig> = grouped
+            .iter()
+            .filter(|entry| entry.group.as_ref() == Some(&name))
+            .cloned()
+            .map(|entry| MenuEntryConfig { group: None, ..entry })
+            .collect();
+        let key = config.group_keys.get(&name).map_or("", String::as_str);
+        let mut opener = MenuEntryConfig::new(key, &name, "");
+        opener.next = Some(name.clone());
+        opener.enabled = members.iter().any(|entry| entry.enabled);
+        entries.push(opener);
+        config.flows.insert(
+            name,
+            FlowMenuConfig {
+                prompt: None,
+                binding: None,
+                theme: None,
+                theme_str: None,
+                message: None,
+                show_commands: None,
+                show_key_hints: None,
+                entries: members,
+            },
+        );
+    }
+    config.entries = entries;
+    Ok(())
+}
+
+// Give entries without a key the first free character of their label (or
+// failing that a digit or letter), never one rofi or hint_key/cycle_theme_key
+// already binds; explicit keys that collide are kept with a warning
+fn assign_keys(config: &mut Config) {
+    let overrides = kb_overrides(config);
+    let mut reserved: Vec<(String, String)> = kb::effective_bindings(&overrides)
+        .into_iter()
+        .flat_map(|(action, keys)| {
+            keys.split(',')
+                .map(|key| (key.trim().to_string(), format!("rofi's {}", action)))
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let menu_keys = [("hint_key", &config.hint_key), ("cycle_theme_key", &config.cycle_theme_key)];
+    for (name, key) in menu_keys {
+        if let Some(key) = key {
+            reserved.push((named_key_binding(key), name.to_string()));
+        }
+    }
+    let reserved_by = |key: char| {
+        let binding = key_binding(key);
+        reserved
+            .iter()
+            .find(|(bound, _)| *bound == binding)
+            .map(|(_, what)| what.as_str())
+    };
+
+    // number_keys hands out 1-9 by position, so digits would be taken twice
+    let numbered = |key: &char| config.number_keys && ('1'..='9').contains(key);
+    let menus = std::iter::once(("main menu", &mut config.entries))
+        .chain(config.flows.iter_mut().map(|(name, flow)| (name.as_str(), &mut flow.entries)));
+    for (menu, entries) in menus {
+        let mut taken: Vec<char> = entries.iter().filter_map(|entry| entry.key.chars().next()).collect();
+        for entry in entries.iter().filter(|entry| entry.enabled) {
+            if let Some(what) = entry.key.chars().next().and_then(reserved_by) {
+                output::warn(format!(
+                    "{}: key '{}' of '{}' is also {}",
+                    menu, entry.key, entry.label, what
+                ));
+            }
+        }
+        let keyless = entries
+            .iter_mut()
+            .filter(|entry| entry.key.is_empty() && entry.enabled && !entry.inline);
+        for entry in keyless {
+            let from_label = entry
+                .label
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase);
+            let free = from_label
+                .chain(CHOICE_KEYS.chars())
+                .find(|key| !taken.contains(key) && reserved_by(*key).is_none() && !numbered(key));
+            match free {
+                Some(key) => {
+                    taken.push(key);
+                    entry.key = key.to_string();
+                }
+                None => output::warn(format!("{}: no free key left for '{}'", menu, entry.label)),
+            }
+        }
+    }
+}
+
+// Turn `command_ref` into the named command; like `url`, an entry with a
+// command as well keeps both for the one-action check
+fn resolve_command_ref(
+    entry: &mut MenuEntryConfig,
+    commands: &BTreeMap<String, String>,
+) -> Result<(), Error> {
+    if !entry.command.is_empty() {
+        return Ok(());
+    }
+    if let Some(name) = entry.command_ref.take() {
+        entry.command = commands.get(&name).cloned().ok_or_else(|| {
+            Error::ConfigInvalid(format!(
+                "Entry '{}' refers to unknown command '{}'",
+                entry.label,
+                name
+            ))
+        })?;
+    }
+    Ok(())
+}
+
+// Turn `url` into a command opening it; an entry with a command as well keeps
+// both, for the one-action check to reject
+fn resolve_url(entry: &mut MenuEntryConfig, handler: &str) {
+    if entry.command.is_empty() {
+        if let Some(url) = entry.url.take() {
+            entry.command = format!("{} {}", handler, shell_quote(&url));
+        }
+    }
+}
+
+// Resolve refs across all menus: "Label" is looked for in the alias's own menu,
+// then in the others, and "flow/Label" (or "/Label" for the main menu) names
+// the menu to look in
+fn resolve_entry_references(config: &mut Config) -> Result<(), Error> {
+    let invalid = Error::ConfigInvalid;
+    let menus: Vec<Option<String>> =
+        std::iter::once(None).chain(config.flows.keys().cloned().map(Some)).collect();
+    let entries_of = |menu: &Option<String>| match menu {
+        Some(name) => &config.flows[name].entries,
+        None => &config.entries,
+    };
+    let mut resolved = Vec::new();
+
+    for menu in &menus {
+        for (index, entry) in entries_of(menu).iter().enumerate() {
+            let actions = [
+                !entry.command.is_empty(),
+                entry.reference.is_some(),
+                entry.command_ref.is_some(),
+                entry.url.is_some(),
+                entry.command_template.is_some(),
+                entry.source_type.is_some(),
+                entry.next.is_some(),
+                entry.action.is_some(),
+            ];
+            match actions.iter().filter(|set| **set).count() {
+                0 => {
+                    return Err(invalid(format!(
+                        "Entry '{}' needs one of command, ref, command_ref, url, command_template, source_type, next or action",
+                        entry.label
+                    )))
+                }
+                1 => {}
+                _ => {
+                    return Err(invalid(format!(
+                        "Entry '{}' may only set one of command, ref, command_ref, url, command_template, source_type, next or action",
+                        entry.label
+                    )))
+                }
+            }
+            let Some(reference) = &entry.reference else {
+                continue;
+            };
+
+            // Follow the chain of refs until we reach an entry with a real command
+            let mut chain = vec![(menu.clone(), entry.label.as_str())];
+            let mut from = (menu.clone(), reference.as_str());
+            let target = loop {
+                let (target_menu, target) = find_reference(&menus, &entries_of, &from.0, from.1)
+                    .map_err(|e| invalid(format!("Entry '{}' {}", entry.label, e)))?;
+                let target_label = (target_menu.clone(), target.label.as_str());
+                if chain.contains(&target_label) {
+                    chain.push(target_label);
+                    let names: Vec<String> = chain
+                        .iter()
+                        .map(|(in_menu, label)| {
+                            if in_menu == menu {
+                                label.to_string()
+                            } else {
+                                menu_label(in_menu, label)
+                            }
+                        })
+                        .collect();
+                    return Err(invalid(format!("Ref cycle: {}", names.join(" -> "))));
+                }
+                match &target.reference {
+                    Some(next) => {
+                        chain.push(target_label);
+                        from = (target_menu, next.as_str());
+                    }
+                    None => break target,
+                }
+            };
+
+            resolved.push((menu.clone(), index, target.clone()));
+        }
+    }
+
+    for (menu, index, target) in resolved {
+        let entry = match &menu {
+            Some(name) => {
+                &mut config.flows.get_mut(name).expect("menu listed above").entries[index]
+            }
+            None => &mut config.entries[index],
+        };
+        borrow_target(entry, target);
+    }
+
+    Ok(())
+}
+
+// The menu and entry `reference` names, for an alias in `menu`
+fn find_reference<'a>(
+    menus: &[Option<String>],
+    entries_of: &impl Fn(&Option<String>) -> &'a Vec<MenuEntryConfig>,
+    menu: &Option<String>,
+    reference: &str,
+) -> Result<(Option<String>, &'a MenuEntryConfig), String> {
+    let in_menu = |menu: &Option<String>, label: &str| {
+        entries_of(menu).iter().find(|candidate| candidate.label == label)
+    };
+    if let Some(target) = in_menu(menu, reference) {
+        return Ok((menu.clone(), target));
+    }
+    if let Some((name, label)) = reference.split_once('/') {
+        let named = (!name.is_empty()).then(|| name.to_string());
+        if menus.contains(&named) {
+            return in_menu(&named, label)
+                .map(|target| (named.clone(), target))
+                .ok_or_else(|| format!("refers to unknown entry '{}'", reference));
+        }
+    }
+    let found: Vec<_> = menus
+        .iter()
+        .filter_map(|other| in_menu(other, reference).map(|target| (other.clone(), target)))
+        .collect();
+    match found.len() {
+        0 => Err(format!("refers to unknown entry '{}'", reference)),
+        1 => Ok(found.into_iter().next().expect("one match")),
+        _ => Err(format!(
+            "refers to '{}', which is in several menus; write it as 'flow/{}' or '/{}'",
+            reference, reference, reference
+        )),
+    }
+}
+
+// How a ref to `label` in `menu` is written from another menu
+fn menu_label(menu: &Option<String>, label: &str) -> String {
+    format!("{}/{}", menu.as_deref().unwrap_or(""), label)
+}
+
+// Borrow what the target does, and whatever else the alias leaves unset; the
+// alias keeps its own key, label, tags and group, and anything it sets itself
+fn borrow_target(entry: &mut MenuEntryConfig, target: MenuEntryConfig) {
+    entry.command = target.command;
+    entry.command_template = target.command_template;
+    entry.args = target.args;
+    entry.source_type = target.source_type;
+    entry.next = target.next;
+    entry.action = target.action;
+    entry.clipboard_tool = entry.clipboard_tool.take().or(target.clipboard_tool);
+    entry.missing = entry.missing.take().or(target.missing);
+    entry.source_file = entry.source_file.take().or(target.source_file);
+    entry.output = entry.output.take().or(target.output);
+    entry.cache_seconds = entry.cache_seconds.or(target.cache_seconds);
+    entry.stale_while_revalidate |= target.stale_while_revalidate;
+    entry.source_timeout_ms = entry.source_timeout_ms.or(target.source_timeout_ms);
+    entry.limit = entry.limit.or(target.limit);
+    entry.icon = entry.icon.take().or(target.icon);
+    entry.options.fill_from(target.options);
+}
+
+// Reject settings that would only fail once an entry is launched
+fn validate_config(config: &Config) -> Result<(), Error> {
+    if let Some(method) = &config.launch_method {
+        LaunchMethod::parse(method)?;
+    }
+    if let Some(style) = &config.key_style {
+        KeyStyle::parse(style)?;
+    }
+    if let Some(position) = &config.key_position {
+        KeyPosition::parse(position)?;
+    }
+    if let Some(layout) = &config.layout {
+        Layout::parse(layout)?;
+    }
+    placement_theme(config)?;
+    if let Some(mode) = &config.group_mode {
+        GroupMode::parse(mode)?;
+    }
+    if config.rofi_command.as_deref().is_some_and(|template| split_words(template).is_empty()) {
+        return Err(Error::ConfigInvalid("rofi_command is empty".to_string()));
+    }
+    if let Some(name) = &config.fallback_backend {
+        Fallback::parse(name)?;
+    }
+    if config.cycle_theme_key.is_some() && config.themes.is_empty() {
+        return Err(Error::ConfigInvalid(
+            "cycle_theme_key needs a list of themes to cycle through".to_string(),
+        ));
+    }
+    if config.save_theme && config.cycle_theme_key.is_none() {
+        return Err(Error::ConfigInvalid(
+            "save_theme needs a cycle_theme_key to pick the theme with".to_string(),
+        ));
+    }
+    if let Some(preset) = &config.kb_preset {
+        if !kb::is_preset(preset) {
+            return Err(Error::ConfigInvalid(format!(
+                "Unknown kb_preset '{}' (expected one of: {})",
+                preset,
+                kb::preset_names().join(", ")
+            )));
+        }
+    }
+    if let Some(pattern) = &config.custom_command_pattern {
+        if !config.allow_custom_command {
+            return Err(Error::ConfigInvalid(
+                "custom_command_pattern only applies with allow_custom_command".to_string(),
+            ));
+        }
+        custom_command_regex(pattern)?;
+    }
+
+    let all_entries = config
+        .entries
+        .iter()
+        .chain(config.flows.values().flat_map(|flow| &flow.entries));
+    for entry in all_entries {
+        let in_entry = |e: Error| Error::ConfigInvalid(format!("Entry '{}': {}", entry.label, e));
+        if let Some(mode) = &entry.options.exec_mode {
+            LaunchMethod::parse(mode).map_err(in_entry)?;
+        }
+        if let Some(backend) = &entry.options.backend {
+            Backend::parse(backend).map_err(in_entry)?;
+        }
+        if let Some(nice) = entry.options.nice {
+            validate_nice(nice).map_err(in_entry)?;
+        }
+        if let Some(ionice) = &entry.options.ionice {
+            IoPriority::parse(ionice).map_err(in_entry)?;
+        }
+        Source::validate(entry).map_err(in_entry)?;
+        if let Some(action) = &entry.action {
+            MetaAction::parse(action).map_err(in_entry)?;
+        }
+        if let Some(policy) = &entry.missing {
+            MissingPolicy::parse(policy).map_err(in_entry)?;
+        }
+        if let Some(name) = &entry.options.on_running {
+            OnRunning::parse(name).map_err(in_entry)?;
+        }
+        if let Some(pattern) = &entry.options.match_pattern {
+            instance_regex(pattern).map_err(in_entry)?;
+        }
+        if !entry.options.single_instance {
+            let instance_only = [
+                ("match", entry.options.match_pattern.is_some()),
+                ("focus_class", entry.options.focus_class.is_some()),
+                ("on_running", entry.options.on_running.is_some()),
+            ];
+            if let Some((name, _)) = instance_only.iter().find(|(_, set)| *set) {
+                return Err(in_entry(Error::ConfigInvalid(format!(
+                    "{} only applies to single_instance entries",
+                    name
+                ))));
+            }
+        }
+        if let Some(id) = entry.options.lint_ignore.iter().find(|id| !lint::is_known(id)) {
+            return Err(in_entry(Error::ConfigInvalid(format!(
+                "Unknown lint '{}' in lint_ignore",
+                id
+            ))));
+        }
+        if entry.source_type.is_none() {
+            let source_only = [
+                ("cache_seconds", entry.cache_seconds.is_some()),
+                ("inline", entry.inline),
+                ("source_timeout_ms", entry.source_timeout_ms.is_some()),
+                ("limit", entry.limit.is_some()),
+            ];
+            if let Some((name, _)) = source_only.iter().find(|(_, set)| *set) {
+                return Err(in_entry(Error::ConfigInvalid(format!(
+                    "{} only applies to entries with a source_type",
+                    name
+                ))));
+            }
+        }
+        if entry.command_template.is_some() && entry.args.is_empty() {
+            return Err(in_entry(Error::ConfigInvalid(
+                "command_template needs at least one entry in args".to_string(),
+            )));
+        }
+        let plain_command = !entry.command.is_empty()
+            && entry.args.is_empty()
+            && entry.source_type.is_none()
+            && entry.next.is_none()
+            && entry.action.is_none();
+        if entry.options.dual_activation && !plain_command {
+            return Err(in_entry(Error::ConfigInvalid(
+                "dual_activation only applies to entries that run a command".to_string(),
+            )));
+        }
+        if entry.options.exec_replace {
+            // Nothing is left to reopen the menu or wait for output afterwards
+            let stays_around = [
+                ("repeatable", entry.options.repeatable),
+                ("capture_output", entry.options.capture_output),
+                ("dual_activation", entry.options.dual_activation),
+            ];
+            if let Some((name, _)) = stays_around.iter().find(|(_, set)| *set) {
+                return Err(in_entry(Error::ConfigInvalid(format!(
+                    "exec_replace can't be combined with {}",
+                    name
+                ))));
+            }
+        }
+        if let Some(next) = &entry.next {
+            if !config.flows.contains_key(next) {
+                return Err(in_entry(Error::ConfigInvalid(format!(
+                    "next refers to unknown flow '{}'",
+                    next
+                ))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Two entries called "Terminal" are confusing even with different keys,
+// so warn about labels in the same menu that only differ in case or punctuation
+fn warn_duplicate_labels(config: &Config) {
+    let menus = std::iter::once(("main menu", &config.entries))
+        .chain(config.flows.iter().map(|(name, flow)| (name.as_str(), &flow.entries)));
+    for (menu, entries) in menus {
+        let mut seen: HashMap<String, &str> = HashMap::new();
+        for entry in entries.iter().filter(|entry| entry.enabled) {
+            let normalized: String = entry
+                .label
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect();
+            if let Some(first) = seen.get(&normalized) {
+                output::warn(format!(
+                    "{}: entries '{}' and '{}' have the same label",
+                    menu, first, entry.label
+                ));
+            } else {
+                seen.insert(normalized, &entry.label);
+            }
+        }
+    }
+}
+
+// Config from the setup wizard if the user wants one, else the default config
+fn first_run_config(config_path: &Path) -> Result<LoadedConfig, Error> {
+    if let Some(mut config) = setup::offer()? {
+        write_config(&config, config_path)?;
+        config.file = Some(config_path.to_path_buf());
+        let sources = config_sources(config_path, &config);
+        return Ok(LoadedConfig { config, sources });
+    }
+    load_config(config_path)
+}
+
+// A loaded config and the files it was read from
+struct LoadedConfig {
+    config: Config,
+    // The config file first, then the files it names, in the order they are read
+    sources: Vec<PathBuf>,
+}
+
+// Function to load menu entries from JSON config file
+fn load_config(config_path: &Path) -> Result<LoadedConfig, Error> {
+    // Check if the config file exists
+    if !config_path.exists() {
+        // Create a default config
+        let mut default_config = create_default_config();
+        
+        // Write the default config
+        write_config(&default_config, config_path)?;
+        default_config.file = Some(config_path.to_path_buf());
+        
+        return Ok(LoadedConfig {
+            config: default_config,
+            sources: vec![config_path.to_path_buf()],
+        });
+    }
+    
+    let mut config = read_config(config_path)?;
+    config.file = Some(config_path.to_path_buf());
+    profile::mark("read config");
+    if let Some(locale) = &config.locale {
+        i18n::set_locale(locale);
+    }
+    if let Some(dir) = config_path.parent() {
+        resolve_theme_paths(&mut config, dir);
+    }
+    resolve_references(&mut config)?;
+    group_submenus(&mut config)?;
+    assign_keys(&mut config);
+    profile::mark("resolve refs");
+    // When the menu is about to be shown, its sources run while the config is checked
+    sources::prefetch(|| main_menu_jobs(&config));
+    validate_config(&config)?;
+    if !config.allow_duplicate_labels {
+        warn_duplicate_labels(&config);
+    }
+    profile::mark("validate");
     
-    // Add entries from config
-    for entry in config.entries {
-        if let Some(key_char) = entry.key.chars().next() {
-            menu.add_entry(key_char, &entry.label, &entry.command);
+    let sources = config_sources(config_path, &config);
+    Ok(LoadedConfig { config, sources })
+}
+
+// The config file, then its env_file and the snippet files its entries list
+fn config_sources(config_path: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut sources = vec![config_path.to_path_buf()];
+    sources.extend(config.env_file.iter().map(|file| PathBuf::from(expand_path(file))));
+    let entries = config.entries.iter().chain(config.flows.values().flat_map(|flow| &flow.entries));
+    for entry in entries.filter(|entry| entry.source_type.as_deref() == Some("snippets")) {
+        if let Some(file) = &entry.source_file {
+            let path = PathBuf::from(expand_path(file));
+            if !sources.contains(&path) {
+                sources.push(path);
+            }
+        }
+    }
+    sources
+}
+
+// The inline sources of the main menu that can run, for sources::prefetch;
+// an invalid one is left for validate_config to report
+fn main_menu_jobs(config: &Config) -> Vec<SourceJob> {
+    config
+        .entries
+        .iter()
+        .filter(|entry| entry.enabled && entry.inline)
+        .filter_map(|entry| Some(source_job(entry, Source::resolve(entry).ok()??)))
+        .collect()
+}
+
+// How an inline source entry's rows are fetched, alongside the menu's other sources
+fn source_job(entry: &MenuEntryConfig, source: Source) -> SourceJob {
+    SourceJob {
+        source,
+        cache: entry.cache_seconds.map(|ttl| CachePolicy {
+            ttl,
+            stale_while_revalidate: entry.stale_while_revalidate,
+        }),
+        timeout: Duration::from_millis(entry.source_timeout_ms.unwrap_or(DEFAULT_SOURCE_TIMEOUT_MS)),
+    }
+}
+
+// Theme paths in a config are relative to the config file, not to wherever
+// rofi happens to run; named themes, ~/ and absolute paths are left alone
+fn resolve_theme_paths(config: &mut Config, dir: &Path) {
+    let resolve = |theme: &mut Option<String>| {
+        if let Some(path) = theme.as_mut() {
+            if path.contains('/') && !path.starts_with('~') && Path::new(path.as_str()).is_relative() {
+                *path = dir.join(path.as_str()).to_string_lossy().into_owned();
+            }
+        }
+    };
+    resolve(&mut config.theme);
+    for theme in &mut config.themes {
+        let mut cycled = Some(std::mem::take(theme));
+        resolve(&mut cycled);
+        *theme = cycled.unwrap_or_default();
+    }
+    for flow in config.flows.values_mut() {
+        resolve(&mut flow.theme);
+    }
+}
+
+// Read and parse the JSON config as written, without resolving refs
+fn read_config(config_path: &Path) -> Result<Config, Error> {
+    let bytes = fs::read(config_path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => Error::ConfigNotFound {
+            path: config_path.to_path_buf(),
+        },
+        _ => Error::Io(e),
+    })?;
+    // Point at the first bad byte the way the JSON errors point at theirs
+    let content = String::from_utf8(bytes).map_err(|e| {
+        let valid = &e.as_bytes()[..e.utf8_error().valid_up_to()];
+        let valid = str::from_utf8(valid).unwrap_or_default();
+        let line_start = valid.rfind('\n').map_or(0, |newline| newline + 1);
+        Error::ConfigEncoding {
+            path: config_path.to_path_buf(),
+            line: valid.matches('\n').count() + 1,
+            col: valid[line_start..].chars().count() + 1,
+        }
+    })?;
+    serde_json::from_str(&content).map_err(|e| Error::ConfigParse {
+        path: config_path.to_path_buf(),
+        line: e.line(),
+        col: e.column(),
+        source: e,
+    })
+}
+
+// Old field names that still load through serde aliases, for --strict;
+// a file that can't be read or parsed is reported when it is loaded instead
+fn deprecated_fields(config_path: &Path) -> Vec<String> {
+    let Ok(content) = fs::read_to_string(config_path) else {
+        return Vec::new();
+    };
+    let Ok(config) = serde_json::from_str::<serde_json::Value>(&content) else {
+        return Vec::new();
+    };
+    let mut messages = Vec::new();
+    if config.get("menu_title").is_some() {
+        messages.push("menu_title is deprecated; use prompt".to_string());
+    }
+    if let Some(flows) = config.get("flows").and_then(|flows| flows.as_object()) {
+        for (name, flow) in flows {
+            if flow.get("title").is_some() {
+                messages.push(format!("flows.{}.title is deprecated; use prompt", name));
+            }
+        }
+    }
+    messages
+}
+
+// Flip the enabled flag of the one entry whose label or key is `name`
+fn set_entry_enabled(config_path: &Path, name: &str, enabled: bool) -> io::Result<()> {
+    let mut config = read_config(config_path)?;
+    let mut matches: Vec<&mut MenuEntryConfig> = config
+        .entries
+        .iter_mut()
+        .chain(config.flows.values_mut().flat_map(|flow| &mut flow.entries))
+        .filter(|entry| entry.label == name || entry.key == name)
+        .collect();
+
+    // An exact label beats a key shared with other entries
+    if matches.len() > 1 && matches.iter().any(|entry| entry.label == name) {
+        matches.retain(|entry| entry.label == name);
+    }
+    match matches.as_mut_slice() {
+        [entry] => entry.enabled = enabled,
+        [] => {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No entry with label or key '{}'", name),
+            ))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' matches {} entries; use the label instead", name, matches.len()),
+            ))
+        }
+    }
+
+    write_config(&config, config_path)
+}
+
+// Make the cycled theme at `index` in `themes` the theme of the main menu, or
+// of `flow`, in the config file; it is copied as written, so a relative path
+// stays relative to the config
+fn save_theme(config_path: &Path, flow: Option<&str>, index: usize) -> io::Result<()> {
+    let mut config = read_config(config_path)?;
+    let theme = config.themes.get(index).cloned().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "The config's themes changed while the menu was open")
+    })?;
+    match flow {
+        Some(name) => {
+            let flow = config.flows.get_mut(name).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("Unknown flow '{}'", name))
+            })?;
+            flow.theme = Some(theme);
+        }
+        None => config.theme = Some(theme),
+    }
+    let json = serde_json::to_string_pretty(&config).map_err(io::Error::other)?;
+    write_atomic(config_path, json.as_bytes())
+}
+
+// What happened after an entry was picked
+enum Activation {
+    // Something was run
+    Done,
+    // A follow-up menu was cancelled; show the same menu again
+    Back,
+    // Switch to the named flow menu
+    Goto(String),
+}
+
+// Carry out a picked entry, asking follow-up questions where needed
+fn activate(entry: &MenuEntry, menu: &Menu, launcher: &Launcher) -> Result<Activation, Error> {
+    if let Some(next) = &entry.next {
+        return Ok(Activation::Goto(next.clone()));
+    }
+
+    if entry.action == Some(MetaAction::History) {
+        return show_output_history(menu, launcher);
+    }
+
+    if entry.inline {
+        return match &entry.item {
+            Some(item) => apply_source_action(entry, &item.action, launcher),
+            None => Ok(Activation::Back),
+        };
+    }
+
+    // Argument-choice entries ask for their argument first;
+    // cancelling that menu goes back to the main one
+    if let Some(choices) = entry.choice_menu(menu) {
+        return match choices.display_with_rofi()? {
+            Some(Choice::Entry(choice)) => launch_confirmed(choice, &choices, launcher),
+            _ => Ok(Activation::Back),
+        };
+    }
+
+    // Source rows act directly (e.g. copy back to the clipboard);
+    // cancelling returns to the main menu like argument choices
+    // The refresh key runs the source again, past its cache, and redraws the rows
+    if let Some(source) = &entry.source {
+        let mut refresh = launcher.refresh_sources;
+        loop {
+            let mut items = cache::items(source, entry.cache, refresh)?;
+            let dropped = limit_items(&entry.label, &mut items, entry.limit);
+            let mut rows: Vec<String> = items.iter().map(|item| item.display.clone()).collect();
+            if dropped > 0 {
+                rows.push(more_rows_label(dropped));
+            }
+            refresh = false;
+            match menu.choose_or_key(&entry.label, &rows, Some(&menu.refresh_key), entry.backend())? {
+                Picked::Row(index) if index < items.len() => {
+                    return apply_source_action(entry, &items[index].action, launcher);
+                }
+                // The "more not listed" row; show the rows again
+                Picked::Row(_) => {}
+                Picked::Key => refresh = true,
+                Picked::Cancelled => return Ok(Activation::Back),
+            }
+        }
+    }
+
+    launch_confirmed(entry, menu, launcher)
+}
+
+// Do what a picked source row says
+fn apply_source_action(entry: &MenuEntry, action: &SourceAction, launcher: &Launcher) -> Result<Activation, Error> {
+    match action {
+        SourceAction::Run(argv) => launcher.start(entry, argv, None).map(|()| Activation::Done),
+        // Settings picked from a source apply to what is launched next
+        SourceAction::SetEnv(name, value) => {
+            envfile::set(name, value);
+            Ok(Activation::Back)
+        }
+    }
+}
+
+// Browse captured outputs, newest first; picking one shows it in full
+// with the option to run the same command again
+fn show_output_history(menu: &Menu, launcher: &Launcher) -> Result<Activation, Error> {
+    let log = OutputLog::load();
+    if log.outputs.is_empty() {
+        notify("Output history", "No captured output yet");
+        return Ok(Activation::Back);
+    }
+
+    let records: Vec<&OutputRecord> = log.outputs.iter().rev().collect();
+    let rows: Vec<String> = records
+        .iter()
+        .map(|record| {
+            let failed = match record.status {
+                Some(0) => String::new(),
+                Some(code) => format!(" (exit {})", code),
+                None => " (killed)".to_string(),
+            };
+            format!(
+                "{}  {}{}  {}",
+                history::format_timestamp(record.timestamp),
+                record.label,
+                failed,
+                record.output.lines().next().unwrap_or_default()
+            )
+        })
+        .collect();
+    let Some(index) = menu.choose_with_rofi("Output history", &rows, None)? else {
+        return Ok(Activation::Back);
+    };
+
+    let record = records[index];
+    let mut rows = vec!["↻ Run again".to_string()];
+    rows.extend(record.output.lines().map(str::to_string));
+    match menu.choose_with_rofi(&record.label, &rows, None)? {
+        Some(0) => launcher
+            .capture(&record.label, &record.argv, record.cwd.as_deref(), &LaunchEnv::default())
+            .map(|()| Activation::Done),
+        _ => Ok(Activation::Back),
+    }
+}
+
+// The Alt+key of a dual_activation entry: run the command to completion and
+// show what it printed, which also lands in the output history
+fn run_in_foreground(entry: &MenuEntry, menu: &Menu, launcher: &Launcher) -> Result<Activation, Error> {
+    if entry.options.confirm && !menu.confirm(&entry.label, entry.backend())? {
+        return Ok(Activation::Back);
+    }
+    launcher.launch_as(entry, true)?;
+    if !launcher.runs_commands() {
+        return Ok(Activation::Done);
+    }
+    if let Some(record) = OutputLog::load().outputs.pop() {
+        let mut rows: Vec<String> = record.output.lines().map(str::to_string).collect();
+        if let Some(code) = record.status.filter(|&code| code != 0) {
+            rows.insert(0, format!("(exit {})", code));
+        }
+        menu.choose_with_rofi(&record.label, &rows, entry.backend())?;
+    }
+    Ok(Activation::Done)
+}
+
+// Launch an entry, first asking for confirmation when it wants that
+fn launch_confirmed(entry: &MenuEntry, menu: &Menu, launcher: &Launcher) -> Result<Activation, Error> {
+    if entry.options.confirm && !menu.confirm(&entry.label, entry.backend())? {
+        return Ok(Activation::Back);
+    }
+    launcher.launch(entry).map(|()| Activation::Done)
+}
+
+// Show menus until something is run or the user cancels
+fn run_menus(config: &Config, start: Menu, flow: Option<String>, launcher: &Launcher) -> Result<(), Error> {
+    let mut menu = start;
+    let mut flow = flow;
+    loop {
+        menu.fill_inline_sources(launcher.refresh_sources);
+        let next = {
+            // Handle keyboard shortcut detection
+            let (entry, activation) = match menu.display_with_rofi()? {
+                Some(Choice::Entry(entry)) => (entry, activate(entry, &menu, launcher)?),
+                Some(Choice::Foreground(entry)) => (entry, run_in_foreground(entry, &menu, launcher)?),
+                Some(Choice::Custom(command)) => return run_custom_command(&command, &menu, launcher),
+                None => return Err(Error::Cancelled),
+            };
+            match activation {
+                Activation::Done => {
+                    // Nothing was actually launched
+                    if !launcher.runs_commands() {
+                        return Ok(());
+                    }
+                    // Source rows change between runs, so they aren't worth repeating
+                    if !entry.inline {
+                        record_launch(entry, flow.as_deref());
+                    }
+                    // Repeatable entries keep the menu open for the next press
+                    if entry.options.repeatable {
+                        continue;
+                    }
+                    return Ok(());
+                }
+                Activation::Back => continue,
+                Activation::Goto(name) => name,
+            }
+        };
+        menu = Menu::for_flow(config, &next, Some(&menu.style))?;
+        flow = Some(next);
+    }
+}
+
+// Run text typed into the menu like an entry with that command, with the
+// global launch settings, if custom_command_pattern allows it
+fn run_custom_command(command: &str, menu: &Menu, launcher: &Launcher) -> Result<(), Error> {
+    if let Some(pattern) = &menu.custom_pattern {
+        if !pattern.is_match(command) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("'{}' doesn't match custom_command_pattern", command),
+            )
+            .into());
+        }
+    }
+    let mut custom = Menu::new(menu.style.clone());
+    let label = format!("{}{}", CUSTOM_COMMAND_LABEL, command);
+    let entry = custom.add_entry(' ', MenuEntryConfig::new("", &label, command));
+    launcher.launch(entry)?;
+    if launcher.runs_commands() {
+        record_launch(entry, None);
+    }
+    Ok(())
+}
+
+// Prefix of the label typed commands are recorded under in the history
+const CUSTOM_COMMAND_LABEL: &str = "$ ";
+
+// custom_command_pattern must match the whole command, not just part of it
+fn custom_command_regex(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| {
+        Error::ConfigInvalid(format!("Invalid custom_command_pattern: {}", e))
+    })
+}
+
+fn instance_regex(pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|e| Error::ConfigInvalid(format!("Invalid match: {}", e)))
+}
+
+// Remember a launch for usage statistics and --repeat-last
+fn record_launch(entry: &MenuEntry, flow: Option<&str>) {
+    // Usage statistics are nice to have, never worth failing a launch over
+    if let Err(e) = History::load().record(&entry.label, entry.key, flow) {
+        eprintln!("Could not record launch history: {}", e);
+    }
+}
+
+// The menu holding the last launched entry and the entry's index,
+// if an entry with that label and key is still there
+fn last_launched(config: &Config) -> io::Result<Option<(Menu, Option<String>, usize)>> {
+    let history = History::load();
+    let Some(last) = history.launches.last() else {
+        return Ok(None);
+    };
+    let menu = match &last.menu {
+        Some(flow) if !config.flows.contains_key(flow) => return Ok(None),
+        Some(flow) => Menu::for_flow(config, flow, None)?,
+        None => Menu::from_config(config)?,
+    };
+    let index = menu.entries.iter().position(|entry| {
+        entry.activatable && entry.label == last.label && entry.key.to_string() == last.key
+    });
+    Ok(index.map(|index| (menu, last.menu.clone(), index)))
+}
+
+// Activate an entry of `menu` as if its key had been pressed, following it
+// into submenus; false when a follow-up prompt (confirmation, source rows)
+// was cancelled and nothing happened
+fn activate_directly(
+    config: &Config,
+    menu: &Menu,
+    flow: Option<String>,
+    index: usize,
+    launcher: &Launcher,
+) -> Result<bool, Error> {
+    let entry = &menu.entries[index];
+    match activate(entry, menu, launcher)? {
+        Activation::Done => {
+            if launcher.runs_commands() {
+                record_launch(entry, flow.as_deref());
+            }
+            Ok(true)
+        }
+        Activation::Goto(next) => {
+            let next_menu = Menu::for_flow(config, &next, Some(&menu.style))?;
+            run_menus(config, next_menu, Some(next), launcher)?;
+            Ok(true)
+        }
+        Activation::Back => Ok(false),
+    }
+}
+
+// Show the menu described by `config`
+fn run(config: Config, args: &RunArgs) -> Result<(), Error> {
+    prepare_environment(&config)?;
+    if config.warn_missing_commands {
+        warn_missing_commands(&config);
+    }
+    profile::mark("environment");
+
+    // Resolve how selected commands will be started
+    let mut launcher = Launcher::from_config(&config)?;
+    launcher.dry_run = args.dry_run;
+    if !args.dry_run {
+        hooks::install(config.on_open.clone(), config.on_close.clone());
+    }
+    launcher.refresh_sources = args.refresh;
+    let target = match (args.output_fd, &args.output_file) {
+        (Some(fd), _) => Some(SelectionTarget::Fd(fd)),
+        (None, Some(path)) => Some(SelectionTarget::File(path.clone())),
+        (None, None) => None,
+    };
+    launcher.selection = match target {
+        Some(target) => Some(SelectionSink {
+            target,
+            format: args.output_format,
+            and_exec: args.and_exec,
+        }),
+        None if args.and_exec => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--and-exec only makes sense with --output-fd or --output-file",
+            )
+            .into())
+        }
+        None => None,
+    };
+
+    let mut config = config;
+    if args.raw_rofi {
+        config.raw_rofi = true;
+    }
+
+    // Narrow the starting menu down to the requested tags
+    if !args.tags.is_empty() {
+        let entries = match &args.flow {
+            Some(name) => config.flows.get_mut(name).map(|flow| &mut flow.entries),
+            None => Some(&mut config.entries),
+        };
+        if let Some(entries) = entries {
+            entries.retain(|entry| entry.tags.iter().any(|tag| args.tags.contains(tag)));
+            if entries.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No entries tagged {}", args.tags.join(", ")),
+                )
+                .into());
+            }
+        }
+    }
+
+    if args.repeat_last {
+        if let Some((menu, flow, index)) = last_launched(&config)? {
+            // A follow-up prompt was cancelled; offer the menu instead
+            if activate_directly(&config, &menu, flow, index, &launcher)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // Create menu
+    let mut menu = match &args.flow {
+        Some(name) => Menu::for_flow(&config, name, None)?,
+        None => Menu::from_config(&config)?,
+    };
+    // Only the first menu starts filtered; flows opened from it start empty
+    menu.query.clone_from(&args.query);
+    profile::mark("build menu");
+
+    // A which-key style prompt: whatever the keys mean is up to the caller
+    if args.emit_key {
+        return match menu.display_with_rofi()? {
+            Some(Choice::Entry(entry) | Choice::Foreground(entry)) if !entry.inline => {
+                println!("{}", entry.key);
+                Ok(())
+            }
+            _ => Err(Error::Cancelled),
+        };
+    }
+
+    if let Some(key) = args.run_key {
+        let index = menu
+            .key_index
+            .get(&key)
+            .copied()
+            .filter(|&index| menu.entries[index].activatable)
+            .ok_or_else(|| {
+                let menu_name = args.flow.as_deref().unwrap_or("the main menu");
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No entry in {} has the key '{}'", menu_name, key),
+                )
+            })?;
+        // A follow-up prompt was cancelled
+        if !activate_directly(&config, &menu, args.flow.clone(), index, &launcher)? {
+            return Err(Error::Cancelled);
+        }
+        return Ok(());
+    }
+
+    run_menus(&config, menu, args.flow.clone(), &launcher)
+}
+
+// Run the entry labelled `label` in the main menu or the flow `flow`, for
+// `rofi-keys ctl run`; follow-up prompts are shown as usual
+fn run_label(config: Config, label: &str, flow: Option<String>) -> Result<(), Error> {
+    prepare_environment(&config)?;
+    let launcher = Launcher::from_config(&config)?;
+    hooks::install(config.on_open.clone(), config.on_close.clone());
+    let menu = match &flow {
+        Some(name) => Menu::for_flow(&config, name, None)?,
+        None => Menu::from_config(&config)?,
+    };
+    let index = menu
+        .entries
+        .iter()
+        .position(|entry| entry.activatable && entry.label == label)
+        .ok_or_else(|| {
+            let menu_name = flow.as_deref().unwrap_or("the main menu");
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No entry in {} is labelled '{}'", menu_name, label),
+            )
+        })?;
+    // A follow-up prompt was cancelled
+    if !activate_directly(&config, &menu, flow, index, &launcher)? {
+        return Err(Error::Cancelled);
+    }
+    Ok(())
+}
+
+// `rofi-keys daemon`: the config it serves, replaced on reload
+struct Daemon {
+    config_path: PathBuf,
+    preset: Option<Preset>,
+    config: Mutex<Config>,
+    // Held while a menu, or a run's follow-up prompts, are open
+    busy: Mutex<()>,
+}
+
+// Answer ctl requests on the socket, and with `dbus` calls on the session bus,
+// until killed
+fn daemon(
+    config: Config,
+    config_path: &Path,
+    preset: Option<Preset>,
+    dbus: bool,
+) -> Result<(), Error> {
+    #[cfg(not(feature = "dbus"))]
+    if dbus {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "This rofi-keys was built without D-Bus support (cargo build --features dbus)",
+        )
+        .into());
+    }
+    // Taken before listening, so a second daemon stops with a clear error
+    #[cfg(feature = "dbus")]
+    let bus = dbus.then(dbus::register).transpose()?;
+
+    let daemon = Arc::new(Daemon {
+        config_path: config_path.to_path_buf(),
+        preset,
+        config: Mutex::new(config),
+        busy: Mutex::new(()),
+    });
+    #[cfg(feature = "dbus")]
+    if let Some(bus) = bus {
+        eprintln!("Serving {} on the session bus", dbus::NAME);
+        let daemon = Arc::clone(&daemon);
+        thread::spawn(move || {
+            if let Err(e) = bus.serve(|request| daemon.handle(request)) {
+                output::error(format!("D-Bus service stopped: {}", e));
+            }
+        });
+    }
+    let socket = ctl::socket_path();
+    eprintln!("Listening on {}", socket.display());
+    ctl::serve(&socket, |request| daemon.handle(request))?;
+    Ok(())
+}
+
+impl Daemon {
+    fn handle(&self, request: ctl::Request) -> ctl::Response {
+        let result = match request {
+            ctl::Request::Show { menu } => self.exclusive(|config| {
+                let args = RunArgs {
+                    flow: menu,
+                    ..RunArgs::default()
+                };
+                run(config, &args)
+            }),
+            ctl::Request::Run { label, menu } => self.exclusive(|config| run_label(config, &label, menu)),
+            ctl::Request::Reload => self.reload(),
+            ctl::Request::List => return self.list(),
+        };
+        match result {
+            Ok(()) => ctl::Response::ok(),
+            Err(Error::Cancelled) => ctl::Response {
+                cancelled: true,
+                ..ctl::Response::ok()
+            },
+            Err(e) => ctl::Response::error(e.to_string()),
+        }
+    }
+
+    // Run `action` with the current config, unless a menu is open already:
+    // there is one rofi at a time, and a second `show` gets a busy error
+    fn exclusive(&self, action: impl FnOnce(Config) -> Result<(), Error>) -> Result<(), Error> {
+        let _busy = match self.busy.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "Busy: a menu is already open").into())
+            }
+        };
+        let config = self.config.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let result = action(config);
+        cache::wait_for_refreshes();
+        result
+    }
+
+    // Read the config again; a broken one leaves the current config in place
+    fn reload(&self) -> Result<(), Error> {
+        let config = match self.preset {
+            Some(preset) => preset.config(),
+            None => load_config(&self.config_path)?.config,
+        };
+        hooks::install(config.on_open.clone(), config.on_close.clone());
+        *self.config.lock().unwrap_or_else(PoisonError::into_inner) = config;
+        Ok(())
+    }
+
+    fn list(&self) -> ctl::Response {
+        let config = self.config.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let menu = match Menu::from_config(&config) {
+            Ok(menu) => menu,
+            Err(e) => return ctl::Response::error(e.to_string()),
+        };
+        let dump = MenuDump {
+            version: tree::DUMP_VERSION,
+            menu: MenuNode::from_menu(&menu),
+        };
+        ctl::Response {
+            menu: serde_json::to_value(&dump).ok(),
+            ..ctl::Response::ok()
+        }
+    }
+}
+
+// Load the config for `validate`, which must not create one as a side effect
+fn validate(config_path: &Path, preset: Option<Preset>) -> io::Result<()> {
+    let config = load_for_checking(config_path, preset)?;
+    prepare_environment(&config)?;
+    warn_missing_commands(&config);
+    println!("{}: {}", config_path.display(), output::ok("OK"));
+    Ok(())
+}
+
+// Load and validate a config without creating a default one when it is missing
+fn load_for_checking(config_path: &Path, preset: Option<Preset>) -> io::Result<Config> {
+    if preset.is_none() && !config_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist (run `rofi-keys init`)", config_path.display()),
+        ));
+    }
+    match preset {
+        Some(preset) => {
+            let mut config = preset.config();
+            resolve_references(&mut config)?;
+            validate_config(&config)?;
+            Ok(config)
+        }
+        None => Ok(load_config(config_path)?.config),
+    }
+}
+
+// Preflight for a config on a new machine: everything validate checks, plus a
+// line per entry with what would keep it from working
+fn check(config_path: &Path, preset: Option<Preset>) -> io::Result<()> {
+    let config = load_for_checking(config_path, preset)?;
+    prepare_environment(&config)?;
+    let mut problems = 0;
+    let mut warnings = 0;
+    let mut themes = Vec::new();
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+
+    let flows = config.flows.keys().map(|name| (Some(name.as_str()), name.as_str()));
+    for (flow, name) in std::iter::once((None, "main menu")).chain(flows) {
+        let menu = match flow {
+            Some(flow) => Menu::for_flow(&config, flow, None)?,
+            None => Menu::from_config(&config)?,
+        };
+        println!("{}", output::bold(name));
+        let slots = plan_slots(&menu.entries, menu.hint_key.is_some(), menu.cycle_theme_key.is_some());
+        for (index, entry) in menu.entries.iter().enumerate() {
+            let mut issues = Vec::new();
+            let command = entry.command_template.as_deref().unwrap_or(&entry.command);
+            if let Some(program) = checked_program(command) {
+                if find_in_path(&program).is_none() {
+                    issues.push(format!("'{}' not found on PATH", program));
+                }
+            }
+            if slots.overflow.contains(&index) {
+                issues.push(format!("no key binding (rofi has {} custom keys)", ROFI_CUSTOM_KEYS));
+            }
+            if slots.foreground_overflow.contains(&index) {
+                issues.push(format!("no custom key left for Alt+{}", key_binding(entry.key)));
+            }
+            problems += issues.len();
+            let status = if issues.is_empty() { output::ok("ok") } else { output::bad(&issues.join("; ")) };
+            println!("  [{}] {:<32} {}", output::bold(&entry.key.to_string()), entry.label, status);
+            // Lints are only warnings and don't fail the check
+            for found in lint::lint_command(command, wayland) {
+                if !entry.options.lint_ignore.iter().any(|id| id == found.id) {
+                    warnings += 1;
+                    println!("      {}", output::notice(&format!("{}: {}", found.id, found.message)));
+                }
+            }
+        }
+
+        // Keys, the hint key and Alt+keys all share rofi's custom key slots
+        let status = format!("{} of {} custom keys needed", slots.needed, ROFI_CUSTOM_KEYS);
+        if slots.needed <= ROFI_CUSTOM_KEYS {
+            println!("  {}", output::ok(&status));
+        } else {
+            println!("  {}", output::bad(&status));
+            if slots.hint_overflow {
+                problems += 1;
+                println!("  {}", output::bad("the hint key gets no custom key"));
+            }
+            if slots.theme_overflow {
+                problems += 1;
+                println!("  {}", output::bad("the theme key gets no custom key"));
+            }
+            let lost: Vec<String> = slots
+                .overflow
+                .iter()
+                .map(|&index| format!("[{}] {}", menu.entries[index].key, menu.entries[index].label))
+                .chain(slots.foreground_overflow.iter().map(|&index| {
+                    format!("Alt+{} {}", menu.entries[index].key, menu.entries[index].label)
+                }))
+                .collect();
+            if !lost.is_empty() {
+                println!("  lose their key: {}", lost.join(", "));
+            }
+            println!(
+                "  {}",
+                output::notice(
+                    "with \"show_key_hints\": false Enter still runs every entry; \
+                     or move some entries into a flow"
+                )
+            );
+        }
+        if let Some(theme) = &menu.style.theme {
+            if !themes.contains(theme) {
+                themes.push(theme.clone());
+            }
+        }
+    }
+
+    for theme in &themes {
+        let found = theme_exists(theme);
+        let status = if found { output::ok("ok") } else { output::bad("not found") };
+        println!("theme {}: {}", theme, status);
+        if !found {
+            problems += 1;
+        }
+    }
+
+    if warnings > 0 {
+        println!(
+            "{}",
+            output::notice(&format!("{} warning(s); silence one per entry with lint_ignore", warnings))
+        );
+    }
+    if problems > 0 {
+        return Err(io::Error::other(format!("{} problem(s) found", problems)));
+    }
+    println!("{}: {}", config_path.display(), output::ok("OK"));
+    Ok(())
+}
+
+// Report on the programs rofi-keys needs; missing optional ones are only noted
+fn doctor(config_path: &Path) -> io::Result<()> {
+    let mut problems = 0;
+    let mut check = |what: &str, found: bool, required: bool| {
+        let status = match (found, required) {
+            (true, _) => output::ok("ok"),
+            (false, true) => output::bad("MISSING"),
+            (false, false) => output::notice("not installed (optional)"),
+        };
+        println!("{:<16} {}", what, status);
+        if !found && required {
+            problems += 1;
+        }
+    };
+
+    // With a fallback_backend rofi is optional, but the fallback is not
+    let fallback = load_config(config_path)
+        .ok()
+        .and_then(|loaded| loaded.config.fallback_backend)
+        .and_then(|name| Fallback::parse(&name).ok());
+    let rofi = rofi_program();
+    check(&rofi, find_in_path(&rofi).is_some(), fallback.is_none());
+    if let Some(fallback) = fallback {
+        check(fallback.name(), fallback.installed(), find_in_path(&rofi).is_none());
+    }
+    check("notify-send", find_in_path("notify-send").is_some(), false);
+
+    if !config_path.exists() {
+        println!("{:<16} {} not created yet", "config", config_path.display());
+    } else {
+        match load_config(config_path).map(|loaded| loaded.config) {
+            Ok(config) => {
+                println!("{:<16} {} {}", "config", config_path.display(), output::ok("ok"));
+                // Programs behind the launch methods the config uses
+                let methods = config
+                    .entries
+                    .iter()
+                    .chain(config.flows.values().flat_map(|flow| &flow.entries))
+                    .filter_map(|entry| entry.options.exec_mode.as_deref())
+                    .chain(config.launch_method.as_deref());
+                let mut seen = Vec::new();
+                for method in methods.filter_map(|method| LaunchMethod::parse(method).ok()) {
+                    if seen.contains(&method) {
+                        continue;
+                    }
+                    seen.push(method);
+                    match method {
+                        LaunchMethod::Systemd => check("systemd-run", find_in_path("systemd-run").is_some(), true),
+                        LaunchMethod::Setsid => check("setsid", find_in_path("setsid").is_some(), true),
+                        LaunchMethod::Wm => match wm::detect() {
+                            Some(compositor) => check(compositor.name(), true, true),
+                            None => check("window manager", false, true),
+                        },
+                        LaunchMethod::Shell | LaunchMethod::Exec => {}
+                    }
+                }
+                problems += report_key_bindings(&config)?;
+            }
+            Err(e) => {
+                println!("{:<16} {}: {}", "config", config_path.display(), output::bad(&e.to_string()));
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        return Err(io::Error::other(format!("{} problem(s) found", problems)));
+    }
+    Ok(())
+}
+
+// Cross-check every menu's keys against rofi's own bindings and its custom key
+// slots, returning how many keys won't do anything when pressed
+fn report_key_bindings(config: &Config) -> io::Result<usize> {
+    let overrides = kb_overrides(config);
+    let rofi_bindings = kb::effective_bindings(&overrides);
+    let overridden_slots = kb::overridden_slots(&overrides);
+    let mut problems = 0;
+
+    println!("\n{}", output::bold("Key bindings"));
+    let flows = config.flows.keys().map(|name| (Some(name.as_str()), name.as_str()));
+    for (flow, name) in std::iter::once((None, "main menu")).chain(flows) {
+        let menu = match flow {
+            Some(flow) => Menu::for_flow(config, flow, None)?,
+            None => Menu::from_config(config)?,
+        };
+        let slots = plan_slots(&menu.entries, menu.hint_key.is_some(), menu.cycle_theme_key.is_some());
+        let bound = slots.entries.len()
+            + usize::from(slots.hint.is_some())
+            + usize::from(slots.theme.is_some())
+            + slots.foreground.len();
+        println!("{:<16} {} of {} custom keys used", name, bound, ROFI_CUSTOM_KEYS);
+
+        for (index, entry) in menu.entries.iter().enumerate() {
+            if !entry.activatable {
+                continue;
+            }
+            let binding = key_binding(entry.key);
+            let issue = if entry.shadowed {
+                // Deliberate at times, e.g. a disabled entry left in place
+                let first = &menu.entries[menu.key_index[&entry.key]];
+                output::notice(&format!("same key as {}; never bound", first.label))
+            } else if slots.overflow.contains(&index) {
+                problems += 1;
+                output::bad(&format!("no key binding (rofi has {} custom keys)", ROFI_CUSTOM_KEYS))
+            } else if let Some(action) = kb::action_for(&rofi_bindings, &binding) {
+                problems += 1;
+                output::bad(&format!("{} is also rofi's {}", binding, action))
+            } else if overridden_slots.contains(&(index + 1)) {
+                problems += 1;
+                output::bad(&format!("kb-custom-{} is also set in rofi_kb_overrides", index + 1))
+            } else if slots.foreground_overflow.contains(&index) {
+                problems += 1;
+                output::bad(&format!("no custom key left for Alt+{}", binding))
+            } else {
+                continue;
+            };
+            println!("  [{}] {:<32} {}", output::bold(&entry.key.to_string()), entry.label, issue);
+        }
+
+        // The hint and theme keys take the slots after the last entry, like show() binds them
+        let menu_keys = [
+            ("hint key", &menu.hint_key, slots.hint_overflow),
+            ("theme key", &menu.cycle_theme_key, slots.theme_overflow),
+        ];
+        for (what, key, overflow) in menu_keys {
+            let Some(key) = key else {
+                continue;
+            };
+            let binding = named_key_binding(key);
+            let clash = menu
+                .entries
+                .iter()
+                .take(ROFI_CUSTOM_KEYS)
+                .find(|entry| entry.activatable && !entry.shadowed && key_binding(entry.key) == binding);
+            let issue = if overflow {
+                Some("no custom key left for it".to_string())
+            } else if let Some(entry) = clash {
+                Some(format!("same key as {}", entry.label))
+            } else {
+                kb::action_for(&rofi_bindings, &binding)
+                    .map(|action| format!("{} is also rofi's {}", binding, action))
+            };
+            if let Some(issue) = issue {
+                problems += 1;
+                println!("  {:<9} {:<27} {}", what, key, output::bad(&issue));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+// One binding per menu that has one; menus without are reported on stderr
+fn menu_bindings(config: &Config, config_arg: Option<&Path>) -> Vec<MenuBinding> {
+    let mut base = "rofi-keys".to_string();
+    if let Some(path) = config_arg {
+        base.push_str(&format!(" --config {}", shell_quote(&path.display().to_string())));
+    }
+
+    let main = ("main menu".to_string(), config.binding.as_ref(), base.clone());
+    let flows = config.flows.iter().map(|(name, flow)| {
+        let command = format!("{} --menu {}", base, shell_quote(name));
+        (name.clone(), flow.binding.as_ref(), command)
+    });
+
+    let mut bindings = Vec::new();
+    for (menu, binding, command) in std::iter::once(main).chain(flows) {
+        match binding {
+            Some(binding) => bindings.push(MenuBinding {
+                menu,
+                binding: binding.clone(),
+                command,
+            }),
+            None => output::warn(format!("{} has no binding field; skipped", menu)),
+        }
+    }
+    bindings
+}
+
+// Open the config in the user's editor and check it once they are done
+fn edit_config(config_path: &Path) -> io::Result<()> {
+    if !config_path.exists() {
+        write_config(&create_default_config(), config_path)?;
+    }
+
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let mut argv = split_words(&editor);
+    argv.push(config_path.display().to_string());
+
+    let status = Command::new(&argv[0]).args(&argv[1..]).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", argv[0], status)));
+    }
+    validate(config_path, None)
+}
+
+fn main() -> ExitCode {
+    // Parse command-line arguments using Clap
+    let cli = Cli::parse();
+    output::init(cli.color);
+    output::set_verbose(cli.verbose);
+    match try_main(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(&e);
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+// Tell the user what went wrong: on stderr from a terminal, otherwise (started
+// from a keybinding) in a rofi dialog for config mistakes and a notification
+// for everything else
+fn report_error(e: &Error) {
+    // Nothing went wrong; the exit status says it all
+    if matches!(e, Error::Cancelled) {
+        return;
+    }
+    output::error(e);
+    if io::stderr().is_terminal() {
+        return;
+    }
+    if e.is_config() {
+        let shown = envfile::command(rofi_program())
+            .args(["-e", &format!("rofi-keys: {}", e)])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if shown.is_ok() {
+            return;
         }
     }
+    notify(i18n::tr("Error"), &e.to_string());
+}
+
+fn try_main(cli: Cli) -> Result<(), Error> {
+    if cli.profile_startup {
+        profile::start();
+    }
+    if cli.help_all {
+        print!("{}", help::render());
+        return Ok(());
+    }
     
-    // Handle keyboard shortcut detection
-    if let Some(command) = menu.display_with_rofi()? {
-        execute_command(&command)?;
+    // Get the config path (custom or default); an explicit one never needs HOME
+    let explicit_config = cli.config.clone().or_else(|| {
+        env::var_os("ROFI_KEYS_CONFIG")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    });
+    let config_path = match explicit_config.clone() {
+        Some(path) => path,
+        None => get_default_config_path()?,
+    };
+    let config_origin = if cli.config.is_some() {
+        "--config"
+    } else if explicit_config.is_some() {
+        "$ROFI_KEYS_CONFIG"
+    } else {
+        "default"
+    };
+
+    if cli.strict {
+        for message in deprecated_fields(&config_path) {
+            output::warn(format!("{}: {}", config_path.display(), message));
+        }
+    }
+
+    // Global flags such as --config work anywhere, but the menu's own options
+    // only mean something without a subcommand or after `run`
+    if cli.command.is_some() && cli.run != RunArgs::default() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Menu options such as --dry-run go after `run`, or are used without a subcommand",
+        )
+        .into());
+    }
+    let command = match cli.command {
+        Some(command) => command,
+        None if cli.init => Commands::Init,
+        None if cli.setup => Commands::Setup,
+        None if cli.list => Commands::List { all: false },
+        None => Commands::Run(cli.run),
+    };
+    // The main menu's sources can start before the config is fully loaded;
+    // --refresh skips their cache and --flow opens another menu
+    if let Commands::Run(args) = &command {
+        if !args.refresh && args.flow.is_none() {
+            sources::allow_prefetch();
+        }
+    }
+
+    // Subcommands that work on the config file itself
+    match command {
+        Commands::Init => {
+            let default_config = create_default_config();
+            write_config(&default_config, &config_path)?;
+            println!("Default configuration initialized at {}", config_path.display());
+            return Ok(());
+        }
+        Commands::Setup => {
+            match setup::run()? {
+                Some(config) => write_config(&config, &config_path)?,
+                None => println!("Setup cancelled; nothing was written"),
+            }
+            return Ok(());
+        }
+        Commands::Validate => return Ok(validate(&config_path, cli.preset)?),
+        Commands::Check => return Ok(check(&config_path, cli.preset)?),
+        Commands::Doctor => return Ok(doctor(&config_path)?),
+        Commands::Edit => return Ok(edit_config(&config_path)?),
+        Commands::Enable { entry } => return Ok(set_entry_enabled(&config_path, &entry, true)?),
+        Commands::Disable { entry } => return Ok(set_entry_enabled(&config_path, &entry, false)?),
+        Commands::Cache {
+            action: CacheCommand::Clear,
+        } => {
+            let removed = cache::clear()?;
+            println!("Removed {} cached source(s)", removed);
+            return Ok(());
+        }
+        Commands::ConfigPath { all } => {
+            if all {
+                // A config that doesn't exist yet isn't created just to list it
+                let sources = if config_path.exists() {
+                    load_config(&config_path)?.sources
+                } else {
+                    vec![config_path.clone()]
+                };
+                for (index, source) in sources.iter().enumerate() {
+                    let (origin, missing) = match index {
+                        0 => (config_origin, ", not created yet"),
+                        _ => ("named in the config", ", missing"),
+                    };
+                    let missing = if source.exists() { "" } else { missing };
+                    println!("{}  {}  ({}{})", index + 1, source.display(), origin, missing);
+                }
+            } else {
+                println!("{}", config_path.display());
+            }
+            return Ok(());
+        }
+        Commands::CaptureEnv { file } => {
+            let file = file.unwrap_or_else(|| default_env_file(&config_path));
+            let count = envfile::capture(&file)?;
+            println!("Saved {} variables to {}", count, file.display());
+            return Ok(());
+        }
+        // The daemon has the config; the client only needs its socket
+        Commands::Ctl { action } => {
+            let request = match action {
+                CtlCommand::Show { menu } => ctl::Request::Show { menu },
+                CtlCommand::Run { label, menu } => ctl::Request::Run { label, menu },
+                CtlCommand::Reload => ctl::Request::Reload,
+                CtlCommand::List => ctl::Request::List,
+            };
+            let response = ctl::send(&ctl::socket_path(), &request)?;
+            if let Some(error) = response.error {
+                return Err(io::Error::other(error).into());
+            }
+            if response.cancelled {
+                return Err(Error::Cancelled);
+            }
+            if let Some(menu) = response.menu {
+                println!("{}", serde_json::to_string_pretty(&menu).map_err(io::Error::other)?);
+            }
+            return Ok(());
+        }
+        _ => {}
     }
     
-    Ok(())
+    // Load configuration; presets don't need (or create) a config file
+    let config = match cli.preset {
+        Some(preset) => Ok(preset.config()),
+        // Showing the menu for the first time offers the setup wizard
+        // before falling back to the default config
+        None if matches!(command, Commands::Run(_)) && !config_path.exists() => {
+            first_run_config(&config_path).map(|loaded| loaded.config)
+        }
+        None => load_config(&config_path).map(|loaded| loaded.config),
+    };
+    let config = match config {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            output::error(format!("loading config from {}: {}", config_path.display(), e));
+            // Use default config if loading fails
+            create_default_config()
+        }
+    };
+    
+    match command {
+        Commands::Run(args) => {
+            let result = run(config, &args);
+            cache::wait_for_refreshes();
+            result
+        }
+        Commands::List { all } => {
+            let mut config = config;
+            if !all {
+                config.entries.retain(|entry| entry.enabled);
+                for flow in config.flows.values_mut() {
+                    flow.entries.retain(|entry| entry.enabled);
+                }
+            }
+            let json = serde_json::to_string_pretty(&config).map_err(io::Error::other)?;
+            println!("{}", json);
+            Ok(())
+        }
+        Commands::Stats { since, format } => {
+            let since = match since {
+                Some(span) => history::now().saturating_sub(history::parse_span(&span)?),
+                None => 0,
+            };
+            // Launches are recorded per menu; submenu groups are flows by now
+            let current: Vec<(Option<&str>, &str)> = config
+                .entries
+                .iter()
+                .map(|e| (None, e.label.as_str()))
+                .chain(config.flows.iter().flat_map(|(name, flow)| {
+                    flow.entries.iter().map(move |e| (Some(name.as_str()), e.label.as_str()))
+                }))
+                .collect();
+            let rows = history::stats(&History::load(), since, &current);
+            print!("{}", history::render_stats(&rows, format)?);
+            Ok(())
+        }
+        Commands::Cheatsheet { format } => {
+            let tree = MenuNode::from_menu(&Menu::from_config(&config)?);
+            print!("{}", cheatsheet::render(&tree, format));
+            Ok(())
+        }
+        Commands::Bindings { wm } => {
+            let bindings = menu_bindings(&config, explicit_config.as_deref());
+            print!("{}", bindings::render(wm, &bindings));
+            Ok(())
+        }
+        Commands::Daemon { dbus } => daemon(config, &config_path, cli.preset, dbus),
+        Commands::Dump { format } => {
+            let tree = MenuNode::from_menu(&Menu::from_config(&config)?);
+            match format {
+                DumpFormat::Json => {
+                    let dump = MenuDump {
+                        version: tree::DUMP_VERSION,
+                        menu: tree,
+                    };
+                    let json = serde_json::to_string_pretty(&dump).map_err(io::Error::other)?;
+                    println!("{}", json);
+                }
+                DumpFormat::Markdown => {
+                    print!("{}", cheatsheet::render(&tree, CheatsheetFormat::Markdown));
+                }
+            }
+            Ok(())
+        }
+        Commands::Init
+        | Commands::Setup
+        | Commands::Validate
+        | Commands::Check
+        | Commands::Doctor
+        | Commands::Edit
+        | Commands::Enable { .. }
+        | Commands::Disable { .. }
+        | Commands::Cache { .. }
+        | Commands::ConfigPath { .. }
+        | Commands::CaptureEnv { .. }
+        | Commands::Ctl { .. } => unreachable!("handled before the config is loaded"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::{ExitStatus, Output};
+    use std::rc::Rc;
+
+    // A main menu with one entry per key, in order
+    fn menu_with_keys(keys: &[&str]) -> Menu {
+        let entries: Vec<serde_json::Value> = keys
+            .iter()
+            .enumerate()
+            .map(|(index, key)| serde_json::json!({ "key": key, "label": format!("Entry {}", index), "command": "true" }))
+            .collect();
+        let config: Config = serde_json::from_value(serde_json::json!({ "entries": entries })).unwrap();
+        Menu::from_config(&config).unwrap()
+    }
+
+    #[test]
+    fn key_index_keeps_the_first_of_duplicate_keys() {
+        let menu = menu_with_keys(&["a", "b", "a", "a"]);
+        assert_eq!(menu.key_index[&'a'], 0);
+        assert_eq!(menu.key_index[&'b'], 1);
+        let shadowed: Vec<bool> = menu.entries.iter().map(|entry| entry.shadowed).collect();
+        assert_eq!(shadowed, [false, false, true, true]);
+        // Shadowed entries get no slot, so their exit codes never come back
+        let slots = plan_slots(&menu.entries, false, false);
+        assert_eq!(slots.entries, [(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn key_index_covers_the_first_and_last_slot() {
+        let keys: Vec<String> = ('a'..='t').map(String::from).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let menu = menu_with_keys(&keys);
+        let slots = plan_slots(&menu.entries, false, false);
+        assert_eq!(slots.entries.first(), Some(&(0, menu.key_index[&'a'])));
+        assert_eq!(slots.entries.last(), Some(&(ROFI_CUSTOM_KEYS - 1, menu.key_index[&'s'])));
+        // The 20th key has no kb-custom left
+        assert_eq!(slots.overflow, [menu.key_index[&'t']]);
+    }
+
+    #[test]
+    fn custom_slot_maps_exit_codes_back_to_indices() {
+        for exit_code in 10..=28 {
+            assert_eq!(custom_slot(exit_code), Some(exit_code as usize - 10));
+        }
+        for exit_code in [-1, 0, 1, 9, 29, 65] {
+            assert_eq!(custom_slot(exit_code), None, "exit code {}", exit_code);
+        }
+    }
+
+    #[test]
+    fn rofi_args_leave_out_theme_without_one() {
+        let menu = menu_with_keys(&["a"]);
+        assert_eq!(menu.style.theme, None);
+        let args = menu.rofi_args("Run", &menu.style, None, None, &[], false);
+        assert!(!args.iter().any(|arg| arg == "-theme" || arg == "-theme-str"), "{:?}", args);
+        assert_eq!(args[..4], ["-dmenu", "-p", "Run", "-no-fork"]);
+    }
+
+    #[test]
+    fn rofi_args_pass_theme_and_theme_str() {
+        let menu = menu_with_keys(&["a"]);
+        let style = MenuStyle {
+            theme: Some("nord.rasi".to_string()),
+            ..menu.style.clone()
+        };
+        let args = menu.rofi_args("Run", &style, Some("window { width: 40%; }"), None, &[], false);
+        let theme = args.iter().position(|arg| arg == "-theme").unwrap();
+        assert_eq!(args[theme + 1], "nord.rasi");
+        let theme_str = args.iter().position(|arg| arg == "-theme-str").unwrap();
+        assert_eq!(args[theme_str + 1], "window { width: 40%; }");
+    }
+
+    #[test]
+    fn key_column_pads_labels_to_the_widest() {
+        let mut menu = menu_with_keys(&["f", "t"]);
+        menu.entries[0].label = "Firefox".to_string();
+        menu.entries[1].label = "Terminal emulator".to_string();
+        menu.key_position = KeyPosition::Column;
+        menu.raw = true;
+        let input = menu.generate_rofi_input(&menu.rows());
+        assert_eq!(input, "Firefox            f\nTerminal emulator  t");
+
+        menu.raw = false;
+        let input = menu.generate_rofi_input(&menu.rows());
+        assert_eq!(input.lines().next(), Some("<span font_family=\"monospace\">Firefox            f</span>"));
+    }
+
+    #[test]
+    fn rofi_input_for_1000_entries_matches_plain_formatting() {
+        let keys: Vec<String> = (0..1000).map(|index| char::from_u32(0x4e00 + index).unwrap().to_string()).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let menu = menu_with_keys(&keys);
+        let input = menu.generate_rofi_input(&menu.rows());
+        // What the rows looked like when built with map/collect/join
+        let expected: Vec<String> = menu
+            .entries
+            .iter()
+            .map(|entry| format!("[{}] {}", entry.key, entry.label))
+            .collect();
+        assert_eq!(input, expected.join("\n"));
+    }
+
+    #[test]
+    fn expand_path_needs_home_only_for_tilde() {
+        assert_eq!(expand_path_with("~/themes/nord.rasi", Some("/home/me")), "/home/me/themes/nord.rasi");
+        // Without HOME the path is used as written
+        assert_eq!(expand_path_with("~/themes/nord.rasi", None), "~/themes/nord.rasi");
+        assert_eq!(expand_path_with("/etc/nord.rasi", None), "/etc/nord.rasi");
+        // Only a leading ~/ is the home directory
+        assert_eq!(expand_path_with("a/~/b", Some("/home/me")), "a/~/b");
+    }
+
+    #[test]
+    fn default_config_path_prefers_xdg_config_home() {
+        let path = |config_home, home| default_config_path_with(config_home, home).ok();
+        assert_eq!(
+            path(Some("/xdg"), Some("/home/me")),
+            Some(PathBuf::from("/xdg/rofi-keys/config.json"))
+        );
+        assert_eq!(
+            path(None, Some("/home/me")),
+            Some(PathBuf::from("/home/me/.config/rofi-keys/config.json"))
+        );
+        assert_eq!(
+            path(Some(""), Some("/home/me")),
+            Some(PathBuf::from("/home/me/.config/rofi-keys/config.json"))
+        );
+        assert_eq!(path(Some("/xdg"), None), Some(PathBuf::from("/xdg/rofi-keys/config.json")));
+        assert_eq!(path(None, None), None);
+        assert_eq!(path(Some(""), Some("")), None);
+    }
+
+    #[test]
+    fn save_theme_writes_the_cycled_theme_as_written() {
+        let path = env::temp_dir().join(format!("rofi-keys-theme-{}.json", std::process::id()));
+        let config = serde_json::json!({
+            "entries": [{ "key": "f", "label": "Files", "command": "true" }],
+            "themes": ["Arc-Dark", "themes/mine.rasi"],
+            "flows": { "work": { "entries": [] } }
+        });
+        fs::write(&path, config.to_string()).unwrap();
+
+        save_theme(&path, None, 1).unwrap();
+        save_theme(&path, Some("work"), 0).unwrap();
+        let saved = read_config(&path).unwrap();
+        assert_eq!(saved.theme.as_deref(), Some("themes/mine.rasi"));
+        assert_eq!(saved.flows["work"].theme.as_deref(), Some("Arc-Dark"));
+        assert!(save_theme(&path, Some("missing"), 0).is_err());
+        assert!(save_theme(&path, None, 2).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn invalid_settings_are_config_errors() {
+        let path = env::temp_dir().join(format!("rofi-keys-invalid-{}.json", std::process::id()));
+        let configs = [
+            serde_json::json!({
+                "key_style": "loud",
+                "entries": [{ "key": "f", "label": "Files", "command": "true" }]
+            }),
+            serde_json::json!({
+                "entries": [{ "key": "f", "label": "Files", "command": "true", "nice": 40 }]
+            }),
+            serde_json::json!({
+                "entries": [{ "key": "f", "label": "Files", "ref": "Nowhere" }]
+            }),
+        ];
+        for config in configs {
+            fs::write(&path, config.to_string()).unwrap();
+            let Err(error) = load_config(&path) else {
+                panic!("{} loaded", config);
+            };
+            assert!(matches!(error, Error::ConfigInvalid(_)), "{:?}", error);
+            assert_eq!(error.exit_code(), 2);
+        }
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn refs_reach_entries_in_other_menus() {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "entries": [
+                { "key": "f", "label": "Firefox", "command": "firefox", "icon": "firefox" },
+                { "key": "m", "label": "Mail", "ref": "work/Mail" },
+                { "key": "w", "label": "Work", "next": "work" }
+            ],
+            "flows": { "work": { "entries": [
+                { "key": "m", "label": "Mail", "command": "thunderbird" },
+                { "key": "b", "label": "Browser", "ref": "Firefox" },
+                { "key": "i", "label": "Inbox", "ref": "Mail" }
+            ] } }
+        }))
+        .unwrap();
+        resolve_references(&mut config).unwrap();
+
+        assert_eq!(config.entries[1].command, "thunderbird");
+        let work = &config.flows["work"].entries;
+        assert_eq!(work[1].command, "firefox");
+        assert_eq!(work[1].icon.as_deref(), Some("firefox"));
+        assert_eq!(work[1].label, "Browser");
+        // Its own menu comes first
+        assert_eq!(work[2].command, "thunderbird");
+    }
+
+    #[test]
+    fn an_alias_keeps_what_it_sets_itself() {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "entries": [
+                {
+                    "key": "r", "label": "Reboot", "command": "systemctl reboot",
+                    "icon": "system-reboot", "confirm": true, "cwd": "/",
+                    "env": { "LANG": "C", "MODE": "target" }
+                },
+                {
+                    "key": "b", "label": "Restart", "ref": "Reboot",
+                    "icon": "view-refresh", "cwd": "/tmp", "env": { "MODE": "alias" }
+                }
+            ]
+        }))
+        .unwrap();
+        resolve_references(&mut config).unwrap();
+
+        let alias = &config.entries[1];
+        assert_eq!(alias.command, "systemctl reboot");
+        assert_eq!(alias.icon.as_deref(), Some("view-refresh"));
+        assert_eq!(alias.options.cwd.as_deref(), Some("/tmp"));
+        assert!(alias.options.confirm);
+        let env: Vec<_> = alias.options.env.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(env, [("LANG", "C"), ("MODE", "alias")]);
+    }
+
+    #[test]
+    fn refs_found_in_several_menus_or_in_a_cycle_are_rejected() {
+        let config = |entries: serde_json::Value| -> Config {
+            serde_json::from_value(serde_json::json!({
+                "entries": entries,
+                "flows": {
+                    "a": { "entries": [{ "key": "x", "label": "Term", "command": "xterm" }] },
+                    "b": { "entries": [
+                        { "key": "x", "label": "Term", "command": "foot" },
+                        { "key": "y", "label": "Loop", "ref": "/Start" }
+                    ] }
+                }
+            }))
+            .unwrap()
+        };
+        let error = |mut config: Config| resolve_references(&mut config).unwrap_err().to_string();
+
+        let start = serde_json::json!({ "key": "s", "label": "Start", "command": "true" });
+        let ambiguous = config(serde_json::json!([start, { "key": "t", "label": "T", "ref": "Term" }]));
+        assert!(error(ambiguous).contains("several menus"));
+        let mut named = config(serde_json::json!([start, { "key": "t", "label": "T", "ref": "b/Term" }]));
+        resolve_references(&mut named).unwrap();
+        assert_eq!(named.entries[1].command, "foot");
+        let cycle = config(serde_json::json!([{ "key": "s", "label": "Start", "ref": "b/Loop" }]));
+        assert_eq!(error(cycle), "Ref cycle: Start -> b/Loop -> Start");
+    }
+
+    #[test]
+    fn prepare_environment_leaves_the_process_environment_alone() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "path": ["/opt/rofi-keys-test/bin"],
+            "entries": [{ "key": "f", "label": "Files", "command": "true" }]
+        }))
+        .unwrap();
+        let before = env::var_os("PATH");
+        // Each run starts over, as the daemon's do
+        prepare_environment(&config).unwrap();
+        prepare_environment(&config).unwrap();
+        assert_eq!(env::var_os("PATH"), before);
+
+        let path = envfile::var_os("PATH").unwrap();
+        let added = env::split_paths(&path).filter(|dir| dir.ends_with("rofi-keys-test/bin"));
+        assert_eq!(added.count(), 1);
+        let output = envfile::command("sh").args(["-c", "printf %s \"$PATH\""]).output().unwrap();
+        assert_eq!(output.stdout, path.as_encoded_bytes());
+        envfile::apply(&[]);
+    }
+
+    #[test]
+    fn config_sources_list_named_files_once_in_load_order() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "env_file": "/etc/rofi-keys/env",
+            "entries": [
+                { "key": "s", "label": "Snippets", "source_type": "snippets", "source_file": "/a/snippets.toml" },
+                { "key": "f", "label": "Files", "command": "true", "source_file": "/ignored.json" }
+            ],
+            "flows": { "work": { "entries": [
+                { "key": "x", "label": "Again", "source_type": "snippets", "source_file": "/a/snippets.toml" },
+                { "key": "y", "label": "More", "source_type": "snippets", "source_file": "/b/more.json" }
+            ] } }
+        }))
+        .unwrap();
+        let sources = config_sources(Path::new("/c/config.json"), &config);
+        let expected = ["/c/config.json", "/etc/rofi-keys/env", "/a/snippets.toml", "/b/more.json"];
+        assert_eq!(sources, expected.map(PathBuf::from));
+    }
+
+    #[test]
+    fn key_binding_names_shifted_symbols() {
+        let cases = [
+            ('?', "question"),
+            ('!', "exclam"),
+            ('@', "at"),
+            ('#', "numbersign"),
+            ('$', "dollar"),
+            ('%', "percent"),
+            ('^', "asciicircum"),
+            ('&', "ampersand"),
+            ('*', "asterisk"),
+            ('(', "parenleft"),
+            (')', "parenright"),
+            ('_', "underscore"),
+            ('+', "plus"),
+            ('{', "braceleft"),
+            ('}', "braceright"),
+            ('|', "bar"),
+            (':', "colon"),
+            ('"', "quotedbl"),
+            ('<', "less"),
+            ('>', "greater"),
+            ('~', "asciitilde"),
+            (' ', "space"),
+        ];
+        for (key, keysym) in cases {
+            assert_eq!(key_binding(key), keysym, "key {:?}", key);
+        }
+    }
+
+    #[test]
+    fn key_binding_passes_other_keys_through() {
+        for (key, binding) in [('f', "f"), ('F', "F"), ('7', "7"), ('é', "é")] {
+            assert_eq!(key_binding(key), binding);
+        }
+        assert_eq!(named_key_binding("?"), "question");
+        assert_eq!(named_key_binding("Control+h"), "Control+h");
+    }
+
+    #[test]
+    fn killed_menu_program_is_a_cancel() {
+        // A menu program that prints the start of an answer, then SIGKILLs itself
+        let script = "cat > /dev/null; printf 0; kill -9 $$";
+        let mut menu = menu_with_keys(&["a", "b"]);
+        menu.rofi_command = Some(format!("sh -c {}", shell_quote(script)));
+        match menu.show(false) {
+            Ok(Shown::Cancelled) => {}
+            Ok(_) => panic!("half an answer from a killed menu was taken"),
+            Err(e) => panic!("a killed menu failed with {}", e),
+        }
+    }
+
+    // What a RecordingExecutor was asked to start
+    #[derive(Debug, PartialEq)]
+    struct Started {
+        argv: Vec<String>,
+        cwd: Option<PathBuf>,
+        vars: Vec<(String, String)>,
+    }
+
+    // Records command lines instead of running them, sharing the log with the test
+    #[derive(Debug, Default)]
+    struct RecordingExecutor {
+        started: Rc<RefCell<Vec<Started>>>,
+    }
+
+    impl RecordingExecutor {
+        fn record(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) {
+            let vars = env.vars.into_iter().flatten().map(|(name, value)| (name.clone(), value.clone()));
+            self.started.borrow_mut().push(Started {
+                argv: argv.to_vec(),
+                cwd: cwd.map(Path::to_path_buf),
+                vars: vars.collect(),
+            });
+        }
+    }
+
+    impl Executor for RecordingExecutor {
+        fn spawn(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Result<(), Error> {
+            self.record(argv, cwd, env);
+            Ok(())
+        }
+
+        fn output(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Result<Output, Error> {
+            self.record(argv, cwd, env);
+            Ok(Output {
+                status: ExitStatus::from_raw(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            })
+        }
+
+        fn replace(&self, argv: &[String], cwd: Option<&Path>, env: &LaunchEnv<'_>) -> Error {
+            self.record(argv, cwd, env);
+            Error::Cancelled
+        }
+    }
+
+    // A launcher for `config` whose executor only records, and the log it records to
+    fn recording_launcher(config: &Config) -> (Launcher, Rc<RefCell<Vec<Started>>>) {
+        let executor = RecordingExecutor::default();
+        let started = Rc::clone(&executor.started);
+        let mut launcher = Launcher::from_config(config).unwrap();
+        launcher.executor = Box::new(executor);
+        (launcher, started)
+    }
+
+    #[test]
+    fn launcher_hands_argv_and_cwd_to_its_executor() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "launch_prefix": "firejail --profile=\"a b\"",
+            "cwd": "/srv",
+            "entries": [
+                { "key": "s", "label": "Shell", "command": "echo hi" },
+                { "key": "e", "label": "Exec", "command": "mpv 'My Video.mkv'", "exec_mode": "exec",
+                  "cwd": "/tmp", "sandbox": false, "env": { "MPV_HOME": "/opt/mpv" } }
+            ]
+        }))
+        .unwrap();
+        let menu = Menu::from_config(&config).unwrap();
+        let (launcher, started) = recording_launcher(&config);
+        for entry in &menu.entries {
+            launcher.launch(entry).unwrap();
+        }
+
+        let argv = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            *started.borrow(),
+            [
+                Started {
+                    argv: argv(&["firejail", "--profile=a b", "sh", "-c", "echo hi"]),
+                    cwd: Some(PathBuf::from("/srv")),
+                    vars: Vec::new(),
+                },
+                Started {
+                    argv: argv(&["mpv", "My Video.mkv"]),
+                    cwd: Some(PathBuf::from("/tmp")),
+                    vars: vec![("MPV_HOME".to_string(), "/opt/mpv".to_string())],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn dry_run_reaches_no_executor() {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "entries": [{ "key": "s", "label": "Shell", "command": "echo hi" }]
+        }))
+        .unwrap();
+        let menu = Menu::from_config(&config).unwrap();
+        let (mut launcher, started) = recording_launcher(&config);
+        launcher.dry_run = true;
+        launcher.launch(&menu.entries[0]).unwrap();
+        assert!(started.borrow().is_empty());
+    }
 }
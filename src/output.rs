@@ -0,0 +1,86 @@
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// When terminal output is colored, chosen with --color
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal and NO_COLOR is unset
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+const BOLD: &str = "1";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+/// Decide once, at startup, whether stdout and stderr get colors
+pub fn init(choice: ColorChoice) {
+    let no_color = env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let decide = |terminal: bool| match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => terminal && !no_color,
+    };
+    STDOUT_COLOR.store(decide(io::stdout().is_terminal()), Ordering::Relaxed);
+    STDERR_COLOR.store(decide(io::stderr().is_terminal()), Ordering::Relaxed);
+}
+
+// Callers pad text before painting it, since escape codes would throw off `{:<width$}`
+fn paint(code: &str, text: &str, enabled: &AtomicBool) -> String {
+    if enabled.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Keys and headings on stdout
+pub fn bold(text: &str) -> String {
+    paint(BOLD, text, &STDOUT_COLOR)
+}
+
+/// Things that passed a check
+pub fn ok(text: &str) -> String {
+    paint(GREEN, text, &STDOUT_COLOR)
+}
+
+/// Things that would keep rofi-keys from working
+pub fn bad(text: &str) -> String {
+    paint(RED, text, &STDOUT_COLOR)
+}
+
+/// Things worth knowing about that don't stop anything
+pub fn notice(text: &str) -> String {
+    paint(YELLOW, text, &STDOUT_COLOR)
+}
+
+/// Turn `verbose` messages on, for --verbose
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+/// Print a detail to stderr, only with --verbose
+pub fn verbose(message: impl Display) {
+    if VERBOSE.load(Ordering::Relaxed) {
+        eprintln!("{} {}", paint(BOLD, "Note:", &STDERR_COLOR), message);
+    }
+}
+
+/// Print a warning to stderr
+pub fn warn(message: impl Display) {
+    eprintln!("{} {}", paint(YELLOW, "Warning:", &STDERR_COLOR), message);
+}
+
+/// Print an error to stderr
+pub fn error(message: impl Display) {
+    eprintln!("{} {}", paint(RED, "Error:", &STDERR_COLOR), message);
+}
@@ -0,0 +1,197 @@
+use std::env;
+
+use crate::i18n::{tr, tr_with};
+use crate::sources::PLAYER_ENV;
+use crate::{find_in_path, wm, Config, MenuEntryConfig};
+
+/// Built-in menus that work without a config file
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum Preset {
+    /// Lock, log out, suspend, hibernate, reboot and shut down
+    Power,
+    /// Full-screen, region and window captures to a file or the clipboard
+    Screenshot,
+    /// Playback, seeking and volume through playerctl and pactl/wpctl
+    Media,
+}
+
+impl Preset {
+    /// Build the preset's menu for the current session
+    pub fn config(self) -> Config {
+        match self {
+            Preset::Power => power(),
+            Preset::Screenshot => screenshot(),
+            Preset::Media => media(),
+        }
+    }
+}
+
+fn installed(program: &str) -> bool {
+    find_in_path(program).is_some()
+}
+
+fn entry(key: &str, label: &str, command: &str, confirm: bool) -> MenuEntryConfig {
+    let mut entry = MenuEntryConfig::new(key, label, command);
+    entry.options.confirm = confirm;
+    entry
+}
+
+fn power() -> Config {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+
+    let lock = if wayland && installed("swaylock") {
+        "swaylock -f"
+    } else if !wayland && installed("i3lock") {
+        "i3lock"
+    } else {
+        "loginctl lock-session"
+    };
+
+    let logout = match wm::detect() {
+        Some(compositor) => compositor.exit().join(" "),
+        None => "loginctl terminate-session \"$XDG_SESSION_ID\"".to_string(),
+    };
+
+    // logind answers both, but systemctl is the usual spelling where it exists
+    let power = if installed("systemctl") {
+        "systemctl"
+    } else {
+        "loginctl"
+    };
+
+    Config {
+        prompt: Some(tr("Power").to_string()),
+        entries: vec![
+            entry("l", tr("Lock"), lock, false),
+            entry("e", tr("Log Out"), &logout, true),
+            entry("s", tr("Suspend"), &format!("{} suspend", power), false),
+            entry("h", tr("Hibernate"), &format!("{} hibernate", power), true),
+            entry("r", tr("Reboot"), &format!("{} reboot", power), true),
+            entry("p", tr("Shut Down"), &format!("{} poweroff", power), true),
+        ],
+        ..Config::default()
+    }
+}
+
+// Where file captures go; {timestamp} is filled in at launch
+const SCREENSHOT_FILE: &str = "\"${XDG_PICTURES_DIR:-$HOME/Pictures}/screenshot-{timestamp}.png\"";
+
+fn screenshot() -> Config {
+    let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+
+    // (label, capture command writing a PNG to stdout, tools it needs)
+    let captures: Vec<(&str, String, Vec<&str>)> = if wayland {
+        let mut captures = vec![
+            ("Screen", "grim -".to_string(), vec!["grim"]),
+            (
+                "Region",
+                "grim -g \"$(slurp)\" -".to_string(),
+                vec!["grim", "slurp"],
+            ),
+        ];
+        // Only the compositor knows where the focused window is
+        if wm::detect().is_some() {
+            captures.push((
+                "Window",
+                "grim -g {window_geometry} -".to_string(),
+                vec!["grim"],
+            ));
+        }
+        captures
+    } else {
+        vec![
+            ("Screen", "maim".to_string(), vec!["maim"]),
+            ("Region", "maim -s".to_string(), vec!["maim", "slop"]),
+            (
+                "Window",
+                "maim -i \"$(xdotool getactivewindow)\"".to_string(),
+                vec!["maim", "xdotool"],
+            ),
+        ]
+    };
+    let copy = if wayland {
+        ("wl-copy -t image/png", "wl-copy")
+    } else {
+        ("xclip -selection clipboard -t image/png", "xclip")
+    };
+
+    let mut entries = Vec::new();
+    for (index, (label, capture, tools)) in captures.iter().enumerate() {
+        // Entries for tools that aren't installed are left out
+        if !tools.iter().all(|tool| installed(tool)) {
+            continue;
+        }
+        entries.push(entry(
+            &(index + 1).to_string(),
+            &tr_with("{} to File", tr(label)),
+            &format!("{} > {}", capture, SCREENSHOT_FILE),
+            false,
+        ));
+        if installed(copy.1) {
+            entries.push(entry(
+                &(index + 4).to_string(),
+                &tr_with("{} to Clipboard", tr(label)),
+                &format!("{} | {}", capture, copy.0),
+                false,
+            ));
+        }
+    }
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+
+    Config {
+        prompt: Some(tr("Screenshot").to_string()),
+        entries,
+        ..Config::default()
+    }
+}
+
+fn media() -> Config {
+    // Scope playerctl to the player picked through the "Player" entry, if any
+    let playerctl = format!(
+        "playerctl ${{{env}:+--player=\"${env}\"}}",
+        env = PLAYER_ENV
+    );
+    let (volume_up, volume_down) = if installed("wpctl") {
+        (
+            "wpctl set-volume @DEFAULT_AUDIO_SINK@ 5%+",
+            "wpctl set-volume @DEFAULT_AUDIO_SINK@ 5%-",
+        )
+    } else {
+        (
+            "pactl set-sink-volume @DEFAULT_SINK@ +5%",
+            "pactl set-sink-volume @DEFAULT_SINK@ -5%",
+        )
+    };
+
+    let repeatable = |key: &str, label: &str, command: &str| {
+        let mut entry = MenuEntryConfig::new(key, label, command);
+        entry.options.repeatable = true;
+        entry
+    };
+
+    let mut player = MenuEntryConfig::new("s", tr("Player…"), "");
+    player.source_type = Some("players".to_string());
+
+    Config {
+        prompt: Some(tr("Media").to_string()),
+        entries: vec![
+            repeatable("p", tr("Play/Pause"), &format!("{} play-pause", playerctl)),
+            repeatable("n", tr("Next"), &format!("{} next", playerctl)),
+            repeatable("b", tr("Previous"), &format!("{} previous", playerctl)),
+            repeatable(
+                "f",
+                tr("Forward 10s"),
+                &format!("{} position 10+", playerctl),
+            ),
+            repeatable(
+                "r",
+                tr("Rewind 10s"),
+                &format!("{} position 10-", playerctl),
+            ),
+            repeatable("u", tr("Volume Up"), volume_up),
+            repeatable("d", tr("Volume Down"), volume_down),
+            player,
+        ],
+        ..Config::default()
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Start of the run and of the current phase, while --profile-startup is active
+static PROFILE: Mutex<Option<(Instant, Instant)>> = Mutex::new(None);
+
+/// Start timing startup phases; `mark` does nothing until this is called
+pub fn start() {
+    let now = Instant::now();
+    if let Ok(mut profile) = PROFILE.lock() {
+        *profile = Some((now, now));
+    }
+}
+
+/// Print how long the phase that just ended took, and the total so far
+pub fn mark(phase: &str) {
+    let Ok(mut profile) = PROFILE.lock() else {
+        return;
+    };
+    if let Some((start, last)) = profile.as_mut() {
+        let now = Instant::now();
+        eprintln!(
+            "{:<16} {:>8.2} ms  (total {:.2} ms)",
+            phase,
+            (now - *last).as_secs_f64() * 1000.0,
+            (now - *start).as_secs_f64() * 1000.0
+        );
+        *last = now;
+    }
+}
+
+/// Print the last phase and stop timing, so later menus don't report
+pub fn finish(phase: &str) {
+    mark(phase);
+    if let Ok(mut profile) = PROFILE.lock() {
+        *profile = None;
+    }
+}
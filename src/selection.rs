@@ -0,0 +1,87 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem::ManuallyDrop;
+use std::os::fd::FromRawFd;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::shell_quote;
+
+/// What --output-fd and --output-file write for the picked entry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SelectionFormat {
+    /// The command line, shell-quoted, ready for `eval`
+    #[default]
+    Command,
+    /// A JSON object with the entry's label, key, argv and cwd
+    Json,
+}
+
+/// Where the picked entry goes
+#[derive(Debug, Clone)]
+pub enum SelectionTarget {
+    /// A file descriptor the caller opened, e.g. 3 for `3>&1`
+    Fd(i32),
+    /// A file or named pipe, replaced on each pick
+    File(PathBuf),
+}
+
+/// Hands picked entries to a wrapper script instead of (or before) running them
+#[derive(Debug, Clone)]
+pub struct SelectionSink {
+    pub target: SelectionTarget,
+    pub format: SelectionFormat,
+    /// Run the entry as well, after writing it
+    pub and_exec: bool,
+}
+
+#[derive(Serialize)]
+struct Record {
+    label: String,
+    key: String,
+    argv: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<PathBuf>,
+}
+
+impl SelectionSink {
+    /// Write one picked entry, as a single line
+    pub fn write(
+        &self,
+        label: &str,
+        key: char,
+        argv: &[String],
+        cwd: Option<&Path>,
+    ) -> io::Result<()> {
+        let mut line = match self.format {
+            SelectionFormat::Command => {
+                let words: Vec<String> = argv.iter().map(|word| shell_quote(word)).collect();
+                words.join(" ")
+            }
+            SelectionFormat::Json => serde_json::to_string(&Record {
+                label: label.to_string(),
+                key: key.to_string(),
+                argv: argv.to_vec(),
+                cwd: cwd.map(Path::to_path_buf),
+            })
+            .map_err(io::Error::other)?,
+        };
+        line.push('\n');
+
+        match &self.target {
+            SelectionTarget::Fd(fd) => {
+                // The descriptor belongs to whoever started rofi-keys; leave it open
+                let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(*fd) });
+                file.write_all(line.as_bytes())?;
+                file.flush()
+            }
+            SelectionTarget::File(path) => OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?
+                .write_all(line.as_bytes()),
+        }
+    }
+}
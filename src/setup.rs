@@ -0,0 +1,167 @@
+use std::str;
+
+use crate::error::Error;
+use crate::{find_in_path, i18n, run_rofi, Config, Menu, MenuEntryConfig, MenuStyle, CHOICE_KEYS};
+
+/// What an app suggestion is, so one of each kind can be picked up front
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Browser,
+    Terminal,
+    Other,
+}
+
+/// Apps offered when they are on PATH: kind, label and command
+const SUGGESTIONS: &[(Kind, &str, &str)] = &[
+    (Kind::Browser, "Firefox", "firefox"),
+    (Kind::Browser, "Chromium", "chromium"),
+    (Kind::Browser, "Chrome", "google-chrome-stable"),
+    (Kind::Browser, "Brave", "brave-browser"),
+    (Kind::Browser, "qutebrowser", "qutebrowser"),
+    (Kind::Terminal, "Alacritty", "alacritty"),
+    (Kind::Terminal, "kitty", "kitty"),
+    (Kind::Terminal, "foot", "foot"),
+    (Kind::Terminal, "WezTerm", "wezterm"),
+    (Kind::Terminal, "GNOME Terminal", "gnome-terminal"),
+    (Kind::Terminal, "Konsole", "konsole"),
+    (Kind::Terminal, "Terminal", "x-terminal-emulator"),
+    (Kind::Other, "Nautilus", "nautilus"),
+    (Kind::Other, "Thunar", "thunar"),
+    (Kind::Other, "PCManFM", "pcmanfm"),
+    (Kind::Other, "VS Code", "code"),
+    (Kind::Other, "MPV", "mpv"),
+    (Kind::Other, "Thunderbird", "thunderbird"),
+];
+
+struct App {
+    label: String,
+    command: String,
+    selected: bool,
+}
+
+/// Ask whether to set up a config now; None when the user says no or backs out
+pub fn offer() -> Result<Option<Config>, Error> {
+    let menu = wizard_menu();
+    if !menu.confirm(i18n::tr("No config yet. Set up rofi-keys"), None)? {
+        return Ok(None);
+    }
+    wizard(&menu)
+}
+
+/// Let the user pick installed apps and add their own commands, and build a
+/// config from them; None when the wizard is cancelled
+pub fn run() -> Result<Option<Config>, Error> {
+    wizard(&wizard_menu())
+}
+
+fn wizard_menu() -> Menu {
+    Menu::new(MenuStyle {
+        prompt: "rofi-keys setup".to_string(),
+        ..MenuStyle::default()
+    })
+}
+
+fn wizard(menu: &Menu) -> Result<Option<Config>, Error> {
+    let mut apps = detected();
+    loop {
+        let mut rows: Vec<String> = apps
+            .iter()
+            .map(|app| {
+                let mark = if app.selected { "[x]" } else { "[ ]" };
+                format!("{} {} ({})", mark, app.label, app.command)
+            })
+            .collect();
+        rows.push("Add a command…".to_string());
+        rows.push("Save".to_string());
+
+        match menu.choose_with_rofi("Apps", &rows, None)? {
+            None => return Ok(None),
+            Some(i) if i < apps.len() => apps[i].selected = !apps[i].selected,
+            Some(i) if i == apps.len() => {
+                if let Some(app) = ask_for_app(menu)? {
+                    apps.push(app);
+                }
+            }
+            Some(_) => return Ok(Some(build_config(&apps))),
+        }
+    }
+}
+
+// Suggestions whose program is installed; the first browser and terminal start selected
+fn detected() -> Vec<App> {
+    let mut picked = Vec::new();
+    SUGGESTIONS
+        .iter()
+        .filter(|(_, _, command)| find_in_path(command).is_some())
+        .map(|&(kind, label, command)| {
+            let selected = kind != Kind::Other && !picked.contains(&kind);
+            if selected {
+                picked.push(kind);
+            }
+            App {
+                label: label.to_string(),
+                command: command.to_string(),
+                selected,
+            }
+        })
+        .collect()
+}
+
+fn ask_for_app(menu: &Menu) -> Result<Option<App>, Error> {
+    let Some(command) = ask(menu, "Command")? else {
+        return Ok(None);
+    };
+    // The program name makes a fine label when none is given
+    let program = command.split_whitespace().next().unwrap_or(&command);
+    let program = program.rsplit('/').next().unwrap_or(program).to_string();
+    let label = ask(menu, &format!("Label ({})", program))?.unwrap_or(program);
+    Ok(Some(App {
+        label,
+        command,
+        selected: true,
+    }))
+}
+
+// Free text typed into rofi; None when cancelled or left empty
+fn ask(menu: &Menu, prompt: &str) -> Result<Option<String>, Error> {
+    let mut rofi_args = vec!["-dmenu", "-p", prompt, "-no-fork", "-l", "0"];
+    rofi_args.extend(menu.style.rofi_args());
+    let output = run_rofi(&rofi_args, "")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let text = str::from_utf8(&output.stdout).unwrap_or("").trim();
+    Ok((!text.is_empty()).then(|| text.to_string()))
+}
+
+fn build_config(apps: &[App]) -> Config {
+    let mut taken = Vec::new();
+    let entries = apps
+        .iter()
+        .filter(|app| app.selected)
+        .filter_map(|app| {
+            let key = pick_key(&app.label, &taken)?;
+            taken.push(key);
+            Some(MenuEntryConfig::new(
+                &key.to_string(),
+                &app.label,
+                &app.command,
+            ))
+        })
+        .collect();
+    Config {
+        prompt: Some("Applications".to_string()),
+        entries,
+        ..Config::default()
+    }
+}
+
+// A letter of the label, first letter first, else the next free choice key
+fn pick_key(label: &str, taken: &[char]) -> Option<char> {
+    label
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .chain(CHOICE_KEYS.chars())
+        .find(|key| !taken.contains(key))
+}
@@ -0,0 +1,593 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cache::{self, CachePolicy};
+use crate::error::Error;
+use crate::{envfile, expand_path, find_in_path, wm, MenuEntryConfig};
+
+// Rows longer than this are shortened for display
+const MAX_ROW_CHARS: usize = 80;
+
+// Jobs started early by `prefetch`, and the thread running them
+type Prefetch = (Vec<SourceJob>, JoinHandle<Vec<io::Result<Vec<SourceItem>>>>);
+static PREFETCH: Mutex<Option<Prefetch>> = Mutex::new(None);
+
+// Set when the menu is about to be shown; other commands never run sources
+static PREFETCH_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+/// A dynamic entry whose rows are produced when it is selected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    ClipboardHistory(ClipboardTool),
+    /// Running MPRIS players; picking one scopes later playerctl commands to it
+    Players,
+    /// Fixed texts from a snippets file, delivered through `OutputMode`
+    Snippets(Vec<Snippet>, OutputMode),
+    /// Open windows of the running compositor; picking one focuses it
+    Windows,
+}
+
+/// A named piece of text offered by a snippets source
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Snippet {
+    pub trigger: String,
+    pub text: String,
+}
+
+// TOML has no top-level arrays, so TOML snippet files use [[snippets]] tables
+#[derive(Debug, Deserialize)]
+struct SnippetFile {
+    snippets: Vec<Snippet>,
+}
+
+/// How picked text is handed over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputMode {
+    /// Copy to the clipboard (wl-copy or xclip)
+    #[default]
+    Clipboard,
+    /// Type into the focused window (wtype or xdotool)
+    Type,
+}
+
+/// Clipboard manager backing a clipboard_history source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClipboardTool {
+    /// cliphist + wl-copy (Wayland)
+    Cliphist,
+    /// greenclip (X11)
+    Greenclip,
+}
+
+/// What to do with a source entry whose tool isn't installed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingPolicy {
+    #[default]
+    Hide,
+    Disable,
+}
+
+/// Environment variable holding the player chosen through a players source
+pub const PLAYER_ENV: &str = "ROFI_KEYS_PLAYER";
+
+/// One row of a source menu
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceItem {
+    /// Possibly truncated text shown in rofi
+    pub display: String,
+    /// What happens when the row is picked
+    pub action: SourceAction,
+}
+
+/// Effect of picking a source row
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SourceAction {
+    /// Run this command line
+    Run(Vec<String>),
+    /// Set an environment variable for commands launched afterwards
+    SetEnv(String, String),
+}
+
+/// A source to run alongside others, given up on after `timeout`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceJob {
+    pub source: Source,
+    pub cache: Option<CachePolicy>,
+    pub timeout: Duration,
+}
+
+fn invalid(message: String) -> Error {
+    Error::ConfigInvalid(message)
+}
+
+impl ClipboardTool {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "cliphist" => Ok(ClipboardTool::Cliphist),
+            "greenclip" => Ok(ClipboardTool::Greenclip),
+            other => Err(invalid(format!(
+                "Unknown clipboard_tool '{}' (expected \"cliphist\" or \"greenclip\")",
+                other
+            ))),
+        }
+    }
+
+    fn installed(self) -> bool {
+        match self {
+            ClipboardTool::Cliphist => {
+                find_in_path("cliphist").is_some() && find_in_path("wl-copy").is_some()
+            }
+            ClipboardTool::Greenclip => find_in_path("greenclip").is_some(),
+        }
+    }
+
+    // cliphist on Wayland sessions, greenclip everywhere else
+    fn for_session() -> Self {
+        if env::var_os("WAYLAND_DISPLAY").is_some() {
+            ClipboardTool::Cliphist
+        } else {
+            ClipboardTool::Greenclip
+        }
+    }
+}
+
+impl MissingPolicy {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "hide" => Ok(MissingPolicy::Hide),
+            "disable" => Ok(MissingPolicy::Disable),
+            other => Err(invalid(format!(
+                "Unknown missing policy '{}' (expected \"hide\" or \"disable\")",
+                other
+            ))),
+        }
+    }
+}
+
+impl OutputMode {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "clipboard" => Ok(OutputMode::Clipboard),
+            "type" => Ok(OutputMode::Type),
+            other => Err(invalid(format!(
+                "Unknown output '{}' (expected \"clipboard\" or \"type\")",
+                other
+            ))),
+        }
+    }
+
+    // Command line that delivers `text`
+    fn action(self, text: &str) -> Vec<String> {
+        let wayland = env::var_os("WAYLAND_DISPLAY").is_some();
+        match (self, wayland) {
+            (OutputMode::Clipboard, true) => {
+                vec!["wl-copy".to_string(), "--".to_string(), text.to_string()]
+            }
+            (OutputMode::Clipboard, false) => {
+                shell_with_arg("printf '%s' \"$1\" | xclip -selection clipboard", text)
+            }
+            (OutputMode::Type, true) => {
+                vec!["wtype".to_string(), "--".to_string(), text.to_string()]
+            }
+            (OutputMode::Type, false) => vec![
+                "xdotool".to_string(),
+                "type".to_string(),
+                "--".to_string(),
+                text.to_string(),
+            ],
+        }
+    }
+}
+
+impl Source {
+    /// Check that an entry's source_type and its settings are valid
+    pub fn validate(entry: &MenuEntryConfig) -> Result<(), Error> {
+        match entry.source_type.as_deref() {
+            None => Ok(()),
+            Some("clipboard_history") => {
+                if let Some(tool) = &entry.clipboard_tool {
+                    ClipboardTool::parse(tool)?;
+                }
+                Ok(())
+            }
+            Some("snippets") => {
+                if entry.source_file.is_none() {
+                    return Err(invalid("snippets sources need a source_file".to_string()));
+                }
+                if let Some(output) = &entry.output {
+                    OutputMode::parse(output)?;
+                }
+                Ok(())
+            }
+            Some("players") | Some("windows") => Ok(()),
+            Some(other) => Err(invalid(format!(
+                "Unknown source_type '{}' (expected \"clipboard_history\", \"snippets\", \"players\" or \"windows\")",
+                other
+            ))),
+        }
+    }
+
+    /// Resolve an entry's source, or None when the program behind it isn't installed
+    pub fn resolve(entry: &MenuEntryConfig) -> io::Result<Option<Self>> {
+        Source::validate(entry)?;
+        match entry.source_type.as_deref() {
+            Some("clipboard_history") => {
+                let tool = match &entry.clipboard_tool {
+                    Some(tool) => ClipboardTool::parse(tool)?,
+                    None => ClipboardTool::for_session(),
+                };
+                Ok(tool.installed().then_some(Source::ClipboardHistory(tool)))
+            }
+            Some("snippets") => {
+                let path = expand_path(entry.source_file.as_deref().unwrap_or_default());
+                let output = match &entry.output {
+                    Some(output) => OutputMode::parse(output)?,
+                    None => OutputMode::default(),
+                };
+                Ok(Some(Source::Snippets(
+                    load_snippets(Path::new(&path))?,
+                    output,
+                )))
+            }
+            // Choosing a player only makes sense when there is more than one
+            Some("players") => Ok(player_names()
+                .is_some_and(|players| players.len() > 1)
+                .then_some(Source::Players)),
+            Some("windows") => Ok(wm::detect().map(|_| Source::Windows)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Run the source program and turn its output into rows
+    pub fn items(&self) -> io::Result<Vec<SourceItem>> {
+        match self {
+            Source::ClipboardHistory(tool) => clipboard_items(*tool),
+            Source::Players => Ok(player_names()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|player| SourceItem {
+                    display: player.clone(),
+                    action: SourceAction::SetEnv(PLAYER_ENV.to_string(), player),
+                })
+                .collect()),
+            Source::Snippets(snippets, output) => Ok(snippets
+                .iter()
+                .map(|snippet| SourceItem {
+                    display: truncate(&format!(
+                        "{}  {}",
+                        snippet.trigger,
+                        snippet.text.replace('\n', " ")
+                    )),
+                    action: SourceAction::Run(output.action(&snippet.text)),
+                })
+                .collect()),
+            Source::Windows => window_items(),
+        }
+    }
+}
+
+/// Let the next `prefetch` start its jobs; only showing the menu wants them early
+pub fn allow_prefetch() {
+    PREFETCH_ALLOWED.store(true, Ordering::Relaxed);
+}
+
+/// Start the jobs from `jobs` in the background, once and only after
+/// `allow_prefetch`, so the first menu's sources run while the config is checked
+pub fn prefetch(jobs: impl FnOnce() -> Vec<SourceJob>) {
+    if !PREFETCH_ALLOWED.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let jobs = jobs();
+    if jobs.is_empty() {
+        return;
+    }
+    let started = jobs.clone();
+    let handle = thread::spawn(move || run_concurrently(jobs, false));
+    if let Ok(mut prefetch) = PREFETCH.lock() {
+        *prefetch = Some((started, handle));
+    }
+}
+
+/// The rows of prefetched `jobs` in their order, waiting for any still running;
+/// None when nothing was prefetched or some of `jobs` weren't
+pub fn take_prefetched(jobs: &[SourceJob]) -> Option<Vec<io::Result<Vec<SourceItem>>>> {
+    let (started, handle) = PREFETCH.lock().ok()?.take()?;
+    // Where each of `jobs` is among those started; equal jobs each get their own
+    let mut taken = vec![false; started.len()];
+    let order = jobs
+        .iter()
+        .map(|job| {
+            let index = (0..started.len()).find(|&index| !taken[index] && started[index] == *job)?;
+            taken[index] = true;
+            Some(index)
+        })
+        .collect::<Option<Vec<usize>>>()?;
+    let mut results: Vec<Option<_>> = handle.join().ok()?.into_iter().map(Some).collect();
+    order.into_iter().map(|index| results[index].take()).collect()
+}
+
+/// Run all `jobs` at once and return their rows in the same order;
+/// a job that fails or outlives its timeout yields an error instead
+pub fn run_concurrently(jobs: Vec<SourceJob>, refresh: bool) -> Vec<io::Result<Vec<SourceItem>>> {
+    let tasks = jobs
+        .into_iter()
+        .map(|job| (job.timeout, move || cache::items(&job.source, job.cache, refresh)))
+        .collect();
+    run_tasks(tasks)
+}
+
+// run_concurrently for any work: each task runs on its own thread and gets
+// an error once it is past its timeout
+fn run_tasks<T, F>(tasks: Vec<(Duration, F)>) -> Vec<io::Result<T>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    let started = Instant::now();
+    let deadlines: Vec<Instant> = tasks.iter().map(|(timeout, _)| started + *timeout).collect();
+    let timeouts: Vec<Duration> = tasks.iter().map(|(timeout, _)| *timeout).collect();
+
+    let (sender, receiver) = mpsc::channel();
+    for (index, (_, task)) in tasks.into_iter().enumerate() {
+        let sender = sender.clone();
+        // A source that runs past its timeout is left to finish on its own;
+        // its rows are dropped, though a cached source still saves them
+        thread::spawn(move || {
+            let _ = sender.send((index, task()));
+        });
+    }
+    drop(sender);
+
+    let mut results: Vec<Option<io::Result<T>>> = deadlines.iter().map(|_| None).collect();
+    loop {
+        let next_deadline = results
+            .iter()
+            .zip(&deadlines)
+            .filter(|(result, _)| result.is_none())
+            .map(|(_, deadline)| *deadline)
+            .min();
+        let Some(deadline) = next_deadline else {
+            break;
+        };
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok((index, items)) => {
+                results[index].get_or_insert(items);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let now = Instant::now();
+                for (index, result) in results.iter_mut().enumerate() {
+                    if result.is_none() && deadlines[index] <= now {
+                        *result = Some(Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("timed out after {} ms", timeouts[index].as_millis()),
+                        )));
+                    }
+                }
+            }
+            // Every thread has finished, so the rest panicked
+            Err(RecvTimeoutError::Disconnected) => {
+                for result in results.iter_mut().filter(|result| result.is_none()) {
+                    *result = Some(Err(io::Error::other("source stopped unexpectedly")));
+                }
+            }
+        }
+    }
+    results.into_iter().flatten().collect()
+}
+
+// Read a JSON array or TOML [[snippets]] file, rejecting repeated triggers
+fn load_snippets(path: &Path) -> io::Result<Vec<Snippet>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let parse_error = |e: &dyn std::fmt::Display| invalid(format!("{}: {}", path.display(), e));
+
+    let snippets: Vec<Snippet> = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str::<SnippetFile>(&content)
+            .map_err(|e| parse_error(&e))?
+            .snippets
+    } else {
+        serde_json::from_str(&content).map_err(|e| parse_error(&e))?
+    };
+
+    let mut seen = HashSet::new();
+    for snippet in &snippets {
+        if !seen.insert(snippet.trigger.as_str()) {
+            return Err(invalid(format!(
+                "{}: duplicate snippet trigger '{}'",
+                path.display(),
+                snippet.trigger
+            ))
+            .into());
+        }
+    }
+    Ok(snippets)
+}
+
+fn clipboard_items(tool: ClipboardTool) -> io::Result<Vec<SourceItem>> {
+    let listing = match tool {
+        ClipboardTool::Cliphist => capture(&["cliphist", "list"])?,
+        ClipboardTool::Greenclip => capture(&["greenclip", "print"])?,
+    };
+
+    Ok(listing
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match tool {
+            // "<id>\t<preview>"; decoding needs the whole line back
+            ClipboardTool::Cliphist => SourceItem {
+                display: truncate(line.split_once('\t').map_or(line, |(_, preview)| preview)),
+                action: SourceAction::Run(shell_with_arg(
+                    "printf '%s' \"$1\" | cliphist decode | wl-copy",
+                    line,
+                )),
+            },
+            // greenclip puts an item back on the clipboard when asked to print it
+            ClipboardTool::Greenclip => SourceItem {
+                display: truncate(line),
+                action: SourceAction::Run(vec![
+                    "greenclip".to_string(),
+                    "print".to_string(),
+                    line.to_string(),
+                ]),
+            },
+        })
+        .collect())
+}
+
+fn window_items() -> io::Result<Vec<SourceItem>> {
+    let compositor = wm::detect()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no supported compositor"))?;
+    Ok(compositor
+        .list_windows()?
+        .into_iter()
+        .map(|window| {
+            let workspace = window.workspace.as_deref().unwrap_or("-");
+            SourceItem {
+                display: truncate(&format!(
+                    "{}  {}  {}",
+                    workspace, window.class, window.title
+                )),
+                action: SourceAction::Run(compositor.focus(&window)),
+            }
+        })
+        .collect())
+}
+
+// Players known to playerctl, or None when playerctl isn't installed
+fn player_names() -> Option<Vec<String>> {
+    find_in_path("playerctl")?;
+    let listing = capture(&["playerctl", "-l"]).unwrap_or_default();
+    Some(
+        listing
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+// sh -c script with `arg` passed as $1, so it never needs quoting
+fn shell_with_arg(script: &str, arg: &str) -> Vec<String> {
+    vec![
+        "sh".to_string(),
+        "-c".to_string(),
+        script.to_string(),
+        "sh".to_string(),
+        arg.to_string(),
+    ]
+}
+
+// Run a program and return its stdout
+fn capture(argv: &[&str]) -> io::Result<String> {
+    let output = envfile::command(argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed with {}",
+            argv[0], output.status
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Shorten long rows for display, keeping them on one line
+fn truncate(text: &str) -> String {
+    let text = text.trim();
+    if text.chars().count() <= MAX_ROW_CHARS {
+        return text.to_string();
+    }
+    let mut short: String = text.chars().take(MAX_ROW_CHARS - 1).collect();
+    short.push('…');
+    short
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    fn snippets_job(trigger: &str) -> SourceJob {
+        let snippet = Snippet {
+            trigger: trigger.to_string(),
+            text: format!("{} text", trigger),
+        };
+        SourceJob {
+            source: Source::Snippets(vec![snippet], OutputMode::default()),
+            cache: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    #[test]
+    fn prefetched_rows_come_back_in_the_order_asked_for() {
+        let (first, second) = (snippets_job("first"), snippets_job("second"));
+        allow_prefetch();
+        prefetch(|| vec![first.clone(), second.clone()]);
+        let results = take_prefetched(&[second, first]).unwrap();
+        let displays: Vec<String> = results
+            .into_iter()
+            .map(|items| items.unwrap().remove(0).display)
+            .collect();
+        assert_eq!(displays, ["second  second text", "first  first text"]);
+        // Taken once; the menu runs its sources itself after that
+        assert!(take_prefetched(&[snippets_job("first")]).is_none());
+    }
+
+    #[test]
+    fn sources_run_at_the_same_time() {
+        // Neither task gets past the barrier until both are running; one at a
+        // time, the first would wait there until it timed out
+        let barrier = Arc::new(Barrier::new(2));
+        let tasks = (0..2)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                let task = move || {
+                    barrier.wait();
+                    Ok(())
+                };
+                (Duration::from_secs(30), task)
+            })
+            .collect();
+        let results = run_tasks(tasks);
+        assert!(results.iter().all(Result::is_ok), "{:?}", results);
+    }
+
+    #[test]
+    fn a_slow_source_times_out_without_holding_up_the_rest() {
+        // The slow task waits for a gate that only opens once run_tasks is done
+        let (open, gate) = mpsc::channel::<()>();
+        let tasks = vec![(Duration::from_secs(30), None), (Duration::from_millis(10), Some(gate))]
+            .into_iter()
+            .enumerate()
+            .map(|(index, (timeout, gate))| {
+                let task = move || {
+                    if let Some(gate) = gate {
+                        let _ = gate.recv();
+                    }
+                    Ok(index)
+                };
+                (timeout, task)
+            })
+            .collect();
+        let results = run_tasks(tasks);
+        drop(open);
+        assert_eq!(results[0].as_ref().unwrap(), &0);
+        assert_eq!(results[1].as_ref().unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+}
+
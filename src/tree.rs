@@ -0,0 +1,72 @@
+use serde::Serialize;
+
+use crate::{expand_title, Menu};
+
+/// Version of the `dump` output, bumped on incompatible changes
+pub const DUMP_VERSION: u32 = 1;
+
+/// Top-level document printed by `rofi-keys dump --format json`
+#[derive(Debug, Serialize)]
+pub struct MenuDump {
+    pub version: u32,
+    pub menu: MenuNode,
+}
+
+/// A menu and its entries, with argument choices expanded into submenus
+#[derive(Debug, Serialize)]
+pub struct MenuNode {
+    pub title: String,
+    pub entries: Vec<EntryNode>,
+}
+
+/// One row of a menu
+#[derive(Debug, Serialize)]
+pub struct EntryNode {
+    pub key: String,
+    pub label: String,
+    /// The command, the template for entries that open a submenu,
+    /// or "→ name" for entries that switch to a flow menu
+    pub command: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submenu: Option<MenuNode>,
+}
+
+impl MenuNode {
+    pub fn from_menu(menu: &Menu) -> Self {
+        let entries = menu
+            .entries
+            .iter()
+            .map(|entry| EntryNode {
+                key: entry.key.to_string(),
+                label: entry.label.clone(),
+                command: match (&entry.command_template, &entry.next) {
+                    (Some(template), _) => template.clone(),
+                    (None, Some(next)) => format!("→ {}", next),
+                    (None, None) => entry.command.clone(),
+                },
+                submenu: entry
+                    .choice_menu(menu)
+                    .map(|choices| MenuNode::from_menu(&choices)),
+            })
+            .collect();
+
+        MenuNode {
+            title: expand_title(&menu.style.prompt, menu.entries.len()),
+            entries,
+        }
+    }
+
+    /// Visit this menu and its submenus depth-first, along with the titles leading to each
+    pub fn walk<'a>(&'a self, path: &mut Vec<&'a str>, visit: &mut impl FnMut(&[&str], &MenuNode)) {
+        path.push(&self.title);
+        visit(path, self);
+        for submenu in self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.submenu.as_ref())
+        {
+            submenu.walk(path, visit);
+        }
+        path.pop();
+    }
+}
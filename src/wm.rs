@@ -0,0 +1,235 @@
+use std::env;
+use std::io;
+use std::process::Stdio;
+
+use serde_json::Value;
+
+use crate::{envfile, find_in_path};
+
+/// A window as the compositor reports it
+#[derive(Debug, Clone, Default)]
+pub struct Window {
+    /// Whatever the compositor uses to address the window (con_id, address)
+    pub id: String,
+    pub title: String,
+    /// app_id on Wayland, the WM_CLASS class on X11
+    pub class: String,
+    pub pid: Option<u32>,
+    pub workspace: Option<String>,
+    /// Position and size as x, y, width, height
+    pub rect: Option<(i64, i64, i64, i64)>,
+}
+
+/// What rofi-keys asks of the running window manager or compositor;
+/// each one supported gets an implementation here and a line in `detect`
+pub trait Compositor {
+    /// Name used in messages, e.g. "sway"
+    fn name(&self) -> &'static str;
+    /// The window that has focus, if any
+    fn focused_window(&self) -> io::Result<Option<Window>>;
+    /// Every window, in the compositor's order
+    fn list_windows(&self) -> io::Result<Vec<Window>>;
+    /// Command line that has the compositor start `command` itself
+    fn exec(&self, command: &str) -> Vec<String>;
+    /// Command line that focuses `window`
+    fn focus(&self, window: &Window) -> Vec<String>;
+    /// Command line that ends the session
+    fn exit(&self) -> Vec<String>;
+}
+
+/// The compositor of this session, recognized by the socket variables it sets
+pub fn detect() -> Option<Box<dyn Compositor>> {
+    let set = |name: &str| env::var_os(name).is_some_and(|value| !value.is_empty());
+    if set("HYPRLAND_INSTANCE_SIGNATURE") && find_in_path("hyprctl").is_some() {
+        return Some(Box::new(Hyprland));
+    }
+    if set("SWAYSOCK") && find_in_path("swaymsg").is_some() {
+        return Some(Box::new(I3Ipc { program: "swaymsg" }));
+    }
+    if set("I3SOCK") && find_in_path("i3-msg").is_some() {
+        return Some(Box::new(I3Ipc { program: "i3-msg" }));
+    }
+    None
+}
+
+/// sway and i3, which share the i3 IPC and its command language
+struct I3Ipc {
+    program: &'static str,
+}
+
+impl I3Ipc {
+    fn windows(&self) -> io::Result<Vec<(Window, bool)>> {
+        let tree = query(&[self.program, "-t", "get_tree"])?;
+        let mut windows = Vec::new();
+        collect_i3_windows(&tree, None, &mut windows);
+        Ok(windows)
+    }
+}
+
+impl Compositor for I3Ipc {
+    fn name(&self) -> &'static str {
+        if self.program == "swaymsg" {
+            "sway"
+        } else {
+            "i3"
+        }
+    }
+
+    fn focused_window(&self) -> io::Result<Option<Window>> {
+        Ok(self
+            .windows()?
+            .into_iter()
+            .find_map(|(window, focused)| focused.then_some(window)))
+    }
+
+    fn list_windows(&self) -> io::Result<Vec<Window>> {
+        Ok(self
+            .windows()?
+            .into_iter()
+            .map(|(window, _)| window)
+            .collect())
+    }
+
+    fn exec(&self, command: &str) -> Vec<String> {
+        vec![
+            self.program.to_string(),
+            "exec".to_string(),
+            command.to_string(),
+        ]
+    }
+
+    fn focus(&self, window: &Window) -> Vec<String> {
+        vec![
+            self.program.to_string(),
+            format!("[con_id={}]", window.id),
+            "focus".to_string(),
+        ]
+    }
+
+    fn exit(&self) -> Vec<String> {
+        vec![self.program.to_string(), "exit".to_string()]
+    }
+}
+
+// Walk a get_tree reply; windows are the nodes with a pid (sway) or an X11
+// window id (i3), and sit below the workspace they belong to
+fn collect_i3_windows(node: &Value, workspace: Option<&str>, out: &mut Vec<(Window, bool)>) {
+    let workspace = match node["type"].as_str() {
+        Some("workspace") => node["name"].as_str(),
+        _ => workspace,
+    };
+    if node["pid"].is_u64() || node["window"].is_u64() {
+        let class = node["app_id"]
+            .as_str()
+            .or_else(|| node["window_properties"]["class"].as_str())
+            .unwrap_or_default();
+        let rect = &node["rect"];
+        let window = Window {
+            id: node["id"].to_string(),
+            title: node["name"].as_str().unwrap_or_default().to_string(),
+            class: class.to_string(),
+            pid: node["pid"].as_u64().and_then(|pid| u32::try_from(pid).ok()),
+            workspace: workspace.map(str::to_string),
+            rect: Some((
+                rect["x"].as_i64().unwrap_or(0),
+                rect["y"].as_i64().unwrap_or(0),
+                rect["width"].as_i64().unwrap_or(0),
+                rect["height"].as_i64().unwrap_or(0),
+            )),
+        };
+        out.push((window, node["focused"].as_bool().unwrap_or(false)));
+    }
+    for child in ["nodes", "floating_nodes"] {
+        for child in node[child].as_array().into_iter().flatten() {
+            collect_i3_windows(child, workspace, out);
+        }
+    }
+}
+
+/// Hyprland, through hyprctl's JSON output and dispatchers
+struct Hyprland;
+
+impl Compositor for Hyprland {
+    fn name(&self) -> &'static str {
+        "Hyprland"
+    }
+
+    fn focused_window(&self) -> io::Result<Option<Window>> {
+        // No focused window is reported as an empty object
+        let active = query(&["hyprctl", "activewindow", "-j"])?;
+        Ok(active["address"]
+            .is_string()
+            .then(|| hyprland_window(&active)))
+    }
+
+    fn list_windows(&self) -> io::Result<Vec<Window>> {
+        let clients = query(&["hyprctl", "clients", "-j"])?;
+        Ok(clients
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(hyprland_window)
+            .collect())
+    }
+
+    fn exec(&self, command: &str) -> Vec<String> {
+        vec![
+            "hyprctl".to_string(),
+            "dispatch".to_string(),
+            "exec".to_string(),
+            command.to_string(),
+        ]
+    }
+
+    fn focus(&self, window: &Window) -> Vec<String> {
+        vec![
+            "hyprctl".to_string(),
+            "dispatch".to_string(),
+            "focuswindow".to_string(),
+            format!("address:{}", window.id),
+        ]
+    }
+
+    fn exit(&self) -> Vec<String> {
+        vec![
+            "hyprctl".to_string(),
+            "dispatch".to_string(),
+            "exit".to_string(),
+        ]
+    }
+}
+
+fn hyprland_window(client: &Value) -> Window {
+    Window {
+        id: client["address"].as_str().unwrap_or_default().to_string(),
+        title: client["title"].as_str().unwrap_or_default().to_string(),
+        class: client["class"].as_str().unwrap_or_default().to_string(),
+        pid: client["pid"]
+            .as_u64()
+            .and_then(|pid| u32::try_from(pid).ok()),
+        workspace: client["workspace"]["name"].as_str().map(str::to_string),
+        rect: Some((
+            client["at"][0].as_i64().unwrap_or(0),
+            client["at"][1].as_i64().unwrap_or(0),
+            client["size"][0].as_i64().unwrap_or(0),
+            client["size"][1].as_i64().unwrap_or(0),
+        )),
+    }
+}
+
+// Run a query command and parse its JSON reply
+fn query(argv: &[&str]) -> io::Result<Value> {
+    let output = envfile::command(argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed with {}",
+            argv[0], output.status
+        )));
+    }
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", argv[0], e)))
+}
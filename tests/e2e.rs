@@ -0,0 +1,273 @@
+// End-to-end runs of the rofi-keys binary against a scripted stand-in for
+// rofi, pointed to by $ROFI_KEYS_ROFI
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Answers each call with the next of $FAKE_ROFI_REPLIES ("CODE" or
+// "CODE:STDOUT", separated by spaces) and dismisses the menu once they run out.
+// Call N's arguments and input are kept in $FAKE_ROFI_DIR/N.args and N.input
+const FAKE_ROFI: &str = r#"#!/bin/sh
+n=$(cat "$FAKE_ROFI_DIR/calls" 2>/dev/null || echo 0)
+n=$((n + 1))
+echo "$n" > "$FAKE_ROFI_DIR/calls"
+printf '%s\n' "$@" > "$FAKE_ROFI_DIR/$n.args"
+cat > "$FAKE_ROFI_DIR/$n.input"
+reply=$(echo $FAKE_ROFI_REPLIES | cut -d ' ' -f "$n" -s)
+[ -z "$reply" ] && [ "$n" -eq 1 ] && reply=$FAKE_ROFI_REPLIES
+[ -z "$reply" ] && exit 1
+case "$reply" in
+    *:*) printf '%s\n' "${reply#*:}"; exit "${reply%%:*}" ;;
+    *) exit "$reply" ;;
+esac
+"#;
+
+// A scratch home with a config, the fake rofi and the state rofi-keys writes
+struct Scenario {
+    dir: PathBuf,
+}
+
+impl Scenario {
+    fn new(name: &str, config: &str) -> Self {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("e2e").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("rofi")).unwrap();
+        fs::write(dir.join("config.json"), config).unwrap();
+        let script = dir.join("fake-rofi");
+        fs::write(&script, FAKE_ROFI).unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        Scenario { dir }
+    }
+
+    // Run rofi-keys with `args`, the fake rofi answering with `replies`
+    fn run(&self, replies: &str, args: &[&str]) -> Output {
+        self.command(replies, args).output().unwrap()
+    }
+
+    fn command(&self, replies: &str, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_rofi-keys"));
+        command
+            .arg("--config")
+            .arg(self.dir.join("config.json"))
+            .args(args)
+            .env("ROFI_KEYS_ROFI", self.dir.join("fake-rofi"))
+            .env("FAKE_ROFI_REPLIES", replies)
+            .env("FAKE_ROFI_DIR", self.dir.join("rofi"))
+            .env("HOME", &self.dir)
+            .env("XDG_STATE_HOME", self.dir.join("state"))
+            .env("XDG_CACHE_HOME", self.dir.join("cache"))
+            .env("XDG_CONFIG_HOME", self.dir.join("config"))
+            .env("XDG_RUNTIME_DIR", &self.dir)
+            .env("LC_ALL", "C")
+            .env_remove("ROFI_KEYS_CONFIG");
+        command
+    }
+
+    // How many times rofi was started
+    fn rofi_calls(&self) -> usize {
+        fs::read_to_string(self.dir.join("rofi/calls"))
+            .map_or(0, |calls| calls.trim().parse().unwrap())
+    }
+
+    // What rofi-keys wrote to rofi's stdin on call `n`, counting from 1
+    fn rofi_input(&self, n: usize) -> String {
+        fs::read_to_string(self.dir.join(format!("rofi/{}.input", n))).unwrap()
+    }
+
+    fn rofi_args(&self, n: usize) -> Vec<String> {
+        let args = fs::read_to_string(self.dir.join(format!("rofi/{}.args", n))).unwrap();
+        args.lines().map(str::to_string).collect()
+    }
+
+    fn history(&self) -> Option<String> {
+        fs::read_to_string(self.dir.join("state/rofi-keys/history.json")).ok()
+    }
+
+    // A file entries touch to show they ran
+    fn marker(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+
+    // Entries are started detached and rofi-keys exits without waiting for
+    // them, so their marker can show up a little after it has gone
+    fn wait_for_marker(&self, name: &str) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !self.marker(name).exists() {
+            if Instant::now() > deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+// A config whose entries each touch a marker file named after their key
+fn config_with(scenario: &str, entries: &[(&str, &str, bool)]) -> Scenario {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("e2e").join(scenario);
+    let entries: Vec<serde_json::Value> = entries
+        .iter()
+        .map(|(key, label, confirm)| {
+            serde_json::json!({
+                "key": key,
+                "label": label,
+                "command": format!("touch {}", dir.join(key).display()),
+                "confirm": confirm,
+            })
+        })
+        .collect();
+    let config = serde_json::json!({ "prompt": "Apps", "entries": entries }).to_string();
+    Scenario::new(scenario, &config)
+}
+
+#[test]
+fn dry_run_prints_the_picked_command() {
+    let scenario = config_with("dry-run", &[("f", "Files", false), ("t", "Terminal", false)]);
+    // kb-custom-2 is the second entry's key
+    let output = scenario.run("11", &["--dry-run"]);
+
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    let expected = format!("sh -c 'touch {}'\n", scenario.marker("t").display());
+    assert_eq!(stdout(&output), expected);
+    assert!(!scenario.marker("t").exists(), "--dry-run ran the command");
+    assert_eq!(scenario.history(), None, "--dry-run recorded a launch");
+
+    assert_eq!(scenario.rofi_calls(), 1);
+    let input = scenario.rofi_input(1);
+    assert!(input.contains("Files") && input.contains("Terminal"), "{}", input);
+    let args = scenario.rofi_args(1);
+    assert_eq!(args[..3], ["-dmenu", "-p", "Apps"]);
+    assert!(args.windows(2).any(|pair| pair == ["-kb-custom-2", "t"]), "{:?}", args);
+}
+
+#[test]
+fn launching_an_entry_runs_it_and_records_the_launch() {
+    let scenario = config_with("launch", &[("f", "Files", false)]);
+    let output = scenario.run("10", &[]);
+
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert!(scenario.wait_for_marker("f"), "the entry's command didn't run");
+    let history = scenario.history().expect("no history file");
+    let history: serde_json::Value = serde_json::from_str(&history).unwrap();
+    assert!(history.to_string().contains("\"Files\""), "{}", history);
+}
+
+#[test]
+fn dismissing_the_menu_exits_with_10() {
+    let scenario = config_with("cancel", &[("f", "Files", false)]);
+    let output = scenario.run("1", &[]);
+
+    assert_eq!(output.status.code(), Some(10), "{:?}", output);
+    assert!(!scenario.marker("f").exists());
+    assert_eq!(scenario.history(), None);
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn confirming_runs_the_entry() {
+    let scenario = config_with("confirm-yes", &[("r", "Reboot", true)]);
+    // The key, then "Yes" (row 0) in the confirmation dialog
+    let output = scenario.run("10 0:0", &[]);
+
+    assert_eq!(output.status.code(), Some(0), "{:?}", output);
+    assert!(scenario.wait_for_marker("r"), "the confirmed entry didn't run");
+    assert_eq!(scenario.rofi_calls(), 2);
+    assert_eq!(scenario.rofi_input(2).lines().collect::<Vec<_>>(), ["Yes", "No"]);
+    assert!(scenario.rofi_args(2).contains(&"Reboot?".to_string()));
+}
+
+#[test]
+fn declining_goes_back_to_the_menu() {
+    let scenario = config_with("confirm-no", &[("r", "Reboot", true)]);
+    // The key, "No" (row 1), then Escape in the menu shown again
+    let output = scenario.run("10 0:1 1", &[]);
+
+    assert_eq!(output.status.code(), Some(10), "{:?}", output);
+    assert!(!scenario.marker("r").exists(), "a declined entry ran");
+    assert_eq!(scenario.history(), None);
+    assert_eq!(scenario.rofi_calls(), 3);
+    assert!(scenario.rofi_input(3).contains("Reboot"));
+}
+
+// A background process that is killed with the test, passed or not
+#[cfg(feature = "dbus")]
+struct Background(std::process::Child);
+
+#[cfg(feature = "dbus")]
+impl Drop for Background {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+// `daemon --dbus` on a private session bus, called through dbus-send; skipped
+// where dbus-daemon isn't installed
+#[cfg(feature = "dbus")]
+#[test]
+fn dbus_calls_reach_the_daemon() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    let scenario = config_with("dbus", &[("f", "Files", false)]);
+    let bus = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address=1"])
+        .arg(format!("--address=unix:path={}", scenario.dir.join("bus").display()))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    let Ok(mut bus) = bus else {
+        eprintln!("dbus-daemon is not installed; skipping");
+        return;
+    };
+    let mut address = String::new();
+    BufReader::new(bus.stdout.take().unwrap()).read_line(&mut address).unwrap();
+    let address = address.trim().to_string();
+    let _bus = Background(bus);
+
+    let call = |method: &str, args: &[&str]| {
+        Command::new("dbus-send")
+            .args(["--session", "--print-reply", "--dest=org.rofikeys.Menu", "/org/rofikeys/Menu"])
+            .arg(method)
+            .args(args)
+            .env("DBUS_SESSION_BUS_ADDRESS", &address)
+            .output()
+            .unwrap()
+    };
+    // The menu is dismissed, so Show is cancelled
+    let daemon = scenario
+        .command("1", &["daemon", "--dbus"])
+        .env("DBUS_SESSION_BUS_ADDRESS", &address)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let _daemon = Background(daemon);
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !call("org.freedesktop.DBus.Peer.Ping", &[]).status.success() {
+        assert!(Instant::now() < deadline, "the daemon never took org.rofikeys.Menu");
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    let output = call("org.rofikeys.Menu.Run", &["string:Files"]);
+    assert!(output.status.success(), "{:?}", output);
+    assert!(scenario.wait_for_marker("f"), "Run didn't run the entry");
+    let output = call("org.rofikeys.Menu.Show", &["string:"]);
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(error.contains("org.rofikeys.Menu.Error.Cancelled"), "{:?}", output);
+    assert!(call("org.rofikeys.Menu.Reload", &[]).status.success());
+
+    let second = scenario
+        .command("", &["daemon", "--dbus"])
+        .env("DBUS_SESSION_BUS_ADDRESS", &address)
+        .output()
+        .unwrap();
+    assert_eq!(second.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&second.stderr).contains("already taken"), "{:?}", second);
+}